@@ -0,0 +1,68 @@
+//! A bursty three stage pipeline, with and without wake batching. The producer sends a burst of messages and
+//! yields, the relay passes every message on, and the consumer counts them. Run it with
+//! `cargo bench --bench batching`.
+//!
+//! Without batching every wake the relay and the consumer get goes through the injector, with batching it only
+//! goes on the runtime's batch and they're woken when the sender yields.
+use green_threads::channel::{channel, Receiver, Sender};
+use green_threads::{yield_task, Runtime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const BURSTS: usize = 50_000;
+const BURST: usize = 16;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+fn producer(tx: Sender<usize>) {
+    for burst in 0..BURSTS {
+        for i in 0..BURST {
+            tx.send(burst * BURST + i).unwrap();
+        }
+        yield_task();
+    }
+}
+
+fn relay((rx, tx): (Receiver<usize>, Sender<usize>)) {
+    while let Ok(message) = rx.recv() {
+        tx.send(message).unwrap();
+    }
+}
+
+fn consumer(rx: Receiver<usize>) {
+    while rx.recv().is_ok() {
+        RECEIVED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn measure(name: &str, batching: bool) {
+    RECEIVED.store(0, Ordering::Relaxed);
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.set_wake_batching(batching);
+    let (tx, rx) = channel();
+    let (relayed_tx, relayed_rx) = channel();
+    runtime.spawn_with(producer, tx).detach();
+    runtime.spawn_with(relay, (rx, relayed_tx)).detach();
+    runtime.spawn_with(consumer, relayed_rx).detach();
+
+    let start = Instant::now();
+    runtime.run();
+    let elapsed = start.elapsed();
+    let messages = RECEIVED.load(Ordering::Relaxed);
+    assert_eq!(messages, BURSTS * BURST);
+
+    println!(
+        "batching/{}: {} messages in {:.3}s ({:.1} ns/message, {} switches)",
+        name,
+        messages,
+        elapsed.as_secs_f64(),
+        elapsed.as_nanos() as f64 / messages as f64,
+        runtime.stats().context_switches
+    );
+}
+
+fn main() {
+    measure("off", false);
+    measure("on", true);
+}
@@ -0,0 +1,62 @@
+//! A TCP echo benchmark over loopback. Run it with `cargo bench` for the epoll backend,
+//! `cargo bench --features io-uring` for the io_uring backend and `cargo bench --features mio` for mio,
+//! and compare the numbers.
+use green_threads::net::{TcpListener, TcpStream};
+use green_threads::Runtime;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+const ADDR: &str = "127.0.0.1:34600";
+const MESSAGES: usize = 100_000;
+const MESSAGE_SIZE: usize = 64;
+
+fn server() {
+    let listener = TcpListener::bind(ADDR).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut buf = [0u8; MESSAGE_SIZE];
+    for _ in 0..MESSAGES {
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    }
+}
+
+fn client() {
+    let mut stream = TcpStream::connect(ADDR).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let msg = [7u8; MESSAGE_SIZE];
+    let mut buf = [0u8; MESSAGE_SIZE];
+
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        stream.write_all(&msg).unwrap();
+        stream.read_exact(&mut buf).unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let backend = if cfg!(feature = "io-uring") {
+        "io_uring"
+    } else if cfg!(feature = "mio") {
+        "mio"
+    } else {
+        "epoll"
+    };
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "echo/{}: {} round trips of {} bytes in {:.3}s ({:.0} round trips/s, {:.2} MiB/s)",
+        backend,
+        MESSAGES,
+        MESSAGE_SIZE,
+        secs,
+        MESSAGES as f64 / secs,
+        (MESSAGES * MESSAGE_SIZE * 2) as f64 / secs / (1024.0 * 1024.0)
+    );
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(server);
+    runtime.spawn(client);
+    runtime.run();
+}
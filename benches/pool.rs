@@ -0,0 +1,65 @@
+//! Compares running many small jobs on a `WorkerPool` with spawning a task for every job. Run it with
+//! `cargo bench --bench pool`.
+//!
+//! There are only a few task slots, so the spawning side spawns as many tasks as it can, joins them all and
+//! goes again. The pool side queues every job at once and lets its workers work through them.
+use green_threads::{coro, yield_task, Runtime, WorkerPool};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const JOBS: usize = 200_000;
+// the base task and the main task of `run_until` take up two of the four slots
+const WORKERS: usize = 2;
+
+static DONE: AtomicUsize = AtomicUsize::new(0);
+
+fn job() {
+    DONE.fetch_add(1, Ordering::Relaxed);
+}
+
+fn per_task_spawn() -> i32 {
+    let mut left = JOBS;
+    while left > 0 {
+        let batch: Vec<_> = (0..WORKERS.min(left)).map(|_| coro::spawn(job)).collect();
+        left -= batch.len();
+        for handle in batch {
+            handle.join();
+        }
+    }
+    0
+}
+
+fn pool() -> i32 {
+    let pool = WorkerPool::new(WORKERS);
+    for _ in 0..JOBS {
+        pool.execute(job);
+    }
+    // gives the workers the queue, dropping the pool waits for the rest
+    yield_task();
+    drop(pool);
+    0
+}
+
+fn measure(name: &str, main: fn() -> i32) {
+    DONE.store(0, Ordering::SeqCst);
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    let start = Instant::now();
+    runtime.run_until(main);
+    let elapsed = start.elapsed();
+    assert_eq!(DONE.load(Ordering::SeqCst), JOBS);
+
+    println!(
+        "pool/{}: {} jobs in {:.3}s ({:.1} ns/job)",
+        name,
+        JOBS,
+        elapsed.as_secs_f64(),
+        elapsed.as_nanos() as f64 / JOBS as f64
+    );
+}
+
+fn main() {
+    measure("spawn", per_task_spawn);
+    measure("worker-pool", pool);
+}
@@ -0,0 +1,56 @@
+//! Measures how long a context switch takes, with and without saving the FP registers. Two tasks yield to
+//! each other over and over, first spawned with `spawn` and then with `spawn_without_fp`. Run it with
+//! `cargo bench --bench switch`.
+//!
+//! The main task and the base task take their turns too and always save their FP registers, so the difference
+//! per yield is a bit smaller than the difference per switch.
+use green_threads::{yield_task, Runtime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const YIELDS: usize = 1_000_000;
+
+static DONE: AtomicUsize = AtomicUsize::new(0);
+
+fn ping_pong() {
+    for _ in 0..YIELDS {
+        yield_task();
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+fn wait_for_both() -> i32 {
+    while DONE.load(Ordering::SeqCst) < 2 {
+        yield_task();
+    }
+    0
+}
+
+fn measure(name: &str, spawn: fn(&mut Runtime, fn())) {
+    DONE.store(0, Ordering::SeqCst);
+    let mut runtime = Runtime::new();
+    runtime.init();
+    spawn(&mut runtime, ping_pong);
+    spawn(&mut runtime, ping_pong);
+
+    let start = Instant::now();
+    runtime.run_until(wait_for_both);
+    let elapsed = start.elapsed();
+
+    println!(
+        "switch/{}: {} yields in {:.3}s ({:.1} ns/yield)",
+        name,
+        2 * YIELDS,
+        elapsed.as_secs_f64(),
+        elapsed.as_nanos() as f64 / (2 * YIELDS) as f64
+    );
+}
+
+fn main() {
+    measure("with-fp", |rt, f| {
+        rt.spawn(f);
+    });
+    measure("without-fp", |rt, f| {
+        rt.spawn_without_fp(f);
+    });
+}
@@ -0,0 +1,38 @@
+/*
+ * The example from the main crate in C. From the capi directory:
+ *
+ *   cargo build --release
+ *   cc examples/hello.c -Iinclude -Ltarget/release -lcoro -o hello
+ *   LD_LIBRARY_PATH=target/release ./hello
+ */
+#include <stdio.h>
+#include "coro.h"
+
+struct counter {
+    const char *name;
+    int steps;
+};
+
+static void count(void *arg)
+{
+    struct counter *counter = arg;
+    printf("TASK %d (%s) STARTING\n", coro_task_id(), counter->name);
+    for (int i = 0; i < counter->steps; i++) {
+        printf("task: %d counter: %d\n", coro_task_id(), i);
+        coro_yield();
+    }
+    printf("TASK %d (%s) FINISHED\n", coro_task_id(), counter->name);
+}
+
+int main(void)
+{
+    struct counter first = { "first", 10 };
+    struct counter second = { "second", 15 };
+
+    coro_runtime *runtime = coro_runtime_new();
+    coro_spawn(count, &first);
+    coro_spawn(count, &second);
+    coro_run(runtime);
+    coro_runtime_free(runtime);
+    return 0;
+}
@@ -0,0 +1,33 @@
+/* The C API of our green threads, see capi/src/lib.rs. Link with -lcoro. */
+#ifndef CORO_H
+#define CORO_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct coro_runtime coro_runtime;
+
+/* Creates a runtime and makes it the current runtime of the calling thread. */
+coro_runtime *coro_runtime_new(void);
+
+/* Frees a runtime created by coro_runtime_new. Don't call it from a task. */
+void coro_runtime_free(coro_runtime *runtime);
+
+/* Spawns a task on the current runtime that calls entry(arg). Returns its id, or -1 if there's no room. */
+int coro_spawn(void (*entry)(void *arg), void *arg);
+
+/* Lets the other tasks run. Only call it from a task. */
+void coro_yield(void);
+
+/* The id of the running task, 0 outside of a task. */
+int coro_task_id(void);
+
+/* Runs the tasks until there's nothing left to do. Never call it from a task. */
+void coro_run(coro_runtime *runtime);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
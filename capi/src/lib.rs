@@ -0,0 +1,80 @@
+//! A C API for our runtime, so a C program can spawn and schedule the same tasks a Rust program does. The
+//! declarations are in `include/coro.h` and `examples/hello.c` shows how to use them.
+//!
+//! It's a crate of its own because it has to be built as a `cdylib`, which the main crate can't be: with the
+//! `static-alloc` feature it's `no_std` and has no panic handler.
+//!
+//! A C entry function takes a `void *` and the runtime only passes a `usize` (see `Runtime::spawn_arg`). So
+//! `coro_spawn` boxes the function with its argument and spawns `trampoline` with a pointer to the box.
+use green_threads::{task_id, yield_task, Runtime};
+use std::os::raw::{c_int, c_void};
+
+type Entry = extern "C" fn(*mut c_void);
+
+fn trampoline(boxed: usize) {
+    let (entry, arg) = *unsafe { Box::from_raw(boxed as *mut (Entry, *mut c_void)) };
+    entry(arg);
+}
+
+/// Creates a runtime and makes it the current runtime of the calling thread, the one `coro_spawn` spawns on.
+/// Free it with `coro_runtime_free`.
+#[no_mangle]
+pub extern "C" fn coro_runtime_new() -> *mut Runtime {
+    let runtime = Box::into_raw(Box::new(Runtime::new()));
+    unsafe { (*runtime).init() };
+    runtime
+}
+
+/// Frees a runtime `coro_runtime_new` returned. Tasks that haven't finished are dropped with it.
+///
+/// # Safety
+///
+/// `runtime` came from `coro_runtime_new` and isn't used afterwards. Don't call it from a task.
+#[no_mangle]
+pub unsafe extern "C" fn coro_runtime_free(runtime: *mut Runtime) {
+    if !runtime.is_null() {
+        drop(Box::from_raw(runtime));
+    }
+}
+
+/// Spawns a task on the current runtime that calls `entry(arg)`. Returns its task id, or -1 if the runtime
+/// has no free task and its overload policy rejected the spawn. Nobody can join the task, it's reaped as soon
+/// as `entry` returns.
+///
+/// # Safety
+///
+/// The calling thread has a runtime from `coro_runtime_new`, and `arg` is still valid when `entry` runs.
+#[no_mangle]
+pub unsafe extern "C" fn coro_spawn(entry: Entry, arg: *mut c_void) -> c_int {
+    let boxed = Box::into_raw(Box::new((entry, arg)));
+    let handle = green_threads::coro::spawn_arg(trampoline, boxed as usize);
+    if handle.id() == usize::MAX {
+        drop(Box::from_raw(boxed));
+        return -1;
+    }
+    handle.id() as c_int
+}
+
+/// Lets the other tasks run. Only call it from a task.
+#[no_mangle]
+pub extern "C" fn coro_yield() {
+    yield_task();
+}
+
+/// The id of the task that's running, 0 outside of a task.
+#[no_mangle]
+pub extern "C" fn coro_task_id() -> c_int {
+    task_id() as c_int
+}
+
+/// Runs the tasks until there's nothing left to do, see `Runtime::run`. Call it from the thread that created
+/// `runtime`, never from a task.
+///
+/// # Safety
+///
+/// `runtime` came from `coro_runtime_new` and wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn coro_run(runtime: *mut Runtime) {
+    (*runtime).init();
+    (*runtime).run();
+}
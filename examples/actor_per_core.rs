@@ -0,0 +1,78 @@
+//! One runtime per OS thread ("core"), each running an actor that owns an inbox. The actors pass a token
+//! around the ring of cores. Whoever sends to an inbox wakes the actor parked on it through the injector of
+//! the runtime it runs on, and when the token has gone around enough times the actor holding it tells
+//! everyone to stop.
+use green_threads::channel::{channel, Receiver, Sender};
+use green_threads::Runtime;
+use std::cell::{Cell, RefCell};
+use std::thread;
+
+const CORES: usize = 4;
+const HOPS: u64 = 10_000;
+
+enum Message {
+    Token(u64),
+    Stop,
+}
+
+// tasks only take a `fn()`, so every core finds its inbox and its peers here
+thread_local! {
+    static CORE: Cell<usize> = const { Cell::new(0) };
+    static INBOX: RefCell<Option<Receiver<Message>>> = const { RefCell::new(None) };
+    static PEERS: RefCell<Vec<Sender<Message>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn actor() -> i32 {
+    let core = CORE.with(|c| c.get());
+    let inbox = INBOX.with(|i| i.borrow_mut().take().unwrap());
+    let peers = PEERS.with(|p| p.borrow().clone());
+    let mut received = 0;
+
+    loop {
+        match inbox.recv().unwrap() {
+            Message::Token(hops) if hops == HOPS => {
+                println!("core {}: the token made {} hops, stopping", core, hops);
+                for (other, peer) in peers.iter().enumerate() {
+                    if other != core {
+                        let _ = peer.send(Message::Stop);
+                    }
+                }
+                break;
+            }
+            Message::Token(hops) => {
+                received += 1;
+                peers[(core + 1) % CORES].send(Message::Token(hops + 1)).unwrap();
+            }
+            Message::Stop => break,
+        }
+    }
+    println!("core {}: passed the token on {} times", core, received);
+    0
+}
+
+fn main() {
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..CORES).map(|_| channel()).unzip();
+    senders[0].send(Message::Token(0)).unwrap();
+
+    let cores: Vec<_> = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(core, inbox)| {
+            let peers = senders.clone();
+            thread::spawn(move || {
+                CORE.with(|c| c.set(core));
+                INBOX.with(|i| *i.borrow_mut() = Some(inbox));
+                PEERS.with(|p| *p.borrow_mut() = peers);
+
+                let mut runtime = Runtime::new();
+                runtime.init();
+                runtime.run_until(actor)
+            })
+        })
+        .collect();
+    drop(senders);
+
+    for core in cores {
+        core.join().unwrap();
+    }
+}
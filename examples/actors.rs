@@ -0,0 +1,61 @@
+//! A bank account actor that's supervised. A withdrawal that would overdraw the account makes it panic, the
+//! supervisor replaces it with a fresh one, and the main task keeps talking to it as if nothing happened.
+//! Balances are reported back over a channel.
+use green_threads::actor::{self, Actor};
+use green_threads::channel::{channel, Sender};
+use green_threads::Runtime;
+
+enum Message {
+    Deposit(u64),
+    Withdraw(u64),
+    Balance(Sender<u64>),
+}
+
+struct Account {
+    balance: u64,
+}
+
+impl Actor for Account {
+    type Message = Message;
+
+    fn handle(&mut self, message: Message) {
+        match message {
+            Message::Deposit(amount) => self.balance += amount,
+            Message::Withdraw(amount) => {
+                assert!(
+                    amount <= self.balance,
+                    "can't withdraw {} from {}",
+                    amount,
+                    self.balance
+                );
+                self.balance -= amount;
+            }
+            Message::Balance(reply) => {
+                let _ = reply.send(self.balance);
+            }
+        }
+    }
+}
+
+fn main_task() -> i32 {
+    let account = actor::spawn_supervised(|| Account { balance: 0 }, 3);
+    let (reply, balances) = channel();
+
+    account.send(Message::Deposit(100)).unwrap();
+    account.send(Message::Withdraw(30)).unwrap();
+    account.send(Message::Balance(reply.clone())).unwrap();
+    println!("balance: {}", balances.recv().unwrap());
+
+    // this one panics, and the account we get after the restart starts from zero again
+    account.send(Message::Withdraw(500)).unwrap();
+    account.send(Message::Deposit(10)).unwrap();
+    account.send(Message::Balance(reply)).unwrap();
+    println!("balance after the restart: {}", balances.recv().unwrap());
+    0
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    std::process::exit(runtime.run_until(main_task));
+}
@@ -0,0 +1,67 @@
+//! Running submissions we don't trust with CPU time budgets.
+//!
+//! First the default: `endless` never finishes, so once it has used up its 20ms it's cancelled, while `polite`
+//! finishes well within its budget. Then the handler demotes tasks instead: `hog` keeps running after it used
+//! up its budget, but only when `interactive` doesn't want the CPU, so `interactive` isn't slowed down by it.
+use green_threads::{coro, maybe_yield, BudgetExceeded, OverBudget, Runtime};
+use std::time::{Duration, Instant};
+
+fn work(total: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < total {
+        maybe_yield();
+    }
+}
+
+fn polite() {
+    work(Duration::from_millis(5));
+    println!("  polite: done at {:?}", coro::now());
+}
+
+fn endless() {
+    loop {
+        maybe_yield();
+    }
+}
+
+fn hog() {
+    work(Duration::from_millis(30));
+    println!("  hog: done at {:?}", coro::now());
+}
+
+fn interactive() {
+    for _ in 0..5 {
+        work(Duration::from_millis(1));
+        coro::sleep(Duration::from_millis(2));
+    }
+    println!("  interactive: done at {:?}", coro::now());
+}
+
+fn cancel(exceeded: &BudgetExceeded) -> OverBudget {
+    println!("  {}, cancelling it", exceeded);
+    OverBudget::Cancel
+}
+
+fn demote(exceeded: &BudgetExceeded) -> OverBudget {
+    println!("  {}, demoting it", exceeded);
+    OverBudget::Demote
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    println!("cancelling:");
+    runtime.on_budget_exceeded(cancel);
+    runtime.spawn_with_budget(polite, Duration::from_millis(50));
+    runtime.spawn_with_budget(endless, Duration::from_millis(20));
+    runtime.run();
+
+    println!("demoting:");
+    runtime.on_budget_exceeded(demote);
+    runtime.spawn_with_budget(hog, Duration::from_millis(5));
+    runtime.spawn(interactive);
+    runtime.run();
+
+    println!("{} tasks went over budget", runtime.stats().budgets_exceeded);
+}
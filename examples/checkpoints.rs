@@ -0,0 +1,67 @@
+//! A task looking for the longest Collatz sequence while another one wants to tick every 2 ms. The search calls
+//! `maybe_yield` on every number it tries, which only yields once its 1 ms time slice is used up, so the ticker
+//! stays on time while the runtime only switches tasks a few hundred times instead of once per number.
+use green_threads::{coro, maybe_yield, Runtime};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+const LIMIT: u64 = 1_000_000;
+
+static DONE: AtomicBool = AtomicBool::new(false);
+
+fn collatz_len(mut n: u64) -> u64 {
+    let mut len = 1;
+    while n != 1 {
+        n = if n & 1 == 0 { n / 2 } else { 3 * n + 1 };
+        len += 1;
+    }
+    len
+}
+
+fn search() {
+    let mut longest = (1, 1);
+    for n in 1..LIMIT {
+        let len = collatz_len(n);
+        if len > longest.1 {
+            longest = (n, len);
+        }
+        maybe_yield();
+    }
+    println!(
+        "the longest Collatz sequence below {} starts at {} ({} numbers)",
+        LIMIT, longest.0, longest.1
+    );
+    DONE.store(true, Ordering::Relaxed);
+}
+
+fn tick() {
+    let mut ticks = 0;
+    let mut worst = Duration::from_secs(0);
+    while !DONE.load(Ordering::Relaxed) {
+        let start = Instant::now();
+        coro::sleep(Duration::from_millis(2));
+        worst = worst.max(start.elapsed());
+        ticks += 1;
+    }
+    println!("ticked {} times, the longest 2 ms sleep took {:?}", ticks, worst);
+}
+
+fn main_task() -> i32 {
+    let counter = coro::spawn(search);
+    let ticker = coro::spawn(tick);
+    counter.join();
+    ticker.join();
+    0
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let code = runtime.run_until(main_task);
+    println!(
+        "{} context switches for {} numbers",
+        runtime.stats().context_switches,
+        LIMIT
+    );
+    std::process::exit(code);
+}
@@ -0,0 +1,26 @@
+//! Runs the same code on OS threads and on green threads. The only difference between the two
+//! modules below is what they `use`.
+use green_threads::Runtime;
+
+mod threads {
+    use std::thread::{current, sleep, spawn, yield_now};
+    use std::time::Duration;
+    include!("program.rs");
+}
+
+mod green {
+    use green_threads::coro::{current, sleep, spawn, yield_now};
+    use std::time::Duration;
+    include!("program.rs");
+}
+
+fn main() {
+    println!("--- std::thread ---");
+    threads::program();
+
+    println!("--- green threads ---");
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(green::program);
+    runtime.run();
+}
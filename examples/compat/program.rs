@@ -0,0 +1,18 @@
+// This file is included twice by `main.rs`: once with the `std::thread` functions in scope and once
+// with the ones from `green_threads::coro`. Nothing in here knows which one it gets.
+
+pub fn program() {
+    let handles: Vec<_> = (0..2).map(|_| spawn(worker)).collect();
+    for handle in handles {
+        let _ = handle.join();
+    }
+    println!("{:?} joined both workers", current().id());
+}
+
+fn worker() {
+    for i in 0..3 {
+        println!("{:?} step {}", current().id(), i);
+        sleep(Duration::from_millis(10));
+        yield_now();
+    }
+}
@@ -0,0 +1,73 @@
+//! Two jobs with deadlines, first with the default round-robin scheduler and then with earliest deadline first.
+//!
+//! The long job needs 40ms and has to be done within 100ms, the short one needs 20ms and has to be done within
+//! 30ms. Taking turns, the short job finishes at about 40ms and misses its deadline. EDF runs the short job
+//! first and both make it.
+use green_threads::coro;
+use green_threads::scheduler::{Edf, RoundRobin, Scheduler};
+use green_threads::sync::Event;
+use green_threads::{DeadlineMiss, Runtime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+static DONE: AtomicUsize = AtomicUsize::new(0);
+static FINISHED: Event = Event::new();
+
+fn work(total: Duration) {
+    // 1ms of work between yields
+    let mut left = total;
+    while !left.is_zero() {
+        let chunk = left.min(Duration::from_millis(1));
+        let start = Instant::now();
+        while start.elapsed() < chunk {}
+        left -= chunk;
+        coro::yield_now();
+    }
+    DONE.fetch_add(1, Ordering::Relaxed);
+    FINISHED.notify_one();
+}
+
+fn long_job() {
+    work(Duration::from_millis(40));
+    println!("  long job done at {:?}", coro::now());
+}
+
+fn short_job() {
+    work(Duration::from_millis(20));
+    println!("  short job done at {:?}", coro::now());
+}
+
+fn main_task() -> i32 {
+    while DONE.load(Ordering::Relaxed) < 2 {
+        FINISHED.wait();
+    }
+    0
+}
+
+fn report(miss: &DeadlineMiss) {
+    println!("  {}", miss);
+}
+
+fn compare(name: &str, scheduler: Box<dyn Scheduler>) {
+    DONE.store(0, Ordering::Relaxed);
+    println!("{}", name);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.set_scheduler(scheduler);
+    runtime.on_deadline_missed(report);
+    runtime
+        .spawn_with_deadline(long_job, Duration::from_millis(100))
+        .detach();
+    runtime
+        .spawn_with_deadline(short_job, Duration::from_millis(30))
+        .detach();
+    runtime.run_until(main_task);
+
+    println!("  {} deadlines missed", runtime.stats().deadlines_missed);
+}
+
+fn main() {
+    compare("round-robin", Box::new(RoundRobin));
+    compare("earliest deadline first", Box::new(Edf));
+}
@@ -0,0 +1,91 @@
+//! Runs the same two busy workers, one with priority 0 and one with priority 2, first with the default
+//! round-robin scheduler and then with the fair one, and prints how much CPU time every task got.
+//!
+//! With round-robin the priority 2 worker runs whenever it can, so the other one gets nothing until it's
+//! done. With the fair scheduler both make progress, the priority 2 worker just gets about 1.25² ≈ 1.56
+//! times as much CPU time. Task 0 is the base task: it polls for I/O between the others and competes for the
+//! CPU like any priority 0 task.
+//!
+//! It also prints how long every task waited to run (see the `latency` module). The fair scheduler makes the
+//! priority 0 worker wait less at the tail than round-robin does, that's what the p99 is for.
+use green_threads::coro;
+use green_threads::scheduler::{Fair, RoundRobin, Scheduler};
+use green_threads::sync::Event;
+use green_threads::Runtime;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// how long the workers keep going, in runtime time
+const WORK: Duration = Duration::from_millis(300);
+
+static ROUNDS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+static DONE: AtomicUsize = AtomicUsize::new(0);
+static FINISHED: Event = Event::new();
+
+fn busy(worker: usize) {
+    while coro::now() < WORK {
+        // about 50µs of work between yields
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_micros(50) {}
+        ROUNDS[worker].fetch_add(1, Ordering::Relaxed);
+        coro::yield_now();
+    }
+    DONE.fetch_add(1, Ordering::Relaxed);
+    FINISHED.notify_one();
+}
+
+fn low() {
+    busy(0);
+}
+
+fn high() {
+    busy(1);
+}
+
+fn main_task() -> i32 {
+    while DONE.load(Ordering::Relaxed) < 2 {
+        FINISHED.wait();
+    }
+    0
+}
+
+fn compare(name: &str, scheduler: Box<dyn Scheduler>) {
+    for rounds in &ROUNDS {
+        rounds.store(0, Ordering::Relaxed);
+    }
+    DONE.store(0, Ordering::Relaxed);
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.set_scheduler(scheduler);
+    runtime.enable_latency_histograms();
+    runtime.spawn_with_priority(low, 0).detach();
+    runtime.spawn_with_priority(high, 2).detach();
+    runtime.run_until(main_task);
+
+    let stats = runtime.stats();
+    println!("{} ({} context switches)", name, stats.context_switches);
+    for task in &stats.tasks {
+        println!(
+            "  task {} priority {}: ran {:>4}ms, scheduled {:>5} times, waited p50 {:?} p99 {:?}",
+            task.id,
+            task.priority,
+            task.run_time.as_millis(),
+            task.scheduled,
+            task.scheduling_latency.percentile(50.0),
+            task.scheduling_latency.percentile(99.0)
+        );
+    }
+    println!(
+        "  rounds of work: priority 0 worker {}, priority 2 worker {}",
+        ROUNDS[0].load(Ordering::Relaxed),
+        ROUNDS[1].load(Ordering::Relaxed)
+    );
+    println!("  waiting to run: {}", stats.scheduling_latency);
+    println!("  switching: {}", stats.switch_time);
+}
+
+fn main() {
+    compare("round-robin", Box::new(RoundRobin));
+    compare("fair", Box::new(Fair::new()));
+}
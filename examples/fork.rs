@@ -0,0 +1,52 @@
+//! `fork()` with tasks. One task counts to three, forks, and from then on the parent and the child each
+//! count on with their own copy of the counter, taking turns: the parent in tens, the child in hundreds. The
+//! counter is a local on the task's stack, so the child got its copy with the rest of the stack. The parent
+//! joins the child in the end, just like a process waits for the child it forked.
+use green_threads::fork::{self, Fork};
+use green_threads::{coro, Runtime};
+
+fn count() {
+    let mut counter = 0;
+    let counter_ref = &mut counter;
+    for _ in 0..3 {
+        *counter_ref += 1;
+        println!("task {}: {}", coro::current().id(), counter_ref);
+        coro::yield_now();
+    }
+
+    // The only things on our stack are integers and a reference to one of them, the child can have copies of those.
+    let me = match unsafe { fork::fork_current() } {
+        Fork::Parent(child) => {
+            println!("task {}: forked, my child is task {}", coro::current().id(), child.id());
+            Some(child)
+        }
+        Fork::Child => {
+            println!("task {}: I'm the child", coro::current().id());
+            None
+        }
+    };
+
+    // `counter_ref` points into our stack and was moved for the child, so both count with their own copy
+    let step = if me.is_some() { 10 } else { 100 };
+    for _ in 0..3 {
+        *counter_ref += step;
+        println!("task {}: {}", coro::current().id(), counter_ref);
+        coro::yield_now();
+    }
+
+    if let Some(child) = me {
+        child.join();
+        println!(
+            "task {}: my child is done, counted to {}",
+            coro::current().id(),
+            counter
+        );
+    }
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(count);
+    runtime.run();
+}
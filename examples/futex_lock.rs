@@ -0,0 +1,68 @@
+//! The classic futex exercise: a lock built on nothing but an atomic and `futex::wait_on`/`wake_one`, after
+//! Ulrich Drepper's "Futexes Are Tricky". The state is 0 when it's unlocked, 1 when it's locked and 2 when
+//! it's locked and someone might be waiting, so unlocking only has to wake anyone when it was 2.
+use green_threads::{coro, futex, Runtime};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+struct FutexLock {
+    state: AtomicU32,
+}
+
+impl FutexLock {
+    const fn new() -> Self {
+        FutexLock {
+            state: AtomicU32::new(0),
+        }
+    }
+
+    fn lock(&self) {
+        if self
+            .state
+            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+        // From here on we say there are waiters, even when we get the lock, since we can't know if we're the last
+        while self.state.swap(2, Ordering::Acquire) != 0 {
+            futex::wait_on(&self.state, 2);
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(0, Ordering::Release) == 2 {
+            futex::wake_one(&self.state);
+        }
+    }
+}
+
+static LOCK: FutexLock = FutexLock::new();
+// only touched while we hold the lock, and we yield in the middle of updating it to give the others a chance
+// to see it half done if the lock didn't work
+static BALANCE: AtomicU64 = AtomicU64::new(0);
+
+fn worker() {
+    for _ in 0..1000 {
+        LOCK.lock();
+        let balance = BALANCE.load(Ordering::Relaxed);
+        coro::yield_now();
+        BALANCE.store(balance + 1, Ordering::Relaxed);
+        LOCK.unlock();
+    }
+}
+
+fn main_task() -> i32 {
+    let workers = [coro::spawn(worker), coro::spawn(worker)];
+    worker();
+    for w in workers {
+        w.join();
+    }
+    println!("balance: {} (expected 3000)", BALANCE.load(Ordering::Relaxed));
+    0
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    std::process::exit(runtime.run_until(main_task));
+}
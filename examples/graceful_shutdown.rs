@@ -0,0 +1,48 @@
+//! A "server" with a background worker that shuts down cleanly on Ctrl-C (or SIGTERM).
+//!
+//! The main task does nothing but wait for a signal. When one arrives it tells the worker to stop,
+//! waits for it to finish what it's doing and returns, which ends `run_until`.
+use green_threads::coro;
+use green_threads::signal::{Signal, Signals};
+use green_threads::sync::Event;
+use green_threads::Runtime;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static STOPPED: Event = Event::new();
+
+fn worker() {
+    let mut jobs = 0;
+    while !STOP.load(Ordering::Relaxed) {
+        jobs += 1;
+        println!("worker: finished job {}", jobs);
+        coro::sleep(Duration::from_millis(500));
+    }
+    println!("worker: shutting down after {} jobs", jobs);
+    STOPPED.notify_one();
+}
+
+fn main_task() -> i32 {
+    let signals = Signals::new(&[Signal::Interrupt, Signal::Terminate]).unwrap();
+    coro::spawn(worker).detach();
+
+    println!("running, press Ctrl-C to stop");
+    let signal = signals.wait().unwrap();
+    println!("got {:?}, stopping the worker", signal);
+    STOP.store(true, Ordering::Relaxed);
+    STOPPED.wait();
+
+    // the usual exit code for a process stopped by a signal is 128 + the signal number
+    match signal {
+        Signal::Interrupt => 130,
+        _ => 143,
+    }
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let code = runtime.run_until(main_task);
+    std::process::exit(code);
+}
@@ -0,0 +1,59 @@
+//! A server that spawns a task per connection gets more connections than it has tasks for. With
+//! `Overload::Wait` the accept loop waits for a connection to finish before it takes the next one, so every
+//! connection gets served, just a bit later. With `Overload::Call` the connections that don't fit are turned
+//! away right away and the rest are served without delay.
+use green_threads::sync::Event;
+use green_threads::{coro, Overload, Runtime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const CONNECTIONS: usize = 8;
+
+static SERVED: AtomicUsize = AtomicUsize::new(0);
+static REJECTED: AtomicUsize = AtomicUsize::new(0);
+static DONE: Event = Event::new();
+
+fn connection() {
+    // pretend we're talking to a client for a while
+    coro::sleep(Duration::from_millis(20));
+    SERVED.fetch_add(1, Ordering::Relaxed);
+    DONE.notify_one();
+}
+
+fn turn_away(_connection: fn()) {
+    REJECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+fn accept_loop() -> i32 {
+    // Connections are detached, so their task is freed as soon as they're done. A task we still hold a
+    // `JoinHandle` to keeps its slot until it's joined, and then waiting for a free one would wait forever.
+    for _ in 0..CONNECTIONS {
+        coro::spawn(connection).detach();
+    }
+    while SERVED.load(Ordering::Relaxed) + REJECTED.load(Ordering::Relaxed) < CONNECTIONS {
+        DONE.wait();
+    }
+    0
+}
+
+fn serve(name: &str, overload: Overload) {
+    SERVED.store(0, Ordering::Relaxed);
+    REJECTED.store(0, Ordering::Relaxed);
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.on_overload(overload);
+    let start = std::time::Instant::now();
+    runtime.run_until(accept_loop);
+    println!(
+        "{}: served {}, turned away {}, took {:?}",
+        name,
+        SERVED.load(Ordering::Relaxed),
+        REJECTED.load(Ordering::Relaxed),
+        start.elapsed()
+    );
+}
+
+fn main() {
+    serve("wait", Overload::Wait);
+    serve("turn away", Overload::Call(turn_away));
+}
@@ -0,0 +1,31 @@
+//! Squares 20 numbers with `parallel_for`, 3 at a time, and prints which task handled which chunk. Every index
+//! "waits for I/O" by sleeping for a bit, so while one worker sleeps the others get to go. It takes about as
+//! long as the slowest worker instead of as long as all of them together.
+use green_threads::{coro, parallel, task_id, Runtime};
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+const N: usize = 20;
+
+fn main_task() -> i32 {
+    let squares = RefCell::new(vec![0; N]);
+    let start = Instant::now();
+    parallel::parallel_for(0..N, 3, |i| {
+        println!("task {} squares {}", task_id(), i);
+        coro::sleep(Duration::from_millis(10));
+        squares.borrow_mut()[i] = i * i;
+    });
+    println!("{:?}", squares.borrow());
+    println!(
+        "took {:?}, one at a time it would take {:?}",
+        start.elapsed(),
+        Duration::from_millis(10) * N as u32
+    );
+    0
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    std::process::exit(runtime.run_until(main_task));
+}
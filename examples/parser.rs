@@ -0,0 +1,34 @@
+//! A parser for `key=value;` pairs as a two-way generator. We feed it one character at a time with `send` and
+//! it yields a pair whenever one is complete. Whether it's reading a key or a value is simply where it is in its
+//! code, the state machine a hand-written parser would need is the generator's stack.
+use green_threads::generator::Generator;
+
+fn parser() -> Generator<Option<(String, String)>, char> {
+    Generator::two_way(|mut c, y| loop {
+        let mut key = String::new();
+        while c != '=' {
+            key.push(c);
+            c = y.yield_with(None);
+        }
+        c = y.yield_with(None);
+
+        let mut value = String::new();
+        while c != ';' {
+            value.push(c);
+            c = y.yield_with(None);
+        }
+        c = y.yield_with(Some((key, value)));
+    })
+}
+
+fn main() {
+    let mut parser = parser();
+    // the input could just as well arrive in pieces from a socket, the parser picks up where it stopped
+    for chunk in &["name=fer", "ris;lang", "=rust;ye", "ar=2015;"] {
+        for c in chunk.chars() {
+            if let Some(Some((key, value))) = parser.send(c) {
+                println!("{} is {}", key, value);
+            }
+        }
+    }
+}
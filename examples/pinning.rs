@@ -0,0 +1,42 @@
+//! Tasks on pinned workers. Every worker is pinned to a CPU of its own, and every task notes which CPUs it ran on
+//! while it yields its way through some work. With pinning each task sees exactly one CPU, the one its worker is
+//! pinned to. Without it (`Workers::new`), the OS is free to move the workers around.
+use green_threads::workers::{self, Workers};
+use green_threads::{coro, maybe_yield};
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn job() {
+    let mut cpus = BTreeSet::new();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(50) {
+        cpus.insert(workers::current_cpu());
+        maybe_yield();
+    }
+    println!(
+        "task {} on worker {}: ran on CPUs {:?}",
+        coro::current().id(),
+        workers::current().unwrap(),
+        cpus
+    );
+}
+
+fn main() {
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get()).min(4);
+    let cpus: Vec<usize> = (0..cpus).collect();
+
+    println!("pinned to CPUs {:?}:", cpus);
+    let pinned = Workers::pinned(&cpus).expect("failed to pin the workers");
+    for worker in 0..pinned.count() {
+        pinned.spawn_pinned(worker, job);
+        pinned.spawn_pinned(worker, job);
+    }
+    drop(pinned);
+
+    println!("not pinned:");
+    let free = Workers::new(cpus.len());
+    for _ in 0..free.count() * 2 {
+        free.spawn(job);
+    }
+}
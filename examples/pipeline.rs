@@ -0,0 +1,31 @@
+//! A producer → transformer → consumer pipeline built from generators. The producer could go on forever, but
+//! it only ever produces what the consumer asks for: every stage is resumed by the one after it.
+use green_threads::generator::Generator;
+
+fn numbers() -> Generator<u64> {
+    Generator::new(|y| {
+        let mut n = 0;
+        loop {
+            println!("  producer: {}", n);
+            y.yield_(n);
+            n += 1;
+        }
+    })
+}
+
+fn letters() -> Generator<char> {
+    Generator::new(|y| {
+        for c in "abc".chars() {
+            y.yield_(c);
+        }
+    })
+}
+
+fn main() {
+    let squares = numbers().map(|n| n * n).filter(|n| n % 2 == 0);
+    let labeled = letters().chain(Generator::new(|y| y.yield_('z'))).zip(squares);
+
+    for (label, square) in labeled {
+        println!("consumer: {} = {}", label, square);
+    }
+}
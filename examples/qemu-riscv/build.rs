@@ -0,0 +1,8 @@
+use std::env;
+
+// Tell the linker to use our linker script no matter which directory cargo runs it from
+fn main() {
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rustc-link-arg=-T{}/linker.ld", dir);
+    println!("cargo:rerun-if-changed=linker.ld");
+}
@@ -0,0 +1,85 @@
+//! Our green threads on bare metal: no operating system, no allocator, just QEMU's `virt` machine.
+//!
+//! Run it with `cargo run` from this directory (you need `qemu-system-riscv64` and the target installed with
+//! `rustup target add riscv64gc-unknown-none-elf`). The stacks are plain `static` arrays and the task table
+//! is part of `StaticRuntime`, so nothing here ever allocates.
+#![no_std]
+#![no_main]
+#![feature(global_asm)]
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use green_threads::bare::{yield_task, StaticRuntime};
+
+// The devices we need on QEMU's `virt` machine: a 16550 UART to print and the SiFive test device to power off
+const UART: *mut u8 = 0x1000_0000 as *mut u8;
+const TEST_DEVICE: *mut u32 = 0x10_0000 as *mut u32;
+const PASS: u32 = 0x5555;
+const FAIL: u32 = 0x3333;
+
+const STACK_SIZE: usize = 16 * 1024;
+static mut STACK_1: [u8; STACK_SIZE] = [0; STACK_SIZE];
+static mut STACK_2: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+// All we need before we can run Rust code is a stack
+global_asm!(
+    "
+    .section .text.entry
+    .globl _start
+_start:
+    la sp, boot_stack_top
+    call kmain
+"
+);
+
+struct Uart;
+
+impl Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            unsafe { UART.write_volatile(byte) };
+        }
+        Ok(())
+    }
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => {
+        let _ = writeln!(Uart, $($arg)*);
+    };
+}
+
+#[no_mangle]
+extern "C" fn kmain() -> ! {
+    let mut runtime = StaticRuntime::new(unsafe { [&mut STACK_1, &mut STACK_2] });
+    runtime.init();
+    runtime.spawn(|| {
+        println!("TASK 1 STARTING");
+        for i in 0..5 {
+            println!("task: 1 counter: {}", i);
+            yield_task();
+        }
+        println!("TASK 1 FINISHED");
+    });
+    runtime.spawn(|| {
+        println!("TASK 2 STARTING");
+        for i in 0..8 {
+            println!("task: 2 counter: {}", i);
+            yield_task();
+        }
+        println!("TASK 2 FINISHED");
+    });
+    runtime.run();
+    shutdown(PASS)
+}
+
+fn shutdown(code: u32) -> ! {
+    unsafe { TEST_DEVICE.write_volatile(code) };
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("{}", info);
+    shutdown(FAIL)
+}
@@ -0,0 +1,61 @@
+//! Rebalancing two runtimes. All the workers start on the "busy" runtime, and after every round `main` moves
+//! some of them to the "idle" one with `Runtime::steal_from` until both have about the same number of tasks.
+//!
+//! Both runtimes run on the main OS thread, one after the other, which is what `migrate` needs: it can only move
+//! tasks between runtimes it can get at. A worker parks after every round, that's where it can be moved, and it
+//! doesn't care which runtime wakes it up.
+use green_threads::{coro, Runtime, TaskState};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const NAMES: [&str; 2] = ["busy", "idle"];
+// the runtime `main` is running right now
+static ON: AtomicUsize = AtomicUsize::new(0);
+
+fn worker(n: usize) {
+    for round in 1..=3 {
+        let on = NAMES[ON.load(Ordering::SeqCst)];
+        println!(
+            "worker {}: round {} on the {} runtime as task {}",
+            n,
+            round,
+            on,
+            coro::current().id()
+        );
+        coro::park();
+    }
+}
+
+fn main() {
+    let mut busy = Runtime::new();
+    let mut idle = Runtime::new();
+    busy.init();
+    for n in 1..=3 {
+        busy.spawn_arg(worker, n).detach();
+    }
+
+    loop {
+        for (on, runtime) in [&mut busy, &mut idle].iter_mut().enumerate() {
+            ON.store(on, Ordering::SeqCst);
+            runtime.init();
+            runtime.run();
+        }
+        if busy.alive_count() + idle.alive_count() == 0 {
+            break;
+        }
+
+        while idle.alive_count() + 1 < busy.alive_count() {
+            match idle.steal_from(&mut busy) {
+                Some(id) => println!("main: moved a worker to the idle runtime, it's task {} there", id),
+                None => break,
+            }
+        }
+        // the ones we moved are ready to go already
+        for runtime in &[&busy, &idle] {
+            for task in runtime.stats().tasks {
+                if task.id != 0 && task.state == TaskState::Parked {
+                    runtime.handle().unpark(task.id);
+                }
+            }
+        }
+    }
+}
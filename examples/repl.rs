@@ -0,0 +1,56 @@
+//! Drives the runtime in bursts from a prompt. Every line you type is a list of words, we spawn a task for each
+//! of them (up to three, that's how many free task slots there are) that spells its word one letter per turn, and
+//! `run` interleaves them until they're done and returns to the prompt. An empty line runs the runtime with
+//! nothing spawned, which returns right away.
+//!
+//! Try `cargo run --example repl` and type `green threads`, or pipe some lines into it.
+use green_threads::{coro, Runtime};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+const MAX_WORDS: usize = 3;
+
+// We can only spawn a `fn()`, so every task takes the next word from here when it starts.
+thread_local! {
+    static WORDS: RefCell<VecDeque<String>> = const { RefCell::new(VecDeque::new()) };
+}
+
+fn spell() {
+    let word = WORDS.with(|words| words.borrow_mut().pop_front()).unwrap_or_default();
+    let id = coro::current().id();
+    for (i, c) in word.chars().enumerate() {
+        println!("  task {}: {}{}", id, " ".repeat(i), c);
+        coro::yield_now();
+    }
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+
+    let stdin = io::stdin();
+    let mut runs = 0;
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() > MAX_WORDS {
+            println!("only the first {} words, there aren't more free tasks", MAX_WORDS);
+        }
+        for word in words.into_iter().take(MAX_WORDS) {
+            WORDS.with(|words| words.borrow_mut().push_back(word.to_string()));
+            runtime.spawn(spell);
+        }
+
+        runtime.run();
+        runs += 1;
+        println!("done, {} task(s) still alive", runtime.alive_count());
+    }
+    println!("ran the runtime {} times", runs);
+}
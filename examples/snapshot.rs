@@ -0,0 +1,66 @@
+//! Checkpointing a long computation. The worker adds up the squares of 0 to 999 and parks every 100 numbers so
+//! we can take a snapshot of it. Halfway through it "loses power" and gives up, so we restore the last snapshot
+//! and the copy carries on from that checkpoint instead of starting over.
+//!
+//! We drive the runtime from `main` in bursts: `run` returns every time the worker parks, since nothing else is
+//! left to do then. The worker keeps its state in plain integers on its stack, which is what a snapshot saves.
+use green_threads::{coro, Runtime};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+const NONE: usize = usize::MAX;
+
+// the id of the worker while it's parked at a checkpoint
+static PARKED: AtomicUsize = AtomicUsize::new(NONE);
+static POWER_LOST: AtomicBool = AtomicBool::new(false);
+static RESULT: AtomicU64 = AtomicU64::new(0);
+
+fn sum_of_squares() {
+    let mut i: u64 = 0;
+    let mut sum: u64 = 0;
+    let mut checkpoint: u64 = 100;
+    while i < 1000 {
+        sum += i * i;
+        i += 1;
+        if i == checkpoint {
+            checkpoint += 100;
+            let id = coro::current().id();
+            println!("task {}: checkpoint at {}", id, i);
+            PARKED.store(id, Ordering::SeqCst);
+            coro::park();
+        }
+        if i == 650 && !POWER_LOST.swap(true, Ordering::SeqCst) {
+            println!("task {}: lost power at {}", coro::current().id(), i);
+            return;
+        }
+    }
+    RESULT.store(sum, Ordering::SeqCst);
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(sum_of_squares);
+
+    let mut last = None;
+    loop {
+        runtime.run();
+        let id = PARKED.swap(NONE, Ordering::SeqCst);
+        if id != NONE {
+            // it's parked in `coro::park` and only has integers on its stack
+            let snapshot = unsafe { runtime.snapshot(id) };
+            println!("main: took a snapshot of task {} ({} bytes)", id, snapshot.len());
+            last = Some(snapshot);
+            runtime.handle().unpark(id);
+        } else if RESULT.load(Ordering::SeqCst) == 0 {
+            let snapshot = last.as_ref().expect("the worker failed before its first checkpoint");
+            // we took it from this runtime, of a task that was fine to snapshot
+            let restored = unsafe { runtime.restore(snapshot) }.unwrap();
+            println!("main: restored the last snapshot as task {}", restored.id());
+        } else {
+            break;
+        }
+    }
+
+    let expected: u64 = (0..1000).map(|i: u64| i * i).sum();
+    println!("result: {} (expected {})", RESULT.load(Ordering::SeqCst), expected);
+}
@@ -0,0 +1,45 @@
+//! A "server" that puts every connection in a `TaskGroup`.
+//!
+//! Every connection's task spawns a heartbeat helper, which ends up in the connection's group since the task that
+//! spawned it is a member. When the client hangs up we cancel the group and the helper is cancelled along with the
+//! connection, so we don't leak a task per connection: with only three tasks to go around, the second connection
+//! would have nowhere to run otherwise.
+use green_threads::{coro, Runtime, TaskGroup};
+use std::time::Duration;
+
+fn heartbeat(connection: usize) {
+    loop {
+        println!("connection {}: heartbeat", connection);
+        coro::sleep(Duration::from_millis(30));
+    }
+}
+
+fn connection(id: usize) {
+    coro::spawn_arg(heartbeat, id).detach();
+    for request in 1.. {
+        println!("connection {}: handled request {}", id, request);
+        coro::sleep(Duration::from_millis(20));
+    }
+}
+
+fn main_task() -> i32 {
+    for id in 1..=3 {
+        println!("connection {}: accepted", id);
+        let group = TaskGroup::new();
+        group.spawn_arg(connection, id).detach();
+        coro::sleep(Duration::from_millis(100));
+
+        println!("connection {}: client hung up, {} tasks to cancel", id, group.alive_count());
+        // they're gone once they ran one last time, waiting lets them do that before we take the next connection
+        group.cancel();
+        group.wait();
+    }
+    0
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let code = runtime.run_until(main_task);
+    std::process::exit(code);
+}
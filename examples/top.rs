@@ -0,0 +1,52 @@
+//! `top` for tasks. The main task samples the runtime twice a second with a `monitor::Monitor` and redraws the
+//! table, while a task that computes and a task that mostly sleeps give it something to look at. The cruncher's
+//! stack grows and shrinks as it recurses deeper and deeper.
+use green_threads::monitor::Monitor;
+use green_threads::{coro, maybe_yield, Runtime};
+use std::time::Duration;
+
+const ROUNDS: usize = 10;
+
+fn sum_to(n: u64) -> u64 {
+    // a frame per level, so the deeper it goes the more stack it uses
+    let padding = [n; 16];
+    maybe_yield();
+    if n == 0 {
+        0
+    } else {
+        padding[0] + sum_to(n - 1)
+    }
+}
+
+fn cruncher() {
+    let mut depth = 0;
+    loop {
+        depth = (depth + 100) % 5_000;
+        sum_to(depth);
+    }
+}
+
+fn sleeper() {
+    loop {
+        coro::sleep(Duration::from_millis(10));
+    }
+}
+
+fn main_task() -> i32 {
+    let mut monitor = Monitor::new();
+    for _ in 0..ROUNDS {
+        coro::sleep(Duration::from_millis(500));
+        // clear the terminal and start at the top left
+        print!("\x1b[2J\x1b[H{}", monitor.sample());
+    }
+    0
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn_named(cruncher, "cruncher").detach();
+    runtime.spawn_named(sleeper, "sleeper").detach();
+    let code = runtime.run_until(main_task);
+    std::process::exit(code);
+}
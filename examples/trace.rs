@@ -0,0 +1,53 @@
+//! A producer and a consumer passing numbers through a channel, with tracing on. It writes `trace.json`,
+//! open it in `chrome://tracing` or https://ui.perfetto.dev to see which task ran when, and where they parked
+//! waiting for each other.
+use green_threads::channel::{self, Receiver, Sender};
+use green_threads::{coro, Runtime};
+use std::cell::RefCell;
+use std::fs::File;
+
+// where `main_task` leaves the two ends of the channel for the tasks it spawns
+thread_local! {
+    static TX: RefCell<Option<Sender<u32>>> = const { RefCell::new(None) };
+    static RX: RefCell<Option<Receiver<u32>>> = const { RefCell::new(None) };
+}
+
+fn producer() {
+    let tx = TX.with(|tx| tx.borrow_mut().take().unwrap());
+    for n in 0..10 {
+        tx.send(n).unwrap();
+        if n % 3 == 2 {
+            coro::yield_now();
+        }
+    }
+}
+
+fn consumer() {
+    let rx = RX.with(|rx| rx.borrow_mut().take().unwrap());
+    while let Ok(n) = rx.recv() {
+        println!("got {}", n);
+    }
+}
+
+fn main_task() -> i32 {
+    let (tx, rx) = channel::channel();
+    TX.with(|slot| *slot.borrow_mut() = Some(tx));
+    RX.with(|slot| *slot.borrow_mut() = Some(rx));
+    let producer = coro::spawn(producer);
+    let consumer = coro::spawn(consumer);
+    producer.join();
+    consumer.join();
+    0
+}
+
+fn main() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.enable_trace(1024);
+    let code = runtime.run_until(main_task);
+
+    let mut file = File::create("trace.json").expect("can't create trace.json");
+    runtime.dump_trace(&mut file).expect("can't write trace.json");
+    println!("wrote trace.json");
+    std::process::exit(code);
+}
@@ -0,0 +1,192 @@
+"""gdb commands for looking at green_threads tasks. Load them with
+
+    (gdb) source gdb/green_threads.py
+
+and then
+
+    (gdb) info coroutines      lists the tasks of every runtime in the program
+    (gdb) coroutine 3          shows entry 3 of that list: `bt`, `frame`, `info locals`... all work on its stack
+    (gdb) coroutine            goes back to the registers the program actually stopped with
+
+Switch back with a plain `coroutine` before you `continue` or `step`, or the program continues with the registers
+of a task that isn't running. rr won't let you change registers while replaying, so there only `info coroutines`
+works.
+
+The list is the one in `src/debugger.rs`: `GREEN_THREADS_TASKS` points to the first descriptor and every
+descriptor is ten little endian u64.
+"""
+import struct
+
+import gdb
+
+FIELDS = ("next", "id", "alive", "name", "name_len", "stack_low", "stack_high", "sp", "fp", "pc")
+
+# the registers we saved before `coroutine N` changed them, None while we show the real ones
+saved = None
+
+
+def read_u64(address):
+    return struct.unpack("<Q", bytes(gdb.selected_inferior().read_memory(address, 8)))[0]
+
+
+def register(name):
+    # from the innermost frame, no matter which frame the user selected
+    return int(gdb.newest_frame().read_register(name))
+
+
+def list_head():
+    # the symbol has no type gdb's Rust mode understands, so we take its address in C mode
+    language = gdb.parameter("language")
+    gdb.execute("set language c", to_string=True)
+    try:
+        address = int(gdb.parse_and_eval("(unsigned long)&GREEN_THREADS_TASKS"))
+    finally:
+        gdb.execute("set language " + language, to_string=True)
+    return read_u64(address)
+
+
+def runtimes():
+    """The task slots of every runtime, as lists of dicts with the fields of `TaskDescriptor`. A runtime
+    registers its slots one after the other starting with the base task, so every id 0 starts a new runtime."""
+    result = []
+    address = list_head()
+    while address != 0:
+        raw = bytes(gdb.selected_inferior().read_memory(address, 8 * len(FIELDS)))
+        d = dict(zip(FIELDS, struct.unpack("<%dQ" % len(FIELDS), raw)))
+        if d["id"] == 0:
+            result.append([])
+        result[-1].append(d)
+        address = d["next"]
+    return result
+
+
+def stack_pointers():
+    """The real stack pointer of every OS thread."""
+    selected = gdb.selected_thread()
+    sps = []
+    for thread in gdb.selected_inferior().threads():
+        if saved and thread.ptid == saved["thread"]:
+            sps.append(saved["sp"])
+            continue
+        thread.switch()
+        sps.append(register("sp"))
+    selected.switch()
+    return sps
+
+
+def descriptors():
+    """Every task that's alive, with `running` set for the ones running on some OS thread right now. That's
+    the task whose stack a thread's stack pointer is in, or the base task if it isn't in any of them since the
+    base task runs on the stack of the OS thread."""
+    sps = stack_pointers()
+    for tasks in runtimes():
+        alive = [d for d in tasks if d["alive"]]
+        for d in alive:
+            d["running"] = any(d["stack_low"] <= sp < d["stack_high"] for sp in sps)
+        if alive and alive[0]["id"] == 0:
+            alive[0]["running"] = not any(d["running"] for d in alive[1:])
+        for d in alive:
+            yield d
+
+
+def name_of(d):
+    if d["name_len"] == 0:
+        return "-"
+    raw = bytes(gdb.selected_inferior().read_memory(d["name"], d["name_len"]))
+    return raw.decode("utf-8", "replace")
+
+
+def frame_pointer_register():
+    arch = gdb.selected_frame().architecture().name()
+    if "riscv" in arch:
+        return "s0"
+    if "x86-64" in arch:
+        return "rbp"
+    return "fp"
+
+
+def saved_registers(d):
+    """The pc, sp and frame pointer `switch` will restore for the task."""
+    sp = read_u64(d["sp"])
+    fp = read_u64(d["fp"])
+    if d["pc"] == 0:
+        # x86_64: `switch` returns with `ret`, so the pc is on the stack and `ret` pops it
+        return read_u64(sp), sp + 8, fp
+    return read_u64(d["pc"]), sp, fp
+
+
+def restore():
+    global saved
+    if saved:
+        for thread in gdb.selected_inferior().threads():
+            if thread.ptid == saved["thread"]:
+                thread.switch()
+        set_registers(saved["pc"], saved["sp"], saved["fp"])
+        saved = None
+
+
+def set_registers(pc, sp, fp):
+    gdb.execute("set $pc = %d" % pc)
+    gdb.execute("set $sp = %d" % sp)
+    gdb.execute("set $%s = %d" % (frame_pointer_register(), fp))
+
+
+class InfoCoroutines(gdb.Command):
+    """List the tasks of every green_threads runtime: info coroutines"""
+
+    def __init__(self):
+        super(InfoCoroutines, self).__init__("info coroutines", gdb.COMMAND_STATUS)
+
+    def invoke(self, arg, from_tty):
+        print("%3s  %4s  %-20s  %-18s  %-18s  %s" % ("#", "id", "name", "stack", "pc", ""))
+        for n, d in enumerate(descriptors()):
+            if d["running"]:
+                pc, where = "-", "running"
+            else:
+                pc = saved_registers(d)[0]
+                block = gdb.block_for_pc(pc)
+                where = block.function.name if block and block.function else ""
+                pc = "0x%x" % pc
+            stack = "0x%x" % d["stack_high"] if d["stack_high"] else "-"
+            print("%3d  %4d  %-20s  %-18s  %-18s  %s" % (n, d["id"], name_of(d), stack, pc, where))
+
+
+class Coroutine(gdb.Command):
+    """Show the stack of a task from `info coroutines`: coroutine N. Without N go back to the real registers."""
+
+    def __init__(self):
+        super(Coroutine, self).__init__("coroutine", gdb.COMMAND_STACK)
+
+    def invoke(self, arg, from_tty):
+        global saved
+        if not arg.strip():
+            restore()
+            gdb.execute("frame")
+            return
+
+        n = int(arg)
+        tasks = list(descriptors())
+        if not 0 <= n < len(tasks):
+            raise gdb.GdbError("there's no coroutine %d, see `info coroutines`." % n)
+        d = tasks[n]
+        if d["running"]:
+            raise gdb.GdbError("coroutine %d is the one that's running, use `coroutine` to go back to it." % n)
+
+        # the registers we change are always the real ones of the current thread
+        if saved and saved["thread"] != gdb.selected_thread().ptid:
+            selected = gdb.selected_thread()
+            restore()
+            selected.switch()
+        if saved is None:
+            saved = {
+                "thread": gdb.selected_thread().ptid,
+                "pc": register("pc"),
+                "sp": register("sp"),
+                "fp": register(frame_pointer_register()),
+            }
+        set_registers(*saved_registers(d))
+        gdb.execute("frame")
+
+
+InfoCoroutines()
+Coroutine()
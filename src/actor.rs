@@ -0,0 +1,110 @@
+//! Actors on top of our tasks and channels. An actor is a task that owns some state and a mailbox, and the
+//! only way to talk to it is to send a message to its `Address`. It handles one message at a time, so its
+//! state never needs a lock.
+//!
+//! An actor spawned with `spawn_supervised` is restarted when it panics: we catch the panic in the actor's
+//! task, throw the broken state away, build a new actor and keep going with the next message. The one that
+//! caused the panic is lost. Nothing outside the actor notices, except for the panic message on stderr.
+//!
+//! If there's no task for an actor and `Overload::Call` turns the spawn down, the actor is dropped right away
+//! and sending to its address fails, like it does for an actor that stopped.
+use crate::channel::{self, Receiver, Sender};
+use crate::{Runtime, RUNTIME};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::SendError;
+
+pub trait Actor: 'static {
+    type Message: Send + 'static;
+
+    /// Handles one message. Gets called for every message sent to the actor's address, in order.
+    fn handle(&mut self, message: Self::Message);
+}
+
+/// Where to send messages for an actor. It can be cloned and sent to other OS threads, so actors on other
+/// runtimes can talk to it too. The actor stops once every address to it is gone.
+pub struct Address<M> {
+    sender: Sender<M>,
+}
+
+impl<M> Address<M> {
+    /// Puts a message in the actor's mailbox. Fails if the actor has stopped.
+    pub fn send(&self, message: M) -> Result<(), SendError<M>> {
+        self.sender.send(message)
+    }
+}
+
+impl<M> Clone for Address<M> {
+    fn clone(&self) -> Self {
+        Address {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+// We can only spawn a `fn()`, so the actor waits here until the task we spawned for it picks it up by its task
+// id, like the workers of `parallel_for` and `WorkerPool`. A queue would get out of step as soon as a spawn is
+// turned down or waits for a slot while somebody else spawns an actor.
+thread_local! {
+    static STARTING: RefCell<HashMap<usize, Box<dyn FnOnce()>>> = RefCell::new(HashMap::new());
+}
+
+fn start() {
+    let run = STARTING.with(|starting| starting.borrow_mut().remove(&crate::task_id()));
+    if let Some(run) = run {
+        run();
+    }
+}
+
+fn spawn_task(run: Box<dyn FnOnce()>) {
+    if cfg!(feature = "sim") {
+        panic!("the sim backend runs every task on an OS thread of its own, actors can't find their state there.");
+    }
+    let handle = unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).spawn(start)
+    };
+    // the task hasn't run yet, so it finds `run` when it does. A rejected spawn has no task and drops it.
+    if handle.id() != usize::MAX {
+        STARTING.with(|starting| starting.borrow_mut().insert(handle.id(), run));
+    }
+    handle.detach();
+}
+
+/// Spawns a task for `actor` on the current runtime. If it panics it's gone for good: its mailbox is closed and
+/// sending to it fails.
+pub fn spawn<A: Actor>(actor: A) -> Address<A::Message> {
+    let (sender, mailbox) = channel::channel();
+    let mut actor = Some(actor);
+    spawn_task(Box::new(move || {
+        supervise(mailbox, || actor.take().unwrap(), 0);
+    }));
+    Address { sender }
+}
+
+/// Spawns a task for the actor `make` builds. Every time the actor panics `make` builds a new one, up to
+/// `max_restarts` times. After that it stops like an actor spawned with `spawn`.
+pub fn spawn_supervised<A, F>(make: F, max_restarts: usize) -> Address<A::Message>
+where
+    A: Actor,
+    F: FnMut() -> A + 'static,
+{
+    let (sender, mailbox) = channel::channel();
+    spawn_task(Box::new(move || supervise(mailbox, make, max_restarts)));
+    Address { sender }
+}
+
+fn supervise<A: Actor>(mailbox: Receiver<A::Message>, mut make: impl FnMut() -> A, max_restarts: usize) {
+    let mut actor = make();
+    let mut restarts = 0;
+    while let Ok(message) = mailbox.recv() {
+        if panic::catch_unwind(AssertUnwindSafe(|| actor.handle(message))).is_err() {
+            if restarts == max_restarts {
+                return;
+            }
+            restarts += 1;
+            actor = make();
+        }
+    }
+}
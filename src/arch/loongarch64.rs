@@ -0,0 +1,195 @@
+task_context! {
+    #[derive(Debug, Default)]
+    #[repr(C)] // not strictly needed but Rust ABI is not guaranteed to be stable
+    struct TaskContext {
+        // 21 u64
+        ra: u64,  //r1: return address
+        sp: u64,  //r3
+        fp: u64,  //r22: fp (also called s9)
+        s0: u64,  //r23-r31: s0-s8
+        s1: u64,
+        s2: u64,
+        s3: u64,
+        s4: u64,
+        s5: u64,
+        s6: u64,
+        s7: u64,
+        s8: u64,
+        save_fp: u64, // see `set_save_fp`
+        fs0: u64,     //f24-f31: fs0-fs7
+        fs1: u64,
+        fs2: u64,
+        fs3: u64,
+        fs4: u64,
+        fs5: u64,
+        fs6: u64,
+        fs7: u64,
+    }
+}
+
+/// Same as on RISC-V, only LoongArch has 8 callee saved FP registers (`fs0-fs7`) instead of 12.
+pub(crate) fn set_save_fp(ctx: &mut TaskContext, save: bool) {
+    ctx.save_fp = save as u64;
+}
+
+pub(crate) fn stack_pointer(ctx: &TaskContext) -> usize {
+    ctx.sp as usize
+}
+
+/// `sp`, `fp` and `ra`, the same as on RISC-V.
+pub(crate) fn saved_registers(ctx: &TaskContext) -> [*const u64; 3] {
+    [&ctx.sp, &ctx.fp, &ctx.ra]
+}
+
+/// This works exactly like the RISC-V version. `switch` returns to our `task_entry` trampoline
+/// which calls `f` (stored in `s0`) and then `guard` (stored in `s1`).
+pub(crate) unsafe fn init_task(ctx: &mut TaskContext, stack: &mut [u8], f: fn(), guard: fn()) {
+    let size = stack.len();
+    let s_ptr = stack.as_mut_ptr().offset(size as isize);
+
+    // the LoongArch calling convention requires `sp` to be 16 byte aligned
+    let s_ptr = (s_ptr as usize & !15) as *mut u8;
+
+    ctx.ra = task_entry as u64;
+    ctx.sp = s_ptr.offset(-32) as u64;
+    ctx.s0 = f as u64;
+    ctx.s1 = guard as u64;
+}
+
+/// The task starts in `f(arg)`: `s0` holds `call_with_arg`, and `arg` and `f` wait in `s2` and `s3` for
+/// `task_entry_with_arg`.
+pub(crate) unsafe fn init_task_with_arg(
+    ctx: &mut TaskContext,
+    stack: &mut [u8],
+    f: fn(usize),
+    arg: usize,
+    guard: fn(),
+) {
+    let size = stack.len();
+    let s_ptr = stack.as_mut_ptr().offset(size as isize);
+    let s_ptr = (s_ptr as usize & !15) as *mut u8;
+
+    ctx.ra = task_entry_with_arg as u64;
+    ctx.sp = s_ptr.offset(-32) as u64;
+    ctx.s0 = super::call_with_arg as u64;
+    ctx.s1 = guard as u64;
+    ctx.s2 = arg as u64;
+    ctx.s3 = f as u64;
+}
+
+/// Every task starts here. `jirl` is LoongArch's "jump and link register", so we call `f` with `ra`
+/// pointing back to us and then jump to `guard` without linking since it never returns.
+///
+/// Unlike the other backends this one uses `naked_asm!` instead of `llvm_asm!`. There's no LoongArch target
+/// in a toolchain old enough to still have `llvm_asm!` (it was removed in Rust 1.59, LoongArch arrived in
+/// 1.71). It's also why our functions are `extern "C"`, a naked function needs an ABI we know.
+#[unsafe(naked)]
+unsafe extern "C" fn task_entry() {
+    core::arch::naked_asm!(
+        "
+        jirl $ra, $s0, 0
+        jirl $zero, $s1, 0
+        "
+    );
+}
+
+/// `task_entry` for `init_task_with_arg`, `move` copies `s2` and `s3` to the argument registers `a0` and `a1`.
+#[unsafe(naked)]
+unsafe extern "C" fn task_entry_with_arg() {
+    core::arch::naked_asm!(
+        "
+        move $a0, $s2
+        move $a1, $s3
+        jirl $ra, $s0, 0
+        jirl $zero, $s1, 0
+        "
+    );
+}
+
+/// The LoongArch version of our context switch. The callee saved registers are `ra`, `sp`,
+/// `fp` and `s0-s8`. `$r21` is reserved by the ABI and `tp` holds the thread pointer which is
+/// the same for all our tasks since they run on the same OS thread, so we leave both alone.
+///
+/// The arguments are passed in `a0` and `a1` just like on RISC-V, so the only real difference is
+/// the syntax: `st.d rd, rj, offset` stores `rd` at `rj + offset` and `ld.d` loads it back. The offsets are
+/// `const` operands, and since `{}` is what marks an operand in the template the `$` in front of a register
+/// name needs no escaping. `fst.d` and `fld.d` are the same for the FP registers, which we skip unless
+/// `save_fp` is set. `t0` is a temporary register, so we can use it for that check.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn switch(old: *mut TaskContext, new: *const TaskContext) {
+    // a0: old, a1: new
+    core::arch::naked_asm!(
+        "
+        ld.d $t0, $a0, {save_fp}
+        beqz $t0, 1f
+        fst.d $fs0, $a0, {fs0}
+        fst.d $fs1, $a0, {fs1}
+        fst.d $fs2, $a0, {fs2}
+        fst.d $fs3, $a0, {fs3}
+        fst.d $fs4, $a0, {fs4}
+        fst.d $fs5, $a0, {fs5}
+        fst.d $fs6, $a0, {fs6}
+        fst.d $fs7, $a0, {fs7}
+    1:
+        st.d $ra, $a0, {ra}
+        st.d $sp, $a0, {sp}
+        st.d $fp, $a0, {fp}
+        st.d $s0, $a0, {s0}
+        st.d $s1, $a0, {s1}
+        st.d $s2, $a0, {s2}
+        st.d $s3, $a0, {s3}
+        st.d $s4, $a0, {s4}
+        st.d $s5, $a0, {s5}
+        st.d $s6, $a0, {s6}
+        st.d $s7, $a0, {s7}
+        st.d $s8, $a0, {s8}
+
+        ld.d $t0, $a1, {save_fp}
+        beqz $t0, 2f
+        fld.d $fs0, $a1, {fs0}
+        fld.d $fs1, $a1, {fs1}
+        fld.d $fs2, $a1, {fs2}
+        fld.d $fs3, $a1, {fs3}
+        fld.d $fs4, $a1, {fs4}
+        fld.d $fs5, $a1, {fs5}
+        fld.d $fs6, $a1, {fs6}
+        fld.d $fs7, $a1, {fs7}
+    2:
+        ld.d $ra, $a1, {ra}
+        ld.d $sp, $a1, {sp}
+        ld.d $fp, $a1, {fp}
+        ld.d $s0, $a1, {s0}
+        ld.d $s1, $a1, {s1}
+        ld.d $s2, $a1, {s2}
+        ld.d $s3, $a1, {s3}
+        ld.d $s4, $a1, {s4}
+        ld.d $s5, $a1, {s5}
+        ld.d $s6, $a1, {s6}
+        ld.d $s7, $a1, {s7}
+        ld.d $s8, $a1, {s8}
+
+        jr $ra
+        ",
+        ra = const offsets::ra,
+        sp = const offsets::sp,
+        fp = const offsets::fp,
+        s0 = const offsets::s0,
+        s1 = const offsets::s1,
+        s2 = const offsets::s2,
+        s3 = const offsets::s3,
+        s4 = const offsets::s4,
+        s5 = const offsets::s5,
+        s6 = const offsets::s6,
+        s7 = const offsets::s7,
+        s8 = const offsets::s8,
+        save_fp = const offsets::save_fp,
+        fs0 = const offsets::fs0,
+        fs1 = const offsets::fs1,
+        fs2 = const offsets::fs2,
+        fs3 = const offsets::fs3,
+        fs4 = const offsets::fs4,
+        fs5 = const offsets::fs5,
+        fs6 = const offsets::fs6,
+        fs7 = const offsets::fs7,
+    );
+}
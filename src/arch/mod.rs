@@ -0,0 +1,107 @@
+//! Everything that depends on the CPU architecture (and on Windows, the OS) lives here. Each
+//! backend provides the same seven things:
+//!
+//! - `TaskContext`: the registers we need to save when we switch away from a task
+//! - `init_task`: sets up the stack and context of a new task so it starts in the function we pass in
+//! - `init_task_with_arg`: the same for a `fn(usize)`, its trampoline passes the argument in the first
+//!   argument register (see `call_with_arg`)
+//! - `switch`: saves the current registers in one context and loads the registers from another
+//! - `set_save_fp`: decides if `switch` saves and restores the FP registers of a context too
+//! - `stack_pointer`: the stack pointer saved in a context, so we can tell how much stack a task uses
+//! - `saved_registers`: where in a context the stack pointer, frame pointer and return address are, so a
+//!   debugger can show the stack of a task that isn't running (see the `debugger` module)
+//!
+//! The `sim` backend replaces all of them when the `sim` feature is on, see `src/arch/sim.rs`.
+//!
+//! `TaskContext` is always defined with the `task_context!` macro. It also generates a module
+//! called `offsets` with the offset of each field, and `switch` passes these to the assembly as
+//! immediates instead of hard coding them. That way you can add, remove or reorder fields without
+//! having to remember to update every load and store in the assembly.
+
+/// Defines `TaskContext` and an `offsets` module with one constant per field, named after the field.
+///
+/// We calculate the offsets by adding up the sizes of the fields before it. That is only correct if
+/// the struct is `#[repr(C)]` and the compiler didn't add any padding between the fields, so we check
+/// both at compile time. If someone adds a field that breaks this the build fails instead of `switch`
+/// quietly reading and writing the wrong registers.
+macro_rules! task_context {
+    (
+        $(#[$attr:meta])*
+        struct TaskContext {
+            $($field:ident: $ty:ty,)*
+        }
+    ) => {
+        $(#[$attr])*
+        pub(crate) struct TaskContext {
+            $($field: $ty,)*
+        }
+
+        #[allow(non_upper_case_globals, dead_code)]
+        mod offsets {
+            field_offsets!(0; $($field: $ty,)*);
+        }
+
+        #[allow(dead_code)]
+        impl TaskContext {
+            /// The context as the `u64`s it's made of (see the assertions below), for code that has to look at
+            /// every saved register without knowing which ones there are.
+            pub(crate) fn words(&self) -> &[u64] {
+                unsafe { core::slice::from_raw_parts(self as *const TaskContext as *const u64, offsets::END / 8) }
+            }
+
+            pub(crate) fn words_mut(&mut self) -> &mut [u64] {
+                unsafe { core::slice::from_raw_parts_mut(self as *mut TaskContext as *mut u64, offsets::END / 8) }
+            }
+        }
+
+        // Our static assertions. The array lengths only match (and only compile, since an underflow
+        // is a compile error in a constant) if every field is 8 byte aligned with a size that's a
+        // multiple of 8, so there can't be any padding between them, and if the fields take up the
+        // whole struct except for padding at the end to satisfy its alignment.
+        const _: [(); 0] = [(); $((core::mem::align_of::<$ty>() - 8) + core::mem::size_of::<$ty>() % 8 +)* 0];
+        const _: [(); 0] = [(); (core::mem::size_of::<TaskContext>() - offsets::END)
+            / core::mem::align_of::<TaskContext>()];
+    };
+}
+
+/// Where the trampoline of a task started with `init_task_with_arg` goes. The context keeps `arg` and `f` in
+/// callee saved registers, and the trampoline moves them to the first two argument registers and calls us. Rust
+/// doesn't promise which registers a `fn(usize)` takes its argument in, but `extern "C"` does, so we let the
+/// compiler make the actual call. `f` is a `fn(usize)`, a Rust function pointer just isn't allowed in a C
+/// signature.
+#[cfg(not(feature = "sim"))]
+unsafe extern "C" fn call_with_arg(arg: usize, f: usize) {
+    let f = core::mem::transmute::<usize, fn(usize)>(f);
+    f(arg)
+}
+
+macro_rules! field_offsets {
+    ($offset:expr;) => {
+        pub(super) const END: usize = $offset;
+    };
+    ($offset:expr; $field:ident: $ty:ty, $($rest:ident: $rest_ty:ty,)*) => {
+        pub(super) const $field: usize = $offset;
+        field_offsets!($field + core::mem::size_of::<$ty>(); $($rest: $rest_ty,)*);
+    };
+}
+
+#[cfg(all(target_arch = "riscv64", not(feature = "sim")))]
+mod riscv64;
+#[cfg(all(target_arch = "riscv64", not(feature = "sim")))]
+pub(crate) use riscv64::*;
+
+#[cfg(all(target_arch = "loongarch64", not(feature = "sim")))]
+mod loongarch64;
+#[cfg(all(target_arch = "loongarch64", not(feature = "sim")))]
+pub(crate) use loongarch64::*;
+
+#[cfg(all(target_arch = "x86_64", windows, not(feature = "sim")))]
+mod x86_64_windows;
+#[cfg(all(target_arch = "x86_64", windows, not(feature = "sim")))]
+pub(crate) use x86_64_windows::*;
+
+// threads instead of assembly, on any architecture
+#[cfg(feature = "sim")]
+mod sim;
+#[cfg(feature = "sim")]
+pub(crate) use sim::*;
@@ -0,0 +1,241 @@
+task_context! {
+    #[derive(Debug, Default)]
+    #[repr(C)] // not strictly needed but Rust ABI is not guaranteed to be stable
+    struct TaskContext {
+        // 27 u64
+        x1: u64,  //ra: return addres
+        x2: u64,  //sp
+        x8: u64,  //s0,fp
+        x9: u64,  //s1
+        x18: u64, //x18-27: s2-11
+        x19: u64,
+        x20: u64,
+        x21: u64,
+        x22: u64,
+        x23: u64,
+        x24: u64,
+        x25: u64,
+        x26: u64,
+        x27: u64,
+        save_fp: u64, // 0 means `switch` leaves the FP registers alone, see `set_save_fp`
+        f8: u64,  //f8-9: fs0-1
+        f9: u64,
+        f18: u64, //f18-27: fs2-11
+        f19: u64,
+        f20: u64,
+        f21: u64,
+        f22: u64,
+        f23: u64,
+        f24: u64,
+        f25: u64,
+        f26: u64,
+        f27: u64,
+    }
+}
+
+/// Decides if `switch` saves and restores the callee saved FP registers (`fs0-fs11`) of this context.
+/// Tasks that never touch floating point don't need it, and it saves 24 loads and stores per switch.
+/// It also matters on bare metal, where the FPU is often turned off and any FP instruction traps, which is
+/// why it's off unless someone turns it on.
+pub(crate) fn set_save_fp(ctx: &mut TaskContext, save: bool) {
+    ctx.save_fp = save as u64;
+}
+
+pub(crate) fn stack_pointer(ctx: &TaskContext) -> usize {
+    ctx.x2 as usize
+}
+
+/// `sp`, `s0` (the frame pointer) and `ra`, which is where `switch` returns to when the task runs again.
+pub(crate) fn saved_registers(ctx: &TaskContext) -> [*const u64; 3] {
+    [&ctx.x2, &ctx.x8, &ctx.x1]
+}
+
+/// Sets up the context of a new task so that the first time we switch to it we start executing `f`,
+/// and when `f` returns we end up in `guard`.
+///
+/// On RISC-V we don't need to write anything to the stack at all. We set `ra` to our `task_entry`
+/// trampoline so that's where `switch` "returns" to the first time we switch to this task. We put
+/// the address of `f` in `s1` and the address of `guard` in `s2`. These are callee saved registers
+/// so `switch` restores them for us and `f` must leave them as they were when it returns.
+pub(crate) unsafe fn init_task(ctx: &mut TaskContext, stack: &mut [u8], f: fn(), guard: fn()) {
+    let size = stack.len();
+    let s_ptr = stack.as_mut_ptr().offset(size as isize);
+
+    // make sure our stack itself is 8 byte aligned - it will always
+    // offset to a lower memory address. Since we know we're at the "high"
+    // memory address of our allocated space, we know that offsetting to
+    // a lower one will be a valid address (given that we actually allocated)
+    // enough space to actually get an aligned pointer in the first place).
+    let s_ptr = (s_ptr as usize & !7) as *mut u8;
+
+    ctx.x1 = task_entry as u64; //ctx.x1  is ra
+    ctx.x2 = s_ptr.offset(-32) as u64; //cxt.x2 is sp
+    ctx.x9 = f as u64; //ctx.x9  is s1
+    ctx.x18 = guard as u64; //ctx.x18 is s2
+}
+
+/// Same as `init_task`, but the task starts in `f(arg)`. `s1` holds `call_with_arg` instead of `f`, and `arg`
+/// and `f` wait in `s3` and `s4` for `task_entry_with_arg` to pass them on.
+pub(crate) unsafe fn init_task_with_arg(
+    ctx: &mut TaskContext,
+    stack: &mut [u8],
+    f: fn(usize),
+    arg: usize,
+    guard: fn(),
+) {
+    let size = stack.len();
+    let s_ptr = stack.as_mut_ptr().offset(size as isize);
+    let s_ptr = (s_ptr as usize & !7) as *mut u8;
+
+    ctx.x1 = task_entry_with_arg as u64; //ctx.x1  is ra
+    ctx.x2 = s_ptr.offset(-32) as u64; //cxt.x2 is sp
+    ctx.x9 = super::call_with_arg as u64; //ctx.x9  is s1
+    ctx.x18 = guard as u64; //ctx.x18 is s2
+    ctx.x19 = arg as u64; //ctx.x19 is s3
+    ctx.x20 = f as u64; //ctx.x20 is s4
+}
+
+/// Every task starts here. We call the function stored in `s1` (`jalr` sets `ra` so it returns back
+/// to us) and when it returns we jump to `guard` which we stored in `s2`. `guard` never returns since
+/// it marks the task as finished and switches to another task.
+///
+/// Doing it this way means `switch` doesn't need to know anything about new tasks. It just restores
+/// the registers and returns like it does for every other task.
+#[naked]
+#[inline(never)]
+unsafe fn task_entry() {
+    llvm_asm!("
+        jalr s1
+        jr s2
+    "
+    :    :    :    : "volatile"
+    );
+}
+
+/// `task_entry` for `init_task_with_arg`. `a0` and `a1` are the first two argument registers.
+#[naked]
+#[inline(never)]
+unsafe fn task_entry_with_arg() {
+    llvm_asm!("
+        mv a0, s3
+        mv a1, s4
+        jalr s1
+        jr s2
+    "
+    :    :    :    : "volatile"
+    );
+}
+
+/// So here is our inline Assembly. As you remember from our first example this is just a bit more elaborate where we first
+/// read out the values of all the registers we need and then sets all the register values to the register values we
+/// saved when we suspended exceution on the "new" task.
+///
+/// This is essentially all we need to do to save and resume execution.
+///
+/// Some details about inline assembly.
+///
+/// The assembly commands in the string literal is called the assemblt template. It is preceeded by
+/// zero or up to four segments indicated by ":":
+///
+/// - First ":" we have our output parameters, this parameters that this function will return.
+/// - Second ":" we have the input parameters. Our contexts are passed in `a0` and `a1` by the calling convention,
+/// so the only inputs we pass are the offsets of the fields in `TaskContext`. The `"i"` constraint tells the compiler
+/// these are immediates, so `$0` is replaced by the offset of `x1`, `$1` by the offset of `x2` and so on.
+/// We only read from the "new" context but we modify the "old" context saving our registers there
+/// (see volatile option below)
+/// - Third ":" This our clobber list, this is information to the compiler that these registers can't be used freely
+/// - Fourth ":" This is options we can pass inn, Rust has 3: "alignstack", "volatile" and "intel"
+///
+/// For this to work on windows we need to use "alignstack" where the compiler adds the neccesary padding to
+/// make sure our stack is aligned. Since we modify one of our inputs, our assembly has "side effects"
+/// therefore we should use the `volatile` option. I **think** this is actually set for us by default
+/// when there are no output parameters given (my own assumption after going through the source code)
+/// for the `asm` macro, but we should make it explicit anyway.
+///
+/// One last important part (it will not work without this) is the #[naked] attribute. Basically this lets us have full
+/// control over the stack layout since normal functions has a prologue-and epilogue added by the
+/// compiler that will cause trouble for us. We avoid this by marking the funtion as "Naked".
+/// For this to work on `release` builds we also need to use the `#[inline(never)] attribute or else
+/// the compiler decides to inline this function (curiously this currently only happens on Windows).
+/// If the function is inlined we get a curious runtime error where it fails when switching back
+/// to as saved context and in general our assembly will not work as expected.
+///
+/// see: https://github.com/rust-lang/rfcs/blob/master/text/1201-naked-fns.md
+#[naked]
+#[inline(never)]
+pub(crate) unsafe fn switch(old: *mut TaskContext, new: *const TaskContext) {
+    // a0: old, a1: new. `t0` is a temporary register so we're free to use it to check `save_fp`.
+    llvm_asm!("
+        ld t0, $14(a0)
+        beqz t0, 1f
+        fsd f8, $15(a0)
+        fsd f9, $16(a0)
+        fsd f18, $17(a0)
+        fsd f19, $18(a0)
+        fsd f20, $19(a0)
+        fsd f21, $20(a0)
+        fsd f22, $21(a0)
+        fsd f23, $22(a0)
+        fsd f24, $23(a0)
+        fsd f25, $24(a0)
+        fsd f26, $25(a0)
+        fsd f27, $26(a0)
+    1:
+        sd x1, $0(a0)
+        sd x2, $1(a0)
+        sd x8, $2(a0)
+        sd x9, $3(a0)
+        sd x18, $4(a0)
+        sd x19, $5(a0)
+        sd x20, $6(a0)
+        sd x21, $7(a0)
+        sd x22, $8(a0)
+        sd x23, $9(a0)
+        sd x24, $10(a0)
+        sd x25, $11(a0)
+        sd x26, $12(a0)
+        sd x27, $13(a0)
+
+        ld t0, $14(a1)
+        beqz t0, 2f
+        fld f8, $15(a1)
+        fld f9, $16(a1)
+        fld f18, $17(a1)
+        fld f19, $18(a1)
+        fld f20, $19(a1)
+        fld f21, $20(a1)
+        fld f22, $21(a1)
+        fld f23, $22(a1)
+        fld f24, $23(a1)
+        fld f25, $24(a1)
+        fld f26, $25(a1)
+        fld f27, $26(a1)
+    2:
+        ld x1, $0(a1)
+        ld x2, $1(a1)
+        ld x8, $2(a1)
+        ld x9, $3(a1)
+        ld x18, $4(a1)
+        ld x19, $5(a1)
+        ld x20, $6(a1)
+        ld x21, $7(a1)
+        ld x22, $8(a1)
+        ld x23, $9(a1)
+        ld x24, $10(a1)
+        ld x25, $11(a1)
+        ld x26, $12(a1)
+        ld x27, $13(a1)
+
+        ret
+    "
+    :
+    : "i"(offsets::x1), "i"(offsets::x2), "i"(offsets::x8), "i"(offsets::x9), "i"(offsets::x18),
+      "i"(offsets::x19), "i"(offsets::x20), "i"(offsets::x21), "i"(offsets::x22), "i"(offsets::x23),
+      "i"(offsets::x24), "i"(offsets::x25), "i"(offsets::x26), "i"(offsets::x27),
+      "i"(offsets::save_fp), "i"(offsets::f8), "i"(offsets::f9), "i"(offsets::f18), "i"(offsets::f19),
+      "i"(offsets::f20), "i"(offsets::f21), "i"(offsets::f22), "i"(offsets::f23), "i"(offsets::f24),
+      "i"(offsets::f25), "i"(offsets::f26), "i"(offsets::f27)
+    : "t0"
+    : "volatile", "alignstack"
+    );
+}
@@ -0,0 +1,226 @@
+//! A backend without any assembly, for testing the runtime (the `sim` feature). Every task runs on an OS thread
+//! of its own, but only one of them runs at a time: `switch` wakes the thread of the context we switch to and
+//! puts the current thread to sleep until somebody switches back to it. The scheduler, the channels and the
+//! locks see exactly the same switches they'd see with a real backend, but everything here is plain Rust, so
+//! the runtime can be checked with Miri, ThreadSanitizer or loom, none of which understand our assembly.
+//!
+//! Run the tests with `cargo +nightly miri test --features sim`. Add `-Zmiri-ignore-leaks` to `MIRIFLAGS`:
+//! the thread of a task that was cancelled keeps waiting for a turn that never comes until the process exits,
+//! and Miri counts that as a leak. For loom, add `loom` as a dependency (we don't, so normal builds don't
+//! need it) and build with `RUSTFLAGS="--cfg loom"`.
+//!
+//! Tasks don't run on the stacks the runtime gives them, and they don't share thread locals, so some things
+//! don't work here:
+//!
+//! - `fork_current` and `Runtime::snapshot`/`restore` copy stacks, they panic.
+//! - `generator`, `actor`, `parallel_for` and `WorkerPool` hand work to the task they spawn through a thread
+//!   local, which that task can't see. They panic too.
+//! - Stack usage, stack canaries, `Runtime::trim` and the gdb commands have no stack to look at.
+#[cfg(loom)]
+use loom::sync::{Condvar, Mutex};
+#[cfg(loom)]
+use loom::{thread, thread_local};
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::{mem, process, ptr};
+#[cfg(not(loom))]
+use std::{
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+task_context! {
+    /// There are no registers to save, the thread a context runs on keeps them. All we need is a way to wake
+    /// that thread up, and for a context that hasn't run yet, the function it starts with.
+    #[derive(Debug, Default)]
+    #[repr(C)]
+    struct TaskContext {
+        // the `Gate` of the thread running this context, 0 while it doesn't have one yet
+        gate: u64,
+        // what a new context runs: `f` and then `guard` once `f` returns. `f` is a `fn(usize)` if `with_arg`
+        // is set, and we call it with `arg`.
+        f: u64,
+        arg: u64,
+        with_arg: u64,
+        guard: u64,
+        // the top of the stack the runtime gave us, we don't use it
+        sp: u64,
+        save_fp: u64,
+    }
+}
+
+// a task's function and the `guard` it returns to
+type Start = (Entry, fn());
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Plain(fn()),
+    WithArg(fn(usize), usize),
+}
+
+/// Where a thread waits for its turn.
+struct Gate {
+    turn: Mutex<Turn>,
+    woken: Condvar,
+}
+
+struct Turn {
+    // set by whoever switches to this thread, cleared once it's awake
+    go: bool,
+    // the runtime of the thread that woke us, `RUNTIME` is a thread local and has to move with us
+    runtime: usize,
+    // what the thread runs next, see `thread_main`
+    start: Option<Start>,
+    // set once the task's function returned: the thread is parked in `guard` for good unless `init_task`
+    // gives it another task
+    finished: bool,
+}
+
+impl Gate {
+    fn new(start: Option<Start>) -> &'static Gate {
+        Box::leak(Box::new(Gate {
+            turn: Mutex::new(Turn {
+                go: false,
+                runtime: 0,
+                start,
+                finished: false,
+            }),
+            woken: Condvar::new(),
+        }))
+    }
+
+    fn open(&self, runtime: usize) {
+        let mut turn = self.turn.lock().unwrap();
+        turn.go = true;
+        turn.runtime = runtime;
+        self.woken.notify_one();
+    }
+
+    fn wait(&self) {
+        let mut turn = self.turn.lock().unwrap();
+        while !turn.go {
+            turn = self.woken.wait(turn).unwrap();
+        }
+        turn.go = false;
+        unsafe { crate::RUNTIME = turn.runtime };
+    }
+}
+
+thread_local! {
+    static GATE: Cell<*const Gate> = const { Cell::new(ptr::null()) };
+}
+
+/// The gate of the thread we're running on. Threads we didn't start (the one running the base task) get one the
+/// first time they switch.
+fn current_gate() -> &'static Gate {
+    GATE.with(|gate| {
+        if gate.get().is_null() {
+            gate.set(Gate::new(None));
+        }
+        unsafe { &*gate.get() }
+    })
+}
+
+fn thread_main(gate: &'static Gate) {
+    GATE.with(|g| g.set(gate));
+    gate.wait();
+    loop {
+        let (f, guard) = gate
+            .turn
+            .lock()
+            .unwrap()
+            .start
+            .take()
+            .expect("a task thread started without a task.");
+        // A panic can't unwind past the start of a task's stack with a real backend either.
+        let run = || match f {
+            Entry::Plain(f) => f(),
+            Entry::WithArg(f, arg) => f(arg),
+        };
+        if panic::catch_unwind(AssertUnwindSafe(run)).is_err() {
+            process::abort();
+        }
+        gate.turn.lock().unwrap().finished = true;
+        // `guard` switches away for good, unless `init_task` hands this thread a new task. Then it returns here.
+        guard();
+    }
+}
+
+/// Every thread has its FP registers to itself, there's nothing to save.
+pub(crate) fn set_save_fp(ctx: &mut TaskContext, save: bool) {
+    ctx.save_fp = save as u64;
+}
+
+pub(crate) fn stack_pointer(ctx: &TaskContext) -> usize {
+    ctx.sp as usize
+}
+
+/// There are no saved registers, a debugger has to look at the task's thread.
+pub(crate) fn saved_registers(ctx: &TaskContext) -> [*const u64; 3] {
+    [&ctx.sp, &ctx.sp, ptr::null()]
+}
+
+/// Makes the context start `f` the next time we switch to it. If the task that ran in it before finished, its
+/// thread runs `f`, otherwise a new thread does.
+pub(crate) unsafe fn init_task(ctx: &mut TaskContext, stack: &mut [u8], f: fn(), guard: fn()) {
+    start(ctx, stack, Entry::Plain(f), guard);
+}
+
+/// Same as `init_task` for a task that starts in `f(arg)`. There's no register to put `arg` in, the thread
+/// simply calls `f` with it.
+pub(crate) unsafe fn init_task_with_arg(
+    ctx: &mut TaskContext,
+    stack: &mut [u8],
+    f: fn(usize),
+    arg: usize,
+    guard: fn(),
+) {
+    start(ctx, stack, Entry::WithArg(f, arg), guard);
+}
+
+unsafe fn start(ctx: &mut TaskContext, stack: &mut [u8], entry: Entry, guard: fn()) {
+    ctx.sp = stack.as_mut_ptr() as u64 + stack.len() as u64;
+    if ctx.gate != 0 {
+        let gate = &*(ctx.gate as *const Gate);
+        let mut turn = gate.turn.lock().unwrap();
+        if turn.finished {
+            turn.finished = false;
+            turn.start = Some((entry, guard));
+            return;
+        }
+    }
+    // The task before us was cancelled (or there was none). We leave its thread waiting.
+    ctx.gate = 0;
+    let (f, arg, with_arg) = match entry {
+        Entry::Plain(f) => (f as usize, 0, false),
+        Entry::WithArg(f, arg) => (f as usize, arg, true),
+    };
+    ctx.f = f as u64;
+    ctx.arg = arg as u64;
+    ctx.with_arg = with_arg as u64;
+    ctx.guard = guard as usize as u64;
+}
+
+/// Wakes the thread of `new` (starting it if it's the first time) and waits until someone switches to `old`.
+pub(crate) unsafe fn switch(old: *mut TaskContext, new: *const TaskContext) {
+    let me = current_gate();
+    (*old).gate = me as *const Gate as u64;
+
+    // the first switch to a context starts its thread, we remember its gate for the next time
+    let new = new as *mut TaskContext;
+    if (*new).gate == 0 {
+        let f = (*new).f as usize;
+        let entry = if (*new).with_arg != 0 {
+            Entry::WithArg(mem::transmute::<usize, fn(usize)>(f), (*new).arg as usize)
+        } else {
+            Entry::Plain(mem::transmute::<usize, fn()>(f))
+        };
+        let guard = mem::transmute::<usize, fn()>((*new).guard as usize);
+        let gate = Gate::new(Some((entry, guard)));
+        (*new).gate = gate as *const Gate as u64;
+        thread::spawn(move || thread_main(gate));
+    }
+
+    (*((*new).gate as *const Gate)).open(crate::RUNTIME);
+    me.wait();
+}
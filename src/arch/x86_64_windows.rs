@@ -0,0 +1,253 @@
+task_context! {
+    /// On Windows the list of callee saved registers is longer than on other platforms. In addition
+    /// to `rsp`, `rbx`, `rbp` and `r12-r15` we need to save `rdi`, `rsi` and `xmm6-xmm15`. The XMM
+    /// registers are 128 bits each so we store them as two `u64` and align the struct to 16 bytes
+    /// so we can use `movaps` to read and write them.
+    ///
+    /// Windows also keeps the top and bottom of the current stack in the Thread Information Block
+    /// (TIB). Some functions (like `__chkstk` which is called whenever a function needs more than a
+    /// page of stack) check the stack pointer against these values, so we need to switch them as well.
+    /// `stack_start` is the "high" address and `stack_end` the "low" one since the stack grows downwards.
+    /// `deallocation_stack` is where the allocation of the stack starts, which the stack probes and the
+    /// code that grows the stack into its guard page use. Our stacks are allocated in one piece, so it's the
+    /// same as `stack_end` for a new task.
+    ///
+    /// The control bits of `MXCSR` (rounding and which SSE exceptions are masked) and the x87 control word
+    /// are callee saved on Windows too. `mxcsr` holds the 32 bits `stmxcsr` writes and `fpu_control` the 16
+    /// bits `fnstcw` writes, so a task that changes the rounding mode doesn't change it for everyone else.
+    #[derive(Debug, Default)]
+    #[repr(C, align(16))]
+    struct TaskContext {
+        xmm6: [u64; 2],
+        xmm7: [u64; 2],
+        xmm8: [u64; 2],
+        xmm9: [u64; 2],
+        xmm10: [u64; 2],
+        xmm11: [u64; 2],
+        xmm12: [u64; 2],
+        xmm13: [u64; 2],
+        xmm14: [u64; 2],
+        xmm15: [u64; 2],
+        rsp: u64,
+        r15: u64,
+        r14: u64,
+        r13: u64,
+        r12: u64,
+        rbx: u64,
+        rbp: u64,
+        rdi: u64,
+        rsi: u64,
+        stack_start: u64,
+        stack_end: u64,
+        save_fp: u64,
+        mxcsr: u64,
+        fpu_control: u64,
+        deallocation_stack: u64,
+    }
+}
+
+// What a new thread starts with on Windows: every SSE exception masked and rounding to nearest, and the same
+// for x87 with 53 bit precision
+const DEFAULT_MXCSR: u64 = 0x1f80;
+const DEFAULT_FPU_CONTROL: u64 = 0x027f;
+
+/// Decides if `switch` saves and restores `xmm6-xmm15` for this context. Tasks that don't use floating point
+/// or SIMD can skip them, that's 20 fewer 16 byte loads and stores per switch.
+pub(crate) fn set_save_fp(ctx: &mut TaskContext, save: bool) {
+    ctx.save_fp = save as u64;
+}
+
+pub(crate) fn stack_pointer(ctx: &TaskContext) -> usize {
+    ctx.rsp as usize
+}
+
+/// `rsp` and `rbp`. There's no slot for the return address: it's on the stack, right where `rsp` points, since
+/// `switch` ends with a `ret`. A null pointer tells the debugger to look there.
+pub(crate) fn saved_registers(ctx: &TaskContext) -> [*const u64; 3] {
+    [&ctx.rsp, &ctx.rbp, core::ptr::null()]
+}
+
+/// On x86_64 `switch` ends with a `ret` which pops the address we return to from the stack, so
+/// we write the address of our `task_entry` trampoline on our new stack and point `rsp` to it. We
+/// put `f` in `rbx` and `guard` in `r12` which `switch` restores for us just like any other register.
+///
+/// The Windows calling convention requires that `rsp` is 16 byte aligned *before* a `call`, which
+/// means it's `8 mod 16` when we enter a function (the return address was just pushed). It also
+/// requires the caller to reserve 32 bytes of "shadow space" right above the return address which
+/// the callee is free to use. `task_entry` is the one calling `f` and `guard`, so all we need to do is
+/// make sure `rsp` is aligned and has 32 bytes of space above it when we enter `task_entry`.
+///
+/// The stack looks like this (the top is the "high" address):
+///
+/// ```text
+/// s_ptr        <- 16 byte aligned
+/// s_ptr - 16   padding
+/// s_ptr - 48   shadow space for f and guard (32 bytes)   <- rsp in task_entry
+/// s_ptr - 56   task_entry                                <- ctx.rsp
+/// ```
+pub(crate) unsafe fn init_task(ctx: &mut TaskContext, stack: &mut [u8], f: fn(), guard: fn()) {
+    let size = stack.len();
+    let s_ptr = stack.as_mut_ptr().offset(size as isize);
+
+    // make sure our stack itself is 16 byte aligned - it will always
+    // offset to a lower memory address.
+    let s_ptr = (s_ptr as usize & !15) as *mut u8;
+
+    std::ptr::write(s_ptr.offset(-56) as *mut u64, task_entry as u64);
+    ctx.rsp = s_ptr.offset(-56) as u64;
+    ctx.rbx = f as u64;
+    ctx.r12 = guard as u64;
+
+    ctx.stack_start = s_ptr as u64;
+    ctx.stack_end = stack.as_ptr() as u64;
+    ctx.deallocation_stack = stack.as_ptr() as u64;
+    ctx.mxcsr = DEFAULT_MXCSR;
+    ctx.fpu_control = DEFAULT_FPU_CONTROL;
+}
+
+/// Same as `init_task`, but the task starts in `f(arg)`. `rbx` holds `call_with_arg` instead of `f`, and
+/// `arg` and `f` wait in `r13` and `r14` for `task_entry_with_arg`. The stack looks the same.
+pub(crate) unsafe fn init_task_with_arg(
+    ctx: &mut TaskContext,
+    stack: &mut [u8],
+    f: fn(usize),
+    arg: usize,
+    guard: fn(),
+) {
+    let size = stack.len();
+    let s_ptr = stack.as_mut_ptr().offset(size as isize);
+    let s_ptr = (s_ptr as usize & !15) as *mut u8;
+
+    std::ptr::write(s_ptr.offset(-56) as *mut u64, task_entry_with_arg as u64);
+    ctx.rsp = s_ptr.offset(-56) as u64;
+    ctx.rbx = super::call_with_arg as u64;
+    ctx.r12 = guard as u64;
+    ctx.r13 = arg as u64;
+    ctx.r14 = f as u64;
+
+    ctx.stack_start = s_ptr as u64;
+    ctx.stack_end = stack.as_ptr() as u64;
+    ctx.deallocation_stack = stack.as_ptr() as u64;
+    ctx.mxcsr = DEFAULT_MXCSR;
+    ctx.fpu_control = DEFAULT_FPU_CONTROL;
+}
+
+/// Every task starts here. We call `f` which we stored in `rbx` and when it returns we call `guard`
+/// which we stored in `r12`. `guard` never returns since it marks the task as finished and switches
+/// to another task, but if it ever did we'd rather crash right away with `ud2` than run whatever is
+/// next in memory.
+#[naked]
+#[inline(never)]
+unsafe fn task_entry() {
+    llvm_asm!("
+        call rbx
+        call r12
+        ud2
+    "
+    :    :    :    : "volatile", "intel"
+    );
+}
+
+/// `task_entry` for `init_task_with_arg`. The first two arguments go in `rcx` and `rdx` on Windows.
+#[naked]
+#[inline(never)]
+unsafe fn task_entry_with_arg() {
+    llvm_asm!("
+        mov rcx, r13
+        mov rdx, r14
+        call rbx
+        call r12
+        ud2
+    "
+    :    :    :    : "volatile", "intel"
+    );
+}
+
+/// The same as `switch` on other platforms, but we also save the XMM registers, `MXCSR`, the x87 control word
+/// and the stack limits stored in the TIB, which `gs` points to. `gs:[0x08]` is the stack base (the "high"
+/// address), `gs:[0x10]` is the stack limit and `gs:[0x1478]` is the `DeallocationStack` of the TEB.
+///
+/// The first argument is passed in `rcx` and the second in `rdx` on Windows. We skip the XMM registers of
+/// a context that doesn't have `save_fp` set. The labels are `2` and `3` since `1b` could be read as a binary
+/// number in Intel syntax.
+#[naked]
+#[inline(never)]
+pub(crate) unsafe fn switch(old: *mut TaskContext, new: *const TaskContext) {
+    // rcx: old, rdx: new
+    llvm_asm!("
+        cmp         qword ptr [rcx + $21], 0
+        je          2f
+        movaps      [rcx + $0], xmm6
+        movaps      [rcx + $1], xmm7
+        movaps      [rcx + $2], xmm8
+        movaps      [rcx + $3], xmm9
+        movaps      [rcx + $4], xmm10
+        movaps      [rcx + $5], xmm11
+        movaps      [rcx + $6], xmm12
+        movaps      [rcx + $7], xmm13
+        movaps      [rcx + $8], xmm14
+        movaps      [rcx + $9], xmm15
+    2:
+        mov         [rcx + $10], rsp
+        mov         [rcx + $11], r15
+        mov         [rcx + $12], r14
+        mov         [rcx + $13], r13
+        mov         [rcx + $14], r12
+        mov         [rcx + $15], rbx
+        mov         [rcx + $16], rbp
+        mov         [rcx + $17], rdi
+        mov         [rcx + $18], rsi
+        mov         rax, qword ptr gs:[0x08]
+        mov         [rcx + $19], rax
+        mov         rax, qword ptr gs:[0x10]
+        mov         [rcx + $20], rax
+        mov         rax, qword ptr gs:[0x1478]
+        mov         [rcx + $24], rax
+        stmxcsr     dword ptr [rcx + $22]
+        fnstcw      word ptr [rcx + $23]
+
+        cmp         qword ptr [rdx + $21], 0
+        je          3f
+        movaps      xmm6, [rdx + $0]
+        movaps      xmm7, [rdx + $1]
+        movaps      xmm8, [rdx + $2]
+        movaps      xmm9, [rdx + $3]
+        movaps      xmm10, [rdx + $4]
+        movaps      xmm11, [rdx + $5]
+        movaps      xmm12, [rdx + $6]
+        movaps      xmm13, [rdx + $7]
+        movaps      xmm14, [rdx + $8]
+        movaps      xmm15, [rdx + $9]
+    3:
+        mov         rsp, [rdx + $10]
+        mov         r15, [rdx + $11]
+        mov         r14, [rdx + $12]
+        mov         r13, [rdx + $13]
+        mov         r12, [rdx + $14]
+        mov         rbx, [rdx + $15]
+        mov         rbp, [rdx + $16]
+        mov         rdi, [rdx + $17]
+        mov         rsi, [rdx + $18]
+        mov         rax, [rdx + $19]
+        mov         qword ptr gs:[0x08], rax
+        mov         rax, [rdx + $20]
+        mov         qword ptr gs:[0x10], rax
+        mov         rax, [rdx + $24]
+        mov         qword ptr gs:[0x1478], rax
+        ldmxcsr     dword ptr [rdx + $22]
+        fldcw       word ptr [rdx + $23]
+
+        ret
+    "
+    :
+    : "i"(offsets::xmm6), "i"(offsets::xmm7), "i"(offsets::xmm8), "i"(offsets::xmm9),
+      "i"(offsets::xmm10), "i"(offsets::xmm11), "i"(offsets::xmm12), "i"(offsets::xmm13),
+      "i"(offsets::xmm14), "i"(offsets::xmm15), "i"(offsets::rsp), "i"(offsets::r15),
+      "i"(offsets::r14), "i"(offsets::r13), "i"(offsets::r12), "i"(offsets::rbx),
+      "i"(offsets::rbp), "i"(offsets::rdi), "i"(offsets::rsi), "i"(offsets::stack_start),
+      "i"(offsets::stack_end), "i"(offsets::save_fp), "i"(offsets::mxcsr), "i"(offsets::fpu_control),
+      "i"(offsets::deallocation_stack)
+    :
+    : "volatile", "alignstack", "intel"
+    );
+}
@@ -0,0 +1,278 @@
+//! A runtime that works without an allocator (the `static-alloc` feature), for bare-metal targets.
+//!
+//! It's the same idea as the full runtime, but there's nothing we need to allocate: the task table is an
+//! array with a size fixed at compile time, and instead of allocating stacks we take one `&'static mut [u8]`
+//! per task from the user (usually a `static mut` array). The only things we have are `spawn`, `yield_task`,
+//! `run` and waiting for interrupts and other events with `wait_for` (see the `wait` module), everything else
+//! in the full runtime depends on the standard library in one way or another.
+//!
+//! The task that calls `run` is our base task. It runs on whatever stack it already has, so it's not part
+//! of the table.
+//!
+//! We never save the floating point registers when we switch tasks here. Kernels usually keep the FPU turned
+//! off, and then the first FP instruction in `switch` would trap.
+//!
+//! `SmpRuntime` (see the `smp` module) is the version for several harts.
+use crate::arch::{self, switch, TaskContext};
+use crate::stack;
+pub use crate::stack::StackPolicy;
+
+mod smp;
+mod spin;
+mod wait;
+pub use smp::SmpRuntime;
+use wait::Woken;
+pub use wait::{wake, wake_pending, WaitSource};
+
+// The runtime is generic over the size of the task table, so we can't just store a pointer to it like the
+// full runtime does. We also store a function that knows its type to call `t_yield` or `t_return` on it.
+// `SmpRuntime` uses the same ones, so `yield_task` and `wait_for` work with either runtime.
+static mut RUNTIME: usize = 0;
+static mut YIELD: fn() -> bool = no_runtime;
+static mut RETURN: fn() = no_return;
+static mut WAIT: fn(usize) = no_wait;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum State {
+    Available,
+    Running,
+    Ready,
+    // waiting for `waiting_for` to be woken
+    Waiting,
+    // only used by `SmpRuntime`, until the hart it ran on has switched away from it
+    Finished,
+}
+
+struct Task {
+    stack: &'static mut [u8],
+    ctx: TaskContext,
+    state: State,
+    waiting_for: usize,
+}
+
+/// A runtime with room for `N` tasks besides the one that calls `run`.
+pub struct StaticRuntime<const N: usize> {
+    base: Task,
+    tasks: [Task; N],
+    // `N` is our base task
+    current: usize,
+    // called by `run` when every task is waiting
+    idle: fn(),
+    stack_policy: StackPolicy,
+}
+
+impl<const N: usize> StaticRuntime<N> {
+    /// Creates a runtime that runs its tasks on the given stacks, one for each task.
+    pub fn new(stacks: [&'static mut [u8]; N]) -> Self {
+        let tasks = stacks.map(|stack| Task {
+            stack,
+            ctx: TaskContext::default(),
+            state: State::Available,
+            waiting_for: 0,
+        });
+
+        StaticRuntime {
+            base: Task {
+                stack: &mut [],
+                ctx: TaskContext::default(),
+                state: State::Running,
+                waiting_for: 0,
+            },
+            tasks,
+            current: N,
+            idle: core::hint::spin_loop,
+            stack_policy: StackPolicy::Canary,
+        }
+    }
+
+    /// Stores a pointer to the runtime so `yield_task` and our guard function can find it. The runtime
+    /// must not move after this.
+    pub fn init(&mut self) {
+        unsafe {
+            RUNTIME = self as *mut Self as usize;
+            YIELD = yield_on::<N>;
+            RETURN = return_on::<N>;
+            WAIT = wait_on::<N>;
+        }
+    }
+
+    /// Sets the function `run` calls when every task is waiting for something. It's called over and over
+    /// until a wake arrives, so it can simply return, but on real hardware you'd rather put the hart to sleep
+    /// until the next interrupt. Mask interrupts, check `wake_pending` and only `wfi` if it's false, or a
+    /// wake that arrives between the check and the `wfi` could leave you sleeping. The default just spins.
+    pub fn on_idle(&mut self, idle: fn()) {
+        self.idle = idle;
+    }
+
+    /// How the stacks of the tasks we spawn from now on are protected, `StackPolicy::Canary` by default. We can't
+    /// map guard pages here, so asking for them panics; `StackPolicy::Plain` saves checking the canary on every
+    /// switch if you're sure your stacks are big enough.
+    pub fn set_stack_policy(&mut self, policy: StackPolicy) {
+        assert!(
+            !policy.has_guard_page(),
+            "guard pages need an OS, use `StackPolicy::Canary`."
+        );
+        self.stack_policy = policy;
+    }
+
+    /// Runs until every task has finished. While tasks are waiting for something we keep waiting with them.
+    pub fn run(&mut self) {
+        loop {
+            if self.t_yield() {
+                continue;
+            }
+            if !self.tasks.iter().any(|t| t.state == State::Waiting) {
+                break;
+            }
+            (self.idle)();
+        }
+    }
+
+    /// Spawns `f` on the next available task. Returns `false` if all tasks are in use.
+    pub fn spawn(&mut self, f: fn()) -> bool {
+        self.spawn_task(|ctx, stack| unsafe { arch::init_task(ctx, stack, f, guard) })
+    }
+
+    /// Same as `spawn` but the task starts in `f(arg)`, the way a driver task gets told which device it drives.
+    pub fn spawn_arg(&mut self, f: fn(usize), arg: usize) -> bool {
+        self.spawn_task(|ctx, stack| unsafe { arch::init_task_with_arg(ctx, stack, f, arg, guard) })
+    }
+
+    fn spawn_task(&mut self, init: impl FnOnce(&mut TaskContext, &mut [u8])) -> bool {
+        let policy = self.stack_policy;
+        let task = match self.tasks.iter_mut().find(|t| t.state == State::Available) {
+            Some(task) => task,
+            None => return false,
+        };
+        stack::prepare(task.stack, policy);
+        init(&mut task.ctx, task.stack);
+        task.state = State::Ready;
+        true
+    }
+
+    fn task(&mut self, id: usize) -> &mut Task {
+        if id == N {
+            &mut self.base
+        } else {
+            &mut self.tasks[id]
+        }
+    }
+
+    fn t_return(&mut self) {
+        if self.current != N {
+            self.tasks[self.current].state = State::Available;
+            self.t_yield();
+        }
+    }
+
+    /// Parks the current task until a wake for `key` arrives.
+    fn t_wait_for(&mut self, key: usize) {
+        if self.current == N {
+            panic!("the base task can't wait, it's the one that runs the other tasks.");
+        }
+        let task = &mut self.tasks[self.current];
+        task.waiting_for = key;
+        task.state = State::Waiting;
+        while self.tasks[self.current].state == State::Waiting {
+            if !self.t_yield() {
+                // nobody else can run, so we're the one that has to wait for the interrupt
+                (self.idle)();
+            }
+        }
+    }
+
+    /// Makes every task that waits for a key that has been woken `Ready`.
+    fn t_handle_wakes(&mut self) {
+        while let Some(woken) = wait::take() {
+            for task in self.tasks.iter_mut() {
+                let wakes = match woken {
+                    Woken::Key(key) => task.waiting_for == key,
+                    Woken::Everyone => true,
+                };
+                if task.state == State::Waiting && wakes {
+                    task.state = State::Ready;
+                }
+            }
+        }
+    }
+
+    /// Plain round-robin: we run the first `Ready` task after the current one.
+    fn t_yield(&mut self) -> bool {
+        self.t_handle_wakes();
+
+        let mut pos = self.current;
+        loop {
+            pos = (pos + 1) % (N + 1);
+            if self.task(pos).state == State::Ready {
+                break;
+            }
+            if pos == self.current {
+                return false;
+            }
+        }
+
+        if self.task(self.current).state == State::Running {
+            self.task(self.current).state = State::Ready;
+        }
+
+        let old = self.current;
+        if old != N && !stack::intact(self.tasks[old].stack, self.stack_policy) {
+            panic!("stack overflow detected: task {} overwrote its stack canary.", old);
+        }
+
+        self.task(pos).state = State::Running;
+        self.current = pos;
+
+        unsafe {
+            let old_ctx: *mut TaskContext = &mut self.task(old).ctx;
+            let new_ctx: *const TaskContext = &self.task(pos).ctx;
+            switch(old_ctx, new_ctx);
+        }
+
+        // see the comment at the end of `t_yield` in the full runtime
+        self.tasks.len() > 0 || N == 0
+    }
+}
+
+fn yield_on<const N: usize>() -> bool {
+    unsafe { (*(RUNTIME as *mut StaticRuntime<N>)).t_yield() }
+}
+
+fn return_on<const N: usize>() {
+    unsafe { (*(RUNTIME as *mut StaticRuntime<N>)).t_return() }
+}
+
+fn wait_on<const N: usize>(key: usize) {
+    unsafe { (*(RUNTIME as *mut StaticRuntime<N>)).t_wait_for(key) }
+}
+
+fn no_runtime() -> bool {
+    panic!("no runtime, call `StaticRuntime::init` first.");
+}
+
+fn no_return() {
+    no_runtime();
+}
+
+fn no_wait(_key: usize) {
+    no_runtime();
+}
+
+fn guard() {
+    unsafe { RETURN() };
+}
+
+/// Lets the other tasks run.
+pub fn yield_task() {
+    unsafe {
+        YIELD();
+    }
+}
+
+/// Parks the current task until `wake` is called with a source that has the same key. Can return without a
+/// wake for `source` if the wake queue overflowed, so check what you're waiting for in a loop.
+pub fn wait_for(source: &impl WaitSource) {
+    unsafe {
+        WAIT(source.key());
+    }
+}
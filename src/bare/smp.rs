@@ -0,0 +1,361 @@
+//! A runtime for several harts (RISC-V's word for a hardware thread, a CPU core as far as we're concerned).
+//!
+//! `StaticRuntime` gets away with `&mut self` everywhere because there's only one hart touching it. Here every
+//! hart calls `run_on_hart` on the same runtime at the same time, so everything they share is behind a
+//! `SpinLock` and the methods take `&self`:
+//!
+//! - every hart has its own run queue, and a task always runs on the hart it was spawned on
+//! - every task slot has a lock for its state, its context and stack belong to whichever hart runs it
+//! - the wakes from `wake` are handed out under one more lock, by whichever hart gets to it first
+//!
+//! We schedule the way xv6 does: every hart has a scheduler loop running on the stack it booted with, and a
+//! task that yields always switches back to that loop, which puts it back on the run queue and picks the next
+//! one. It costs us an extra switch, but a task is never on a run queue while we're still saving its
+//! registers, so no other hart can ever pick up a half saved context.
+//!
+//! None of the locks are ever taken by `wake`, so it's still safe to call from an interrupt handler.
+use super::spin::SpinLock;
+use super::wait::{self, Woken};
+use super::{guard, State, RETURN, RUNTIME, WAIT, YIELD};
+use crate::arch::{self, switch, TaskContext};
+use crate::stack;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// How many keys we remember that were woken while nobody was waiting for them
+const PENDING_WAKES: usize = 16;
+
+struct Slot {
+    status: SpinLock<Status>,
+    // only touched by the hart the task runs on
+    ctx: UnsafeCell<TaskContext>,
+    stack: UnsafeCell<&'static mut [u8]>,
+}
+
+struct Status {
+    state: State,
+    hart: usize,
+    waiting_for: usize,
+    // woken while the wake queue overflowed, the next `wait_for` returns right away
+    permit: bool,
+}
+
+struct Hart<const N: usize> {
+    // the context of the scheduler loop in `run_on_hart`
+    scheduler: UnsafeCell<TaskContext>,
+    // the task this hart runs right now, `N` while it's in the scheduler loop
+    current: AtomicUsize,
+    queue: SpinLock<RunQueue<N>>,
+}
+
+// A ring buffer of task ids. A task is on at most one queue at a time, so `N` entries are always enough.
+struct RunQueue<const N: usize> {
+    ids: [usize; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RunQueue<N> {
+    fn push(&mut self, id: usize) {
+        assert!(self.len < N, "task {} is on a run queue twice.", id);
+        self.ids[(self.head + self.len) % N] = id;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let id = self.ids[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(id)
+    }
+}
+
+struct Wakes {
+    // keys woken while no task was waiting for them
+    pending: [usize; PENDING_WAKES],
+    len: usize,
+}
+
+/// A runtime with room for `N` tasks that runs them on `HARTS` harts.
+pub struct SmpRuntime<const N: usize, const HARTS: usize> {
+    slots: [Slot; N],
+    harts: [Hart<N>; HARTS],
+    // tasks spawned that haven't finished yet, the harts stop once it's zero
+    alive: AtomicUsize,
+    // which hart `spawn` puts the next task on
+    next_hart: AtomicUsize,
+    wakes: SpinLock<Wakes>,
+    hart_id: fn() -> usize,
+    idle: fn(),
+}
+
+unsafe impl<const N: usize, const HARTS: usize> Sync for SmpRuntime<N, HARTS> {}
+
+impl<const N: usize, const HARTS: usize> SmpRuntime<N, HARTS> {
+    /// Creates a runtime that runs its tasks on the given stacks. `hart_id` must return the id of the hart
+    /// that calls it, between 0 and `HARTS` (in a kernel that's usually kept in the `tp` register).
+    pub fn new(stacks: [&'static mut [u8]; N], hart_id: fn() -> usize) -> Self {
+        let slots = stacks.map(|stack| Slot {
+            status: SpinLock::new(Status {
+                state: State::Available,
+                hart: 0,
+                waiting_for: 0,
+                permit: false,
+            }),
+            ctx: UnsafeCell::new(TaskContext::default()),
+            stack: UnsafeCell::new(stack),
+        });
+        let harts = [(); HARTS].map(|_| Hart {
+            scheduler: UnsafeCell::new(TaskContext::default()),
+            current: AtomicUsize::new(N),
+            queue: SpinLock::new(RunQueue {
+                ids: [0; N],
+                head: 0,
+                len: 0,
+            }),
+        });
+
+        SmpRuntime {
+            slots,
+            harts,
+            alive: AtomicUsize::new(0),
+            next_hart: AtomicUsize::new(0),
+            wakes: SpinLock::new(Wakes {
+                pending: [0; PENDING_WAKES],
+                len: 0,
+            }),
+            hart_id,
+            idle: core::hint::spin_loop,
+        }
+    }
+
+    /// Makes `yield_task` and `wait_for` use this runtime. Call it once before any hart calls `run_on_hart`,
+    /// and don't move the runtime after that.
+    pub fn init(&self) {
+        unsafe {
+            RUNTIME = self as *const Self as usize;
+            YIELD = yield_on::<N, HARTS>;
+            RETURN = return_on::<N, HARTS>;
+            WAIT = wait_on::<N, HARTS>;
+        }
+    }
+
+    /// Sets the function a hart calls when it has nothing to run, see `StaticRuntime::on_idle`.
+    pub fn on_idle(&mut self, idle: fn()) {
+        self.idle = idle;
+    }
+
+    /// Spawns `f` on the next hart, taking turns. Returns `false` if all tasks are in use.
+    pub fn spawn(&self, f: fn()) -> bool {
+        let hart = self.next_hart.fetch_add(1, Ordering::Relaxed) % HARTS;
+        self.spawn_on(hart, f)
+    }
+
+    /// Spawns `f` on the given hart. Returns `false` if all tasks are in use.
+    pub fn spawn_on(&self, hart: usize, f: fn()) -> bool {
+        assert!(hart < HARTS, "there's no hart {}.", hart);
+        for (id, slot) in self.slots.iter().enumerate() {
+            let mut status = slot.status.lock();
+            if status.state != State::Available {
+                continue;
+            }
+            // the slot is ours now, so nobody else touches its stack and context
+            unsafe {
+                let stack = &mut *slot.stack.get();
+                stack::write_canary(stack);
+                arch::init_task(&mut *slot.ctx.get(), stack, f, guard);
+            }
+            status.state = State::Ready;
+            status.hart = hart;
+            status.permit = false;
+            drop(status);
+
+            self.alive.fetch_add(1, Ordering::AcqRel);
+            self.harts[hart].queue.lock().push(id);
+            return true;
+        }
+        false
+    }
+
+    /// The scheduler loop for the calling hart. Every hart calls this with its own id, and it returns once
+    /// every task on every hart has finished.
+    pub fn run_on_hart(&self, hart: usize) {
+        assert!(hart < HARTS, "there's no hart {}.", hart);
+        let me = &self.harts[hart];
+        loop {
+            self.t_handle_wakes();
+
+            let id = match me.queue.lock().pop() {
+                Some(id) => id,
+                None if self.alive.load(Ordering::Acquire) == 0 => return,
+                None => {
+                    (self.idle)();
+                    continue;
+                }
+            };
+
+            let slot = &self.slots[id];
+            slot.status.lock().state = State::Running;
+            me.current.store(id, Ordering::Relaxed);
+            unsafe {
+                switch(me.scheduler.get(), slot.ctx.get());
+            }
+            me.current.store(N, Ordering::Relaxed);
+
+            if !stack::canary_intact(unsafe { &*slot.stack.get() }) {
+                panic!("stack overflow detected: task {} overwrote its stack canary.", id);
+            }
+
+            // The task is off its stack now, so it's safe to let anyone else see it again
+            let mut status = slot.status.lock();
+            match status.state {
+                State::Running => {
+                    status.state = State::Ready;
+                    drop(status);
+                    me.queue.lock().push(id);
+                }
+                State::Finished => {
+                    status.state = State::Available;
+                    drop(status);
+                    self.alive.fetch_sub(1, Ordering::AcqRel);
+                }
+                // a task that's `Waiting` is put back on our queue by whoever wakes it, and if that already
+                // happened it's `Ready` and on the queue
+                _ => {}
+            }
+        }
+    }
+
+    // the hart we're on and the task it's running
+    fn t_current(&self) -> (&Hart<N>, usize) {
+        let hart = &self.harts[(self.hart_id)()];
+        (hart, hart.current.load(Ordering::Relaxed))
+    }
+
+    fn t_yield(&self) -> bool {
+        let (hart, id) = self.t_current();
+        if id == N {
+            // the scheduler loop doesn't yield, it's the one that runs everybody else
+            return false;
+        }
+        unsafe {
+            switch(self.slots[id].ctx.get(), hart.scheduler.get());
+        }
+        true
+    }
+
+    fn t_return(&self) {
+        let (hart, id) = self.t_current();
+        self.slots[id].status.lock().state = State::Finished;
+        unsafe {
+            switch(self.slots[id].ctx.get(), hart.scheduler.get());
+        }
+    }
+
+    fn t_wait_for(&self, key: usize) {
+        let (hart, id) = self.t_current();
+        if id == N {
+            panic!("the scheduler loop can't wait, it's the one that runs the other tasks.");
+        }
+
+        // Another hart can hand out wakes at any time, so we decide if we wait while we hold the lock it
+        // needs to do that. A wake that came while nobody was waiting makes us return right away.
+        let mut wakes = self.wakes.lock();
+        self.t_drain_wakes(&mut wakes);
+        let mut status = self.slots[id].status.lock();
+        if status.permit {
+            status.permit = false;
+            return;
+        }
+        if let Some(pos) = wakes.pending[..wakes.len].iter().position(|&k| k == key) {
+            wakes.len -= 1;
+            wakes.pending[pos] = wakes.pending[wakes.len];
+            return;
+        }
+        status.state = State::Waiting;
+        status.waiting_for = key;
+        drop(status);
+        drop(wakes);
+
+        unsafe {
+            switch(self.slots[id].ctx.get(), hart.scheduler.get());
+        }
+    }
+
+    fn t_handle_wakes(&self) {
+        if !wait::wake_pending() {
+            return;
+        }
+        let mut wakes = self.wakes.lock();
+        self.t_drain_wakes(&mut wakes);
+    }
+
+    /// Makes every task waiting for a key that has been woken `Ready` and puts it back on the run queue of its
+    /// hart. Keys nobody waits for are remembered for the next `wait_for`.
+    fn t_drain_wakes(&self, wakes: &mut Wakes) {
+        while let Some(woken) = wait::take() {
+            let key = match woken {
+                Woken::Key(key) => key,
+                Woken::Everyone => {
+                    self.t_wake_everyone();
+                    continue;
+                }
+            };
+
+            let mut delivered = false;
+            for (id, slot) in self.slots.iter().enumerate() {
+                let mut status = slot.status.lock();
+                if status.state == State::Waiting && status.waiting_for == key {
+                    delivered = true;
+                    status.state = State::Ready;
+                    let hart = status.hart;
+                    drop(status);
+                    self.harts[hart].queue.lock().push(id);
+                }
+            }
+
+            if !delivered && !wakes.pending[..wakes.len].contains(&key) {
+                if wakes.len == PENDING_WAKES {
+                    // we can't remember this one, so everybody has to go and check
+                    self.t_wake_everyone();
+                } else {
+                    wakes.pending[wakes.len] = key;
+                    wakes.len += 1;
+                }
+            }
+        }
+    }
+
+    /// Wakes every task that's waiting and makes the next `wait_for` of every other task return right away.
+    /// That's what we do when we've lost track of some wakes, since we can't know who they were for.
+    fn t_wake_everyone(&self) {
+        for (id, slot) in self.slots.iter().enumerate() {
+            let mut status = slot.status.lock();
+            match status.state {
+                State::Waiting => {
+                    status.state = State::Ready;
+                    let hart = status.hart;
+                    drop(status);
+                    self.harts[hart].queue.lock().push(id);
+                }
+                State::Available | State::Finished => {}
+                _ => status.permit = true,
+            }
+        }
+    }
+}
+
+fn yield_on<const N: usize, const HARTS: usize>() -> bool {
+    unsafe { (*(RUNTIME as *const SmpRuntime<N, HARTS>)).t_yield() }
+}
+
+fn return_on<const N: usize, const HARTS: usize>() {
+    unsafe { (*(RUNTIME as *const SmpRuntime<N, HARTS>)).t_return() }
+}
+
+fn wait_on<const N: usize, const HARTS: usize>(key: usize) {
+    unsafe { (*(RUNTIME as *const SmpRuntime<N, HARTS>)).t_wait_for(key) }
+}
@@ -0,0 +1,37 @@
+//! Waiting for things that happen outside of our tasks, like an interrupt or a device becoming ready.
+//!
+//! A task calls `wait_for` with a `WaitSource` and stays off the run queue until someone calls `wake` with
+//! a source that has the same key. `wake` is safe to call from an interrupt handler: it never blocks and
+//! never touches the runtime, it only pushes the key onto a lock-free queue (see `wake_queue`) that the runtime
+//! drains every time it schedules.
+//!
+//! A wake that nobody waits for is dropped, so check whatever you're waiting for before you wait and again
+//! after you wake up. That doesn't lose wakeups: the queue is only drained when a task yields, so a wake that
+//! arrives after your check stays in the queue until you're waiting for it.
+use crate::wake_queue::WakeQueue;
+pub(crate) use crate::wake_queue::Woken;
+
+/// Something a task can wait for. All that matters to the runtime is the key: `wake` wakes every task that
+/// waits for a source with the same key. An IRQ number makes a good key, so does the address of a device.
+pub trait WaitSource {
+    fn key(&self) -> usize;
+}
+
+static QUEUE: WakeQueue = WakeQueue::new();
+
+/// Wakes every task waiting for a source with the same key as `source`. Can be called from anywhere,
+/// including interrupt handlers.
+pub fn wake(source: &impl WaitSource) {
+    QUEUE.push(source.key());
+}
+
+/// Returns true if there are wakes the runtime hasn't handled yet. An idle function can use this to decide
+/// if it's safe to put the hart to sleep (see `StaticRuntime::on_idle`).
+pub fn wake_pending() -> bool {
+    QUEUE.is_pending()
+}
+
+/// Takes the next wake out of the queue. Only the runtime calls this.
+pub(crate) fn take() -> Option<Woken> {
+    QUEUE.take()
+}
@@ -0,0 +1,49 @@
+//! Wake batching, for pipelines where a producer sends messages in bursts. Normally every wake goes through the
+//! injector, even when the task doing the waking runs on the same runtime as the task it wakes: `send` on a
+//! `channel` takes the injector's lock, signals its condition variable and writes to the reactor's eventfd, and a
+//! `sync::Mutex` switches to the task it hands the lock to right away if that task is more important.
+//!
+//! With `Runtime::set_wake_batching(true)` a task that wakes another task on the same runtime only puts its id
+//! on a list, the same id only once. The producer keeps running until it yields, and then we go through the list
+//! and every consumer it woke gets its turn. Unlocking a mutex doesn't switch either, the new owner waits for the
+//! yield like everyone else. Wakes from other OS threads still go through the injector.
+use crate::handle::Injector;
+use crate::{Runtime, RUNTIME};
+use std::sync::Arc;
+
+impl Runtime {
+    /// Batches wakes between tasks of this runtime until the waking task yields, see the `batch` module. Off by
+    /// default.
+    pub fn set_wake_batching(&mut self, batching: bool) {
+        self.batch_wakes = batching;
+        if !batching {
+            self.t_wake_batched();
+        }
+    }
+
+    /// Makes every task woken since the last yield `Ready`.
+    pub(crate) fn t_wake_batched(&mut self) {
+        for i in 0..self.batched.len() {
+            self.t_unpark(self.batched[i]);
+        }
+        self.batched.clear();
+    }
+}
+
+/// Puts task `id` on the batch if wakes are batched and `injector` belongs to the runtime of the OS thread we're
+/// running on. Returns false if the caller has to go through the injector instead.
+pub(crate) fn batch_wake(injector: &Arc<Injector>, id: usize) -> bool {
+    unsafe {
+        if RUNTIME == 0 {
+            return false;
+        }
+        let rt = &mut *(RUNTIME as *mut Runtime);
+        if !rt.batch_wakes || !Arc::ptr_eq(injector, &rt.injector) {
+            return false;
+        }
+        if !rt.batched.contains(&id) {
+            rt.batched.push(id);
+        }
+    }
+    true
+}
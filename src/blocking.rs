@@ -0,0 +1,49 @@
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// The number of OS threads we use to run blocking calls
+const BLOCKING_THREADS: usize = 4;
+
+pub(crate) type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A very simple thread pool. All our blocking calls end up here so they can block an OS
+/// thread of their own instead of the one running our tasks.
+///
+/// We don't start any threads until the first job arrives, most examples never need them.
+pub(crate) struct BlockingPool {
+    sender: Option<Sender<Job>>,
+}
+
+impl BlockingPool {
+    pub(crate) fn new() -> Self {
+        BlockingPool { sender: None }
+    }
+
+    pub(crate) fn execute(&mut self, job: Job) {
+        let sender = self.sender.get_or_insert_with(start_threads);
+        sender.send(job).expect("blocking pool has shut down.");
+    }
+}
+
+/// All threads share one receiver. Whoever gets the lock first gets the next job.
+fn start_threads() -> Sender<Job> {
+    let (sender, receiver) = channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    for i in 0..BLOCKING_THREADS {
+        let receiver = receiver.clone();
+        thread::Builder::new()
+            .name(format!("blocking-{}", i))
+            .spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                job();
+            })
+            .expect("failed to start blocking thread.");
+    }
+
+    sender
+}
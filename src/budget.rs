@@ -0,0 +1,93 @@
+//! CPU time budgets, for running code you don't trust (student submissions, plugins) next to code you do. A
+//! task spawned with `Runtime::spawn_with_budget` may run for that long in total, counted the same way as
+//! `TaskStats::run_time`. We can only take a look when the task yields, so a task that never yields can't be
+//! stopped, that's what `maybe_yield` and the checkpoints are for.
+//!
+//! When a task has used up its budget we call the handler set with `Runtime::on_budget_exceeded`, and it
+//! decides what happens to the task:
+//!
+//! - `OverBudget::Cancel` (the default) cancels it. Like the tasks `run_until` cancels, it's never scheduled
+//!   again and nothing on its stack is dropped. We wait until it yields while it's running and doesn't hold a
+//!   `sync::Mutex`, so the locks and wait queues it's in don't end up pointing to a task that's gone. Joining
+//!   it returns as if it had finished.
+//! - `OverBudget::Demote` lets it carry on, but only when no other task is ready to run.
+use crate::scheduler::Candidate;
+use crate::{JoinHandle, Runtime, State};
+use std::fmt;
+use std::time::Duration;
+
+/// A task that has run for longer than its budget.
+#[derive(Debug, Clone, Copy)]
+pub struct BudgetExceeded {
+    pub task: usize,
+    pub budget: Duration,
+    pub used: Duration,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "task {} ran for {:?}, its budget was {:?}",
+            self.task, self.used, self.budget
+        )
+    }
+}
+
+/// What to do with a task that has used up its budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudget {
+    Cancel,
+    Demote,
+}
+
+/// The default handler.
+pub(crate) fn cancel_over_budget(_exceeded: &BudgetExceeded) -> OverBudget {
+    OverBudget::Cancel
+}
+
+impl Runtime {
+    /// Same as `spawn` but the task may only run for `budget` in total. What happens once it has is up to the
+    /// handler set with `on_budget_exceeded`, by default the task is cancelled.
+    pub fn spawn_with_budget(&mut self, f: fn(), budget: Duration) -> JoinHandle {
+        self.t_spawn_with(f, |task| task.budget = Some(budget))
+    }
+
+    /// Sets the function that gets called when a task has used up its budget. It's called once per task, and
+    /// what it returns decides what happens to the task.
+    pub fn on_budget_exceeded(&mut self, handler: fn(&BudgetExceeded) -> OverBudget) {
+        self.budget_handler = handler;
+    }
+
+    /// Called after we charged the current task for the time it ran.
+    pub(crate) fn t_check_budget(&mut self) {
+        let task = &mut self.tasks[self.current];
+        let budget = match task.budget {
+            Some(budget) if task.run_time > budget => budget,
+            _ => return,
+        };
+        task.budget = None;
+        self.budgets_exceeded += 1;
+        let exceeded = BudgetExceeded {
+            task: self.current,
+            budget,
+            used: task.run_time,
+        };
+        match (self.budget_handler)(&exceeded) {
+            OverBudget::Cancel => self.tasks[self.current].cancelled = true,
+            OverBudget::Demote => self.tasks[self.current].demoted = true,
+        }
+    }
+
+    /// Leaves out the demoted tasks if anybody else wants to run. The base task doesn't count while it's the
+    /// one yielding, or a demoted task would never run from `run`.
+    pub(crate) fn t_skip_demoted(&self, candidates: &mut Vec<Candidate>) {
+        let wants_to_run = |c: &Candidate| {
+            let task = &self.tasks[c.id];
+            !task.demoted && (task.state == State::Ready || (c.id == self.current && c.id != 0))
+        };
+        if candidates.iter().any(|c| self.tasks[c.id].demoted) && candidates.iter().any(wants_to_run) {
+            candidates.retain(|c| !self.tasks[c.id].demoted);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! A channel that works across runtimes. With one `Runtime` per OS thread, a task on one of them can send
+//! messages to a task on another one, and if the receiving task is parked waiting for a message the sender
+//! wakes it through its runtime's injector (see `RuntimeHandle::unpark`). Within a single runtime it works
+//! just the same.
+//!
+//! It's a multi-producer, single-consumer queue like `std::sync::mpsc`, and uses its error types. The
+//! difference is that `Receiver::recv` parks the task instead of blocking the OS thread, so the other tasks
+//! on the receiving runtime keep running while it waits.
+use crate::sync::WaitList;
+use crate::{Runtime, RuntimeHandle, RUNTIME};
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvError, SendError, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+struct Shared<T> {
+    queue: VecDeque<T>,
+    // the task parked in `recv` and a handle to the runtime it runs on
+    waiter: Option<(RuntimeHandle, usize)>,
+    senders: usize,
+    receiver: bool,
+}
+
+/// Creates a new channel. Both ends can be sent to other OS threads, but `recv` must be called from a task.
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        waiter: None,
+        senders: 1,
+        receiver: true,
+    }));
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+pub struct Sender<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a message and wakes the receiving task if it waits for one. Never blocks. Fails and gives the
+    /// message back if the receiver is gone.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.receiver {
+            return Err(SendError(message));
+        }
+        shared.queue.push_back(message);
+        let waiter = shared.waiter.take();
+        drop(shared);
+
+        if let Some((handle, id)) = waiter {
+            handle.unpark(id);
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.senders -= 1;
+        // the receiver has to find out that nothing is coming anymore
+        let waiter = if shared.senders == 0 {
+            shared.waiter.take()
+        } else {
+            None
+        };
+        drop(shared);
+
+        if let Some((handle, id)) = waiter {
+            handle.unpark(id);
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Parks the current task until there's a message. Fails once every sender is gone and the queue is empty.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            let mut shared = self.shared.lock().unwrap();
+            if let Some(message) = shared.queue.pop_front() {
+                return Ok(message);
+            }
+            if shared.senders == 0 {
+                return Err(RecvError);
+            }
+            let (handle, id) = unsafe {
+                let rt_ptr = RUNTIME as *const Runtime;
+                ((*rt_ptr).handle(), (*rt_ptr).current)
+            };
+            shared.waiter = Some((handle, id));
+            drop(shared);
+
+            // can return spuriously, so we check again either way
+            unsafe {
+                let rt_ptr = RUNTIME as *mut Runtime;
+                (*rt_ptr).t_set_waits_in(Some(WaitList::new(&*self.shared, forget_waiter::<T>)));
+                crate::park_task();
+                // we might be on another runtime now
+                let rt_ptr = RUNTIME as *mut Runtime;
+                (*rt_ptr).t_set_waits_in(None);
+            }
+        }
+    }
+
+    /// Takes a message if there is one, without waiting.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.queue.pop_front() {
+            Some(message) => Ok(message),
+            None if shared.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+// Takes task `id` off the channel at `shared` if it's still the one waiting there, see `WaitList`.
+unsafe fn forget_waiter<T>(shared: *const (), id: usize) {
+    let shared = &*(shared as *const Mutex<Shared<T>>);
+    let mut shared = shared.lock().unwrap();
+    if matches!(shared.waiter, Some((_, waiter)) if waiter == id) {
+        shared.waiter = None;
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.receiver = false;
+        shared.waiter = None;
+        shared.queue.clear();
+    }
+}
@@ -0,0 +1,79 @@
+//! Cooperative preemption checkpoints. A task doing a long computation has to yield now and then or nothing
+//! else runs, but yielding on every iteration of a hot loop costs a context switch each time. `maybe_yield`
+//! is cheap enough to call on every iteration: most of the time it only counts, and it only yields once the
+//! task has used up its time slice (or made `yield_every` calls, if that's set).
+//!
+//! Reading the clock isn't free either, so we only look at it every `CLOCK_EVERY` calls. The time the task was
+//! switched in is already kept by `t_account`, so that's what we measure the slice from.
+//!
+//! A checkpoint is also where a task gets preempted: after `RuntimeHandle::preempt` it yields at the next one,
+//! slice or not, unless it holds a `preempt::NoPreempt` (see the `preempt` module).
+use crate::{preempt, Runtime, RUNTIME};
+use std::time::{Duration, Instant};
+
+// how many calls of `maybe_yield` we go between looking at the clock, a power of two so it's a cheap mask
+const CLOCK_EVERY: u32 = 64;
+const DEFAULT_TIME_SLICE: Duration = Duration::from_millis(1);
+
+pub(crate) struct Checkpoints {
+    // calls since the current task was switched in, counted twice: `calls` wraps and only drives the
+    // `CLOCK_EVERY` phase, `saturated` is what we compare `every` against. A task with preemption off can make
+    // more calls than fit in a `u32` without ever yielding.
+    calls: u32,
+    saturated: u32,
+    // yield after this many calls no matter how much time has passed, 0 if only the time slice counts
+    every: u32,
+    slice: Duration,
+}
+
+impl Checkpoints {
+    pub(crate) fn new() -> Self {
+        Checkpoints {
+            calls: 0,
+            saturated: 0,
+            every: 0,
+            slice: DEFAULT_TIME_SLICE,
+        }
+    }
+
+    /// Called whenever the current task yields, however it does it. The next task starts a new slice.
+    pub(crate) fn reset(&mut self) {
+        self.calls = 0;
+        self.saturated = 0;
+    }
+}
+
+impl Runtime {
+    /// How long a task runs before `maybe_yield` actually yields (1 ms by default).
+    pub fn set_time_slice(&mut self, slice: Duration) {
+        self.checkpoints.slice = slice;
+    }
+
+    /// Makes `maybe_yield` also yield after `calls` calls, even if the time slice isn't used up yet. 0 turns
+    /// that off, which is the default.
+    pub fn set_yield_every(&mut self, calls: u32) {
+        self.checkpoints.every = calls;
+    }
+
+    fn t_maybe_yield(&mut self) {
+        let checkpoints = &mut self.checkpoints;
+        checkpoints.calls = checkpoints.calls.wrapping_add(1);
+        checkpoints.saturated = checkpoints.saturated.saturating_add(1);
+        let counted_out = checkpoints.every != 0 && checkpoints.saturated >= checkpoints.every;
+        let timed_out =
+            checkpoints.calls & (CLOCK_EVERY - 1) == 0 && Instant::now() - self.switched_in >= checkpoints.slice;
+        let preempted = self.injector.preempt_requested();
+        if (counted_out || timed_out || preempted) && preempt::is_enabled() {
+            self.t_yield();
+        }
+    }
+}
+
+/// A yield for hot loops. It yields if the current task has run for longer than its time slice (see
+/// `Runtime::set_time_slice`), otherwise it returns right away.
+pub fn maybe_yield() {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_maybe_yield();
+    }
+}
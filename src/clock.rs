@@ -0,0 +1,109 @@
+//! Time as our tasks see it.
+//!
+//! Normally that's just wall time, and `coro::sleep` really waits. With a virtual clock nobody ever waits:
+//! sleeping tasks are parked on a list, and whenever no task is ready to run the scheduler jumps the clock
+//! straight to the earliest deadline on that list and wakes whoever was waiting for it. A test that sleeps
+//! for an hour finishes right away, and since nothing depends on how fast the machine is, it does exactly
+//! the same thing every time it runs.
+use crate::Runtime;
+use std::time::{Duration, Instant};
+
+pub(crate) enum Clock {
+    Real(Instant),
+    Virtual {
+        now: Duration,
+        // (deadline, task) for every sleeping task in the order they went to sleep
+        sleepers: Vec<(Duration, usize)>,
+    },
+}
+
+impl Clock {
+    pub(crate) fn real() -> Self {
+        Clock::Real(Instant::now())
+    }
+
+    fn is_sleeping(&self, id: usize) -> bool {
+        match self {
+            Clock::Real(_) => false,
+            Clock::Virtual { sleepers, .. } => sleepers.iter().any(|&(_, task)| task == id),
+        }
+    }
+
+    /// Forgets that task `id` sleeps, used when a task is cancelled.
+    pub(crate) fn forget(&mut self, id: usize) {
+        if let Clock::Virtual { sleepers, .. } = self {
+            sleepers.retain(|&(_, task)| task != id);
+        }
+    }
+}
+
+impl Runtime {
+    /// Switches the runtime to a virtual clock starting at zero. Do this before you spawn anything, tasks
+    /// that already sleep keep sleeping in wall time.
+    pub fn use_virtual_clock(&mut self) {
+        self.clock = Clock::Virtual {
+            now: Duration::from_secs(0),
+            sleepers: vec![],
+        };
+    }
+
+    /// How much time has passed since the runtime was created (or since it switched to a virtual clock).
+    pub(crate) fn t_now(&self) -> Duration {
+        match &self.clock {
+            Clock::Real(start) => start.elapsed(),
+            Clock::Virtual { now, .. } => *now,
+        }
+    }
+
+    pub(crate) fn t_sleep(&mut self, dur: Duration) {
+        if let Clock::Virtual { now, sleepers } = &mut self.clock {
+            sleepers.push((*now + dur, self.current));
+            let me = self.current;
+            // `t_advance_clock` removes us before it unparks us, anything else is a spurious wakeup
+            while self.clock.is_sleeping(me) {
+                self.t_park();
+            }
+            return;
+        }
+
+        if dur.is_zero() {
+            // a timerfd set to zero is disarmed and would never fire
+            self.t_yield();
+            return;
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let timer = crate::reactor::TimerFd::new().expect("failed to create a timer.");
+            timer.set(dur, None).expect("failed to set the timer.");
+            timer.wait().expect("failed to wait for the timer.");
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            self.t_spawn_blocking(move || std::thread::sleep(dur));
+        }
+    }
+
+    /// Moves a virtual clock forward to the earliest deadline and wakes every task sleeping until then.
+    /// Returns false if there's no virtual clock or nobody sleeps.
+    pub(crate) fn t_advance_clock(&mut self) -> bool {
+        let woken = match &mut self.clock {
+            Clock::Virtual { now, sleepers } if !sleepers.is_empty() => {
+                let deadline = sleepers.iter().map(|&(deadline, _)| deadline).min().unwrap();
+                *now = (*now).max(deadline);
+                let woken: Vec<usize> = sleepers
+                    .iter()
+                    .filter(|&&(d, _)| d == deadline)
+                    .map(|&(_, id)| id)
+                    .collect();
+                sleepers.retain(|&(d, _)| d != deadline);
+                woken
+            }
+            _ => return false,
+        };
+        for id in woken {
+            self.t_unpark(id);
+        }
+        true
+    }
+}
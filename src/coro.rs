@@ -0,0 +1,100 @@
+//! Free functions that act on the runtime running the current task, named after their counterparts in
+//! `std::thread`. Code written against `std::thread::{spawn, yield_now, sleep, current}` can usually be moved
+//! to green threads by changing the `use` to this module (see `examples/compat`).
+//!
+//! The differences are the ones you'd expect: `spawn` takes a `fn()` (closures that don't capture anything
+//! work too), `JoinHandle::join` doesn't return a `Result`, and the ids are our task ids.
+use crate::{JoinHandle, Runtime, RUNTIME};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// Spawns a new task on the current runtime, see `Runtime::spawn`.
+pub fn spawn(f: fn()) -> JoinHandle {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).spawn(f)
+    }
+}
+
+/// Spawns `f(arg)` on the current runtime, see `Runtime::spawn_arg`.
+pub fn spawn_arg(f: fn(usize), arg: usize) -> JoinHandle {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).spawn_arg(f, arg)
+    }
+}
+
+/// Spawns `f(value)` on the current runtime, see `Runtime::spawn_with`.
+pub fn spawn_with<T: Send + 'static>(f: fn(T), value: T) -> JoinHandle {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).spawn_with(f, value)
+    }
+}
+
+/// Lets the other tasks run before we continue.
+pub fn yield_now() {
+    crate::yield_task();
+}
+
+/// Parks the current task for at least `dur`. The other tasks keep running in the meantime. With a virtual
+/// clock (see `Runtime::use_virtual_clock`) this returns as soon as no other task has anything left to do.
+pub fn sleep(dur: Duration) {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_sleep(dur);
+    }
+}
+
+/// How much time has passed since the runtime was created, the virtual time if the runtime uses a
+/// virtual clock.
+pub fn now() -> Duration {
+    unsafe {
+        let rt_ptr = RUNTIME as *const Runtime;
+        (*rt_ptr).t_now()
+    }
+}
+
+/// Parks the current task until someone calls `unpark` on it. Like `std::thread::park` this can return
+/// spuriously.
+pub fn park() {
+    crate::park_task();
+}
+
+/// Returns a handle to the task that's currently running.
+pub fn current() -> Task {
+    Task {
+        id: crate::task_id(),
+        _not_send: PhantomData,
+    }
+}
+
+/// A handle to a task, the green threads version of `std::thread::Thread`.
+#[derive(Debug, Clone)]
+pub struct Task {
+    id: usize,
+    // it talks directly to the runtime on this OS thread, use `RuntimeHandle::unpark` from other threads
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Task {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The name the task was spawned with, see `Runtime::spawn_named`.
+    pub fn name(&self) -> Option<String> {
+        unsafe {
+            let rt_ptr = RUNTIME as *const Runtime;
+            (*rt_ptr).t_name(self.id)
+        }
+    }
+
+    /// Wakes the task if it's parked, or makes its next `park` return right away if it isn't.
+    pub fn unpark(&self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_unpark(self.id);
+        }
+    }
+}
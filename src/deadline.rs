@@ -0,0 +1,68 @@
+//! Tasks with deadlines, for real-time scheduling. A deadline is a point in runtime time (see `coro::now`) by
+//! which the task should have finished. The runtime doesn't enforce it, it only checks when the task returns
+//! and reports it if it was late. To actually run tasks in deadline order use `scheduler::Edf`.
+use crate::{JoinHandle, Runtime};
+use std::fmt;
+use std::time::Duration;
+
+/// A task that finished after its deadline.
+#[derive(Debug, Clone, Copy)]
+pub struct DeadlineMiss {
+    pub task: usize,
+    pub deadline: Duration,
+    pub finished: Duration,
+}
+
+impl DeadlineMiss {
+    pub fn late_by(&self) -> Duration {
+        self.finished - self.deadline
+    }
+}
+
+impl fmt::Display for DeadlineMiss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "task {} missed its deadline at {:?} by {:?}",
+            self.task,
+            self.deadline,
+            self.late_by()
+        )
+    }
+}
+
+/// The default handler. A missed deadline is counted in `Runtime::stats` and that's it.
+pub(crate) fn ignore_miss(_miss: &DeadlineMiss) {}
+
+impl Runtime {
+    /// Same as `spawn` but the task should be done within `deadline` from now. If it isn't, the handler set
+    /// with `on_deadline_missed` is called when it finishes.
+    pub fn spawn_with_deadline(&mut self, f: fn(), deadline: Duration) -> JoinHandle {
+        let deadline = self.t_now() + deadline;
+        self.t_spawn_with(f, |task| task.deadline = Some(deadline))
+    }
+
+    /// Sets the function that gets called when a task finishes after its deadline. The default does nothing,
+    /// but the miss still shows up in `Stats::deadlines_missed`.
+    pub fn on_deadline_missed(&mut self, handler: fn(&DeadlineMiss)) {
+        self.deadline_handler = handler;
+    }
+
+    /// Called when task `id` returns.
+    pub(crate) fn t_check_deadline(&mut self, id: usize) {
+        let deadline = match self.tasks[id].deadline {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        let finished = self.t_now();
+        if finished > deadline {
+            self.deadlines_missed += 1;
+            let miss = DeadlineMiss {
+                task: id,
+                deadline,
+                finished,
+            };
+            (self.deadline_handler)(&miss);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Since all our tasks share one OS thread, a cycle of tasks waiting for each other doesn't crash
+//! anything, the program just stops doing anything. To make that easier to debug we keep track of
+//! what every parked task waits for (a "wait-for graph") and look for cycles whenever there's no task
+//! left to run.
+use crate::{Runtime, State};
+use std::fmt;
+
+/// What a parked task is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitsFor {
+    /// A `sync::Mutex` (identified by its address) held by another task.
+    Mutex { address: usize, owner: usize },
+    /// Another task to finish in `JoinHandle::join`.
+    Join { task: usize },
+}
+
+impl WaitsFor {
+    // the task that has to do something before we can continue
+    fn task(&self) -> usize {
+        match *self {
+            WaitsFor::Mutex { owner, .. } => owner,
+            WaitsFor::Join { task } => task,
+        }
+    }
+}
+
+/// A cycle of tasks waiting for each other. Each entry is a task and what it waits for, and the task
+/// that resource depends on is the next entry (the last one waits for the first one).
+#[derive(Debug, Clone)]
+pub struct Deadlock {
+    pub cycle: Vec<(usize, WaitsFor)>,
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "deadlock detected:")?;
+        for (task, waits_for) in &self.cycle {
+            match waits_for {
+                WaitsFor::Mutex { address, owner } => write!(
+                    f,
+                    "\n  task {} waits for mutex {:#x} held by task {}",
+                    task, address, owner
+                )?,
+                WaitsFor::Join { task: other } => {
+                    write!(f, "\n  task {} waits for task {} to finish", task, other)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The default deadlock handler. There's no way to recover from a deadlock so we might as well stop.
+pub(crate) fn panic_on_deadlock(deadlock: &Deadlock) {
+    panic!("{}", deadlock);
+}
+
+impl Runtime {
+    /// Sets the function that gets called when we find a deadlock. The default panics with a description
+    /// of the cycle.
+    pub fn on_deadlock(&mut self, handler: fn(&Deadlock)) {
+        self.deadlock_handler = handler;
+    }
+
+    /// Returns what the given task waits for, if it's parked waiting for something we know about.
+    fn t_waits_for(&self, id: usize) -> Option<WaitsFor> {
+        let task = &self.tasks[id];
+        if task.state != State::Parked {
+            return None;
+        }
+        if let Some(lock) = task.blocked_on {
+            let owner = unsafe { (*lock).owner() };
+            return Some(WaitsFor::Mutex {
+                address: lock as usize,
+                owner,
+            });
+        }
+        task.joining.map(|task| WaitsFor::Join { task })
+    }
+
+    /// Looks for a cycle in the wait-for graph and calls the deadlock handler if we find one.
+    ///
+    /// A task can only wait for one thing at a time, so every task has at most one edge going out of it.
+    /// That means we don't need anything fancy: we follow the edges from every parked task, and if we get
+    /// back to where we started within as many steps as there are tasks, we've found a cycle.
+    pub(crate) fn t_check_deadlock(&self) {
+        for start in 0..self.tasks.len() {
+            let mut cycle = vec![];
+            let mut id = start;
+            for _ in 0..self.tasks.len() {
+                let waits_for = match self.t_waits_for(id) {
+                    Some(waits_for) => waits_for,
+                    None => break,
+                };
+                cycle.push((id, waits_for));
+                id = waits_for.task();
+                if id == start {
+                    (self.deadlock_handler)(&Deadlock { cycle });
+                    return;
+                }
+            }
+        }
+    }
+}
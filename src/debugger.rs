@@ -0,0 +1,126 @@
+//! Lets a debugger find the tasks that aren't running. When you stop a program in gdb it only knows about the
+//! stacks of OS threads, so a task that has yielded is invisible: its registers are in a `TaskContext` and its
+//! stack is just a `Vec<u8>` somewhere on the heap.
+//!
+//! Every runtime registers one `TaskDescriptor` per task in a linked list that starts at the symbol
+//! `GREEN_THREADS_TASKS`. A descriptor has the task's name, the bounds of its stack and pointers to where
+//! `switch` saved its stack pointer, frame pointer and return address, so it never has to be updated when we
+//! switch tasks, only when a task is spawned or freed. `gdb/green_threads.py` reads the list: `info coroutines`
+//! lists the tasks and `coroutine N` points gdb's registers at the saved ones so `bt` shows that task's stack.
+use crate::{arch, State, Task};
+use std::ptr;
+use std::sync::Mutex;
+
+/// Everything a debugger needs to know about one task. It's `#[repr(C)]` and every field is pointer sized, so
+/// the gdb script can read it as ten `u64` without knowing anything about how Rust lays out a struct.
+#[repr(C)]
+pub(crate) struct TaskDescriptor {
+    next: *const TaskDescriptor,
+    id: usize,
+    // 0 if the task slot is available
+    alive: usize,
+    name: *const u8,
+    name_len: usize,
+    stack_low: usize,
+    stack_high: usize,
+    // see `arch::saved_registers`, a null `pc` means the return address is at the top of the stack
+    sp: *const u64,
+    fp: *const u64,
+    pc: *const u64,
+}
+
+/// The first descriptor of the list. Only changed while holding `LIST`, and only read by a debugger while the
+/// program is stopped.
+#[no_mangle]
+pub(crate) static mut GREEN_THREADS_TASKS: *const TaskDescriptor = ptr::null();
+
+// every runtime on every OS thread adds itself to the same list
+static LIST: Mutex<()> = Mutex::new(());
+
+/// The descriptors of one runtime. They're linked into the list when it's created and unlinked when it's
+/// dropped, so the list never points to a runtime that's gone.
+pub(crate) struct Registration {
+    // a boxed slice we only touch through raw pointers, since other runtimes change `next` of our last
+    // descriptor when they unlink themselves
+    descriptors: *mut TaskDescriptor,
+    len: usize,
+}
+
+impl Registration {
+    /// Registers `tasks`. They must not move after this, which holds for the tasks of a runtime since we never
+    /// grow the `Vec` they're in.
+    pub(crate) fn new(tasks: &[Task]) -> Self {
+        let descriptors: Box<[TaskDescriptor]> = tasks
+            .iter()
+            .map(|t| {
+                let [sp, fp, pc] = arch::saved_registers(&t.ctx);
+                TaskDescriptor {
+                    next: ptr::null(),
+                    id: t.id,
+                    alive: 0,
+                    name: ptr::null(),
+                    name_len: 0,
+                    stack_low: 0,
+                    stack_high: 0,
+                    sp,
+                    fp,
+                    pc,
+                }
+            })
+            .collect();
+        let len = descriptors.len();
+        let descriptors = Box::into_raw(descriptors) as *mut TaskDescriptor;
+        let mut registration = Registration { descriptors, len };
+        for t in tasks {
+            registration.update(t);
+        }
+
+        let _list = LIST.lock().unwrap();
+        unsafe {
+            for i in 0..len - 1 {
+                (*descriptors.add(i)).next = descriptors.add(i + 1);
+            }
+            (*descriptors.add(len - 1)).next = GREEN_THREADS_TASKS;
+            GREEN_THREADS_TASKS = descriptors;
+        }
+        registration
+    }
+
+    /// Copies the name, stack bounds and whether it's alive from `task` to its descriptor. Called whenever
+    /// one of them changes.
+    pub(crate) fn update(&mut self, task: &Task) {
+        let name = task.name.as_deref().unwrap_or("");
+        let stack = match task.stack.is_empty() {
+            true => 0..0,
+            false => {
+                let range = task.stack.as_ptr_range();
+                range.start as usize..range.end as usize
+            }
+        };
+        // field by field, `next` isn't ours to touch without the lock
+        unsafe {
+            let d = self.descriptors.add(task.id);
+            (*d).alive = (task.state != State::Available) as usize;
+            (*d).name = name.as_ptr();
+            (*d).name_len = name.len();
+            (*d).stack_low = stack.start;
+            (*d).stack_high = stack.end;
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _list = LIST.lock().unwrap();
+        unsafe {
+            let last = self.descriptors.add(self.len - 1);
+            // find whoever points to our first descriptor and point them past our last one
+            let mut link: *mut *const TaskDescriptor = ptr::addr_of_mut!(GREEN_THREADS_TASKS);
+            while !ptr::eq(*link, self.descriptors) {
+                link = ptr::addr_of_mut!((*(*link as *mut TaskDescriptor)).next);
+            }
+            *link = (*last).next;
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(self.descriptors, self.len)));
+        }
+    }
+}
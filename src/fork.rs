@@ -0,0 +1,178 @@
+//! `fork()` for tasks, to show what it takes to duplicate a running thread of execution. `fork_current` gives
+//! the calling task a twin: a new task with a copy of its stack and its registers, which starts running right
+//! where the original called `fork_current`. Both return from it, the original (the parent) with
+//! `Fork::Parent` and the copy (the child) with `Fork::Child`.
+//!
+//! The hard part is that a stack is full of pointers into itself: saved frame pointers, references to locals,
+//! the stack pointer itself. The child's stack lives somewhere else, so every one of them has to be moved by
+//! the distance between the two stacks. We don't know which words are pointers, so we guess: every word on
+//! the copied stack and in the saved registers that holds an address inside the parent's stack is moved. An
+//! integer that happens to look like such an address gets moved too, that's the price of guessing.
+//!
+//! Only the stack is copied, not the heap. That's where this differs the most from a real `fork()`, which
+//! copies the whole address space, and it's why `fork_current` is `unsafe` and there are rules:
+//!
+//! - Anything that owns heap memory and lives on the stack when we fork (a `Vec`, a `String`, a `Box`...) is
+//!   owned by both tasks afterwards, and the second one to drop it frees it twice. Fork before creating such
+//!   values, or make sure one of the two `mem::forget`s them.
+//! - The same goes for anything else that's dropped: a `sync::MutexGuard` held across `fork_current` is
+//!   unlocked twice, a `JoinHandle` is joined (or detached) twice.
+//! - The base task runs on the stack of the OS thread, which we can't copy, so it can't fork.
+use crate::arch::{self, TaskContext};
+use crate::{JoinHandle, Runtime, RUNTIME};
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::mem;
+use std::ptr;
+
+pub enum Fork {
+    /// We're the task that called `fork_current`, and this is the handle of the copy.
+    Parent(JoinHandle),
+    /// We're the copy.
+    Child,
+}
+
+// The child never runs this, we only need a `fn()` to spawn it with. It starts where its parent forked.
+fn forked() {}
+
+// The parent's stack mustn't change while we copy it, not even the part right below its stack pointer (on x86_64
+// that's where the address `switch` returns to is), so we copy it from a small stack of our own. These are the
+// parent and the child for `copy_stack` to pick up.
+thread_local! {
+    static FORKING: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+const HELPER_STACK_SIZE: usize = 64 * 1024;
+
+fn copy_stack() {
+    let (parent, child) = FORKING.with(|forking| forking.get());
+    unsafe {
+        let runtime = &mut *(RUNTIME as *mut Runtime);
+        // where the parent carries on, before we move it over to the child's stack
+        let resume = ptr::read(&runtime.tasks[child].ctx);
+        runtime.t_copy_stack(parent, child);
+        let mut done = TaskContext::default();
+        arch::switch(&mut done, &resume);
+    }
+}
+
+// `copy_stack` never returns, it switches back to the parent instead
+fn copied() {
+    unreachable!("the stack we fork on returned.");
+}
+
+impl Runtime {
+    /// Duplicates the current task, see the module documentation for what that means. Panics when called from the
+    /// base task.
+    ///
+    /// # Safety
+    ///
+    /// Only the stack is copied, so everything on it when we fork, in this function's callers as well, is there
+    /// twice afterwards and both tasks go on using it. The caller has to make sure that's fine:
+    ///
+    /// - Nothing on the stack owns heap memory or anything else its `Drop` gives back (a `Vec`, a `String`, a
+    ///   `Box`, an `Rc`...), unless one of the two tasks `mem::forget`s it. It would be freed twice.
+    /// - No `sync::MutexGuard` or `JoinHandle` is alive across the call, it would be unlocked or joined twice.
+    /// - Nothing on the stack relies on a word that looks like an address inside the stack keeping its value in
+    ///   the child. We move every such word to the child's stack, pointer or not.
+    pub unsafe fn fork_current(&mut self) -> Fork {
+        if cfg!(feature = "sim") {
+            panic!("the sim backend doesn't run tasks on their stacks, there's nothing to fork.");
+        }
+        let parent = self.current;
+        assert_ne!(
+            parent, 0,
+            "the base task runs on the stack of the OS thread, it can't fork."
+        );
+
+        let (priority, name) = (self.tasks[parent].priority, self.tasks[parent].name.clone());
+        let handle = self.t_spawn_with(forked, |task| {
+            task.priority = priority;
+            task.effective = priority;
+            task.name = name;
+        });
+        if handle.id() == usize::MAX {
+            // the overload handler took it, nobody is going to run a child
+            return Fork::Parent(handle);
+        }
+        let child = handle.id();
+
+        let mut helper = TaskContext::default();
+        let mut helper_stack = vec![0_u8; HELPER_STACK_SIZE];
+        FORKING.with(|forking| forking.set((parent, child)));
+        // We save our registers in the child's context and copy the stack. The child starts right here when it's
+        // scheduled the first time, and we do as soon as the copy is done.
+        unsafe {
+            arch::init_task(&mut helper, &mut helper_stack, copy_stack, copied);
+            let ctx: *mut TaskContext = &mut self.tasks[child].ctx;
+            arch::switch(ctx, &helper);
+        }
+
+        // `switch` doesn't tell the compiler it returns twice, so make sure it doesn't reuse what it read about
+        // `self.current` before.
+        if unsafe { ptr::read_volatile(&self.current) } != parent {
+            // The parent owns these, our copies of them mustn't detach the child (which is us) or free the stack.
+            mem::forget(handle);
+            mem::forget(helper_stack);
+            return Fork::Child;
+        }
+        Fork::Parent(handle)
+    }
+
+    /// Copies the part of the parent's stack that's in use (everything above the stack pointer we saved in the
+    /// child's context) to the same place on the child's stack, and moves the pointers into it.
+    fn t_copy_stack(&mut self, parent: usize, child: usize) {
+        let (from_low, from_high) = bounds(&self.tasks[parent].stack);
+        let (to_low, _) = bounds(&self.tasks[child].stack);
+        assert_eq!(
+            self.tasks[parent].stack.len(),
+            self.tasks[child].stack.len(),
+            "a child needs a stack just as big as its parent's."
+        );
+        let sp = arch::stack_pointer(&self.tasks[child].ctx);
+        let (start, len) = (sp - from_low, from_high - sp);
+        let src = self.tasks[parent].stack[start..].as_ptr();
+        let dst = self.tasks[child].stack[start..].as_mut_ptr();
+        unsafe { ptr::copy_nonoverlapping(src, dst, len) };
+
+        relocate_stack(&mut self.tasks[child].stack[start..], (from_low, from_high), to_low);
+        relocate_context(&mut self.tasks[child].ctx, (from_low, from_high), to_low);
+    }
+}
+
+/// Moves every word in `stack` that points into the stack from `from.0` to `from.1` to the same place on a
+/// stack that starts at `to_low`. `from.1` is included: a pointer to the end of a stack is still about that
+/// stack. `stack` has to start at a word boundary, like a stack pointer.
+pub(crate) fn relocate_stack(stack: &mut [u8], from: (usize, usize), to_low: usize) {
+    for word in stack.chunks_exact_mut(8) {
+        let value = u64::from_ne_bytes(word.try_into().unwrap()) as usize;
+        if from.0 <= value && value <= from.1 {
+            word.copy_from_slice(&((value - from.0 + to_low) as u64).to_ne_bytes());
+        }
+    }
+}
+
+/// Moves the saved registers that point into the stack from `from` to a stack starting at `to_low`, see
+/// `relocate_stack`.
+pub(crate) fn relocate_context(ctx: &mut TaskContext, from: (usize, usize), to_low: usize) {
+    for word in ctx.words_mut() {
+        let mut bytes = word.to_ne_bytes();
+        relocate_stack(&mut bytes, from, to_low);
+        *word = u64::from_ne_bytes(bytes);
+    }
+}
+
+pub(crate) fn bounds(stack: &[u8]) -> (usize, usize) {
+    let low = stack.as_ptr() as usize;
+    (low, low + stack.len())
+}
+
+/// `Runtime::fork_current` on the runtime running the current task.
+///
+/// # Safety
+///
+/// The same as for `Runtime::fork_current`.
+pub unsafe fn fork_current() -> Fork {
+    let rt_ptr = RUNTIME as *mut Runtime;
+    (*rt_ptr).fork_current()
+}
@@ -0,0 +1,133 @@
+//! Cooperative file I/O. Reading a regular file never returns `WouldBlock` (as far as epoll is concerned
+//! a file is always ready), but it can still block for a long time while the disk does its thing, and while
+//! it does no other task gets to run.
+//!
+//! So we don't do file I/O on the runtime's thread at all. With the `io-uring` feature (and a kernel that
+//! supports it) reads and writes go to the kernel through io_uring and the task is parked until they're done.
+//! Without it, they're sent to the blocking pool just like `spawn_blocking` does. Opening files always goes
+//! through the blocking pool.
+use crate::spawn_blocking;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring;
+use std::fs;
+use std::io::{self, Read, Write};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Reads the whole file at `path`.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    if use_uring() {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+        return Ok(data);
+    }
+    let path = path.as_ref().to_owned();
+    spawn_blocking(move || fs::read(path))
+}
+
+/// Reads the whole file at `path` into a string.
+pub fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    String::from_utf8(read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `contents` to the file at `path`, replacing anything that's already there.
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    if use_uring() {
+        return File::create(path)?.write_all(contents.as_ref());
+    }
+    let path = path.as_ref().to_owned();
+    let contents = contents.as_ref().to_vec();
+    spawn_blocking(move || fs::write(path, contents))
+}
+
+/// A file that parks the current task instead of blocking the OS thread.
+///
+/// Jobs we send to the blocking pool have to be `'static` so they can't borrow the file, which is why we
+/// keep it in an `Arc`.
+pub struct File {
+    inner: Arc<fs::File>,
+}
+
+impl File {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        File::from_std(spawn_blocking(move || fs::File::open(path))?)
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        File::from_std(spawn_blocking(move || fs::File::create(path))?)
+    }
+
+    fn from_std(file: fs::File) -> io::Result<File> {
+        Ok(File {
+            inner: Arc::new(file),
+        })
+    }
+
+    /// Makes sure everything we've written has reached the disk.
+    pub fn sync_all(&self) -> io::Result<()> {
+        let file = self.inner.clone();
+        spawn_blocking(move || file.sync_all())
+    }
+
+    pub fn metadata(&self) -> io::Result<fs::Metadata> {
+        let file = self.inner.clone();
+        spawn_blocking(move || file.metadata())
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            if use_uring() {
+                return uring::read(self.inner.as_raw_fd(), buf, uring::CURRENT_POSITION);
+            }
+        }
+        // the pool thread can't borrow `buf`, so it reads into its own buffer which we copy
+        let file = self.inner.clone();
+        let len = buf.len();
+        let data = spawn_blocking(move || {
+            let mut data = vec![0; len];
+            let n = (&*file).read(&mut data)?;
+            data.truncate(n);
+            Ok::<_, io::Error>(data)
+        })?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            if use_uring() {
+                return uring::write(self.inner.as_raw_fd(), buf, uring::CURRENT_POSITION);
+            }
+        }
+        let file = self.inner.clone();
+        let data = buf.to_vec();
+        spawn_blocking(move || (&*file).write(&data))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // we don't buffer anything and neither does `std::fs::File`
+        Ok(())
+    }
+}
+
+// true if file reads and writes go through io_uring
+fn use_uring() -> bool {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        uring::available()
+    }
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    {
+        false
+    }
+}
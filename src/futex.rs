@@ -0,0 +1,94 @@
+//! Futex-style waiting on an address, the building block for writing your own locks.
+//!
+//! `wait_on(atomic, expected)` parks the current task, but only if the atomic still holds `expected`. That
+//! check is what makes it work: a lock that sees it's taken can go to sleep without a wakeup getting lost in
+//! between, because if the owner unlocked in the meantime the value has changed and we don't wait at all.
+//! Since all tasks of a runtime share one OS thread nobody can change the value between our check and us
+//! parking, which is the part the kernel has to work hard for with a real futex.
+//!
+//! `wake_one` and `wake_all` wake tasks waiting on the same address. They only know about tasks on the current
+//! runtime, so an atomic shared between runtimes needs another way to wake the other side.
+use crate::{Runtime, RUNTIME};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The tasks waiting on every address someone waits on, in the order they started waiting.
+#[derive(Default)]
+pub(crate) struct Futexes {
+    waiters: HashMap<usize, VecDeque<usize>>,
+}
+
+impl Futexes {
+    fn is_waiting(&self, address: usize, id: usize) -> bool {
+        matches!(self.waiters.get(&address), Some(waiters) if waiters.contains(&id))
+    }
+
+    fn take(&mut self, address: usize, count: usize) -> Vec<usize> {
+        let waiters = match self.waiters.get_mut(&address) {
+            Some(waiters) => waiters,
+            None => return vec![],
+        };
+        let woken: Vec<usize> = waiters.drain(..count.min(waiters.len())).collect();
+        if waiters.is_empty() {
+            self.waiters.remove(&address);
+        }
+        woken
+    }
+
+    /// Forgets that task `id` waits, used when a task is cancelled.
+    pub(crate) fn forget(&mut self, id: usize) {
+        self.waiters.retain(|_, waiters| {
+            waiters.retain(|&w| w != id);
+            !waiters.is_empty()
+        });
+    }
+}
+
+impl Runtime {
+    fn t_futex_wait(&mut self, atomic: &AtomicU32, expected: u32) -> bool {
+        if atomic.load(Ordering::SeqCst) != expected {
+            return false;
+        }
+        let address = atomic as *const AtomicU32 as usize;
+        let me = self.current;
+        self.futexes.waiters.entry(address).or_default().push_back(me);
+        // `t_futex_wake` removes us before it unparks us, anything else is a spurious wakeup
+        while self.futexes.is_waiting(address, me) {
+            self.t_park();
+        }
+        true
+    }
+
+    fn t_futex_wake(&mut self, atomic: &AtomicU32, count: usize) -> usize {
+        let woken = self.futexes.take(atomic as *const AtomicU32 as usize, count);
+        for &id in &woken {
+            self.t_unpark(id);
+        }
+        woken.len()
+    }
+}
+
+/// Parks the current task until it's woken by `wake_one` or `wake_all` on the same atomic, unless the atomic
+/// doesn't hold `expected` anymore. Returns false if we didn't wait because of that.
+pub fn wait_on(atomic: &AtomicU32, expected: u32) -> bool {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_futex_wait(atomic, expected)
+    }
+}
+
+/// Wakes the task that has waited on `atomic` the longest. Returns how many tasks we woke (0 or 1).
+pub fn wake_one(atomic: &AtomicU32) -> usize {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_futex_wake(atomic, 1)
+    }
+}
+
+/// Wakes every task waiting on `atomic`. Returns how many tasks we woke.
+pub fn wake_all(atomic: &AtomicU32) -> usize {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_futex_wake(atomic, usize::MAX)
+    }
+}
@@ -0,0 +1,276 @@
+//! Stackful generators. A generator runs a function on its own stack, and every time that function calls
+//! `Yielder::yield_` we switch back to whoever resumed the generator and hand them the value. Resuming it again
+//! switches back to the generator's stack right where it left off. It's the same context switch our tasks use,
+//! only it's the caller and not the scheduler that decides when the generator runs.
+//!
+//! Since the function has a stack of its own it can yield from anywhere, even from deep inside a recursive
+//! call, which is the big difference from the stackless generators the compiler builds for `async fn`.
+//!
+//! Generators are lazy: nothing runs until someone asks for a value, and a generator that yielded waits until
+//! the next one is asked for. The combinators (`map`, `filter`, `chain`, `zip`) are generators themselves that
+//! resume the ones they were built from, so a pipeline only ever produces what the consumer at the end takes,
+//! which is all the backpressure we need.
+//!
+//! Values can go the other way too, like with Python's `send`: a generator built with `Generator::two_way` is
+//! resumed with `send(value)`, and the `Yielder::yield_with` it's suspended in returns that value. That turns a
+//! generator into a state machine that keeps its state in local variables and where it is in its code, see
+//! `examples/parser.rs`.
+//!
+//! A generator doesn't need a `Runtime`, but it works in a task too, and its function can yield to the
+//! scheduler like any other code running in that task.
+use crate::arch::{self, switch, TaskContext};
+use crate::stack;
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+// Generators usually do a lot less than a task, so they get a smaller stack by default
+const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Suspended,
+    Running,
+    Done,
+}
+
+type Body<T, R> = Box<dyn FnOnce(R, &Yielder<T, R>)>;
+// the monomorphized `run` for the generator and a pointer to its `Inner`
+type Start = (unsafe fn(*mut u8), *mut u8);
+
+struct Inner<T, R> {
+    ctx: TaskContext,
+    // where we switch back to when the generator yields or returns
+    caller: TaskContext,
+    stack: Vec<u8>,
+    body: Option<Body<T, R>>,
+    state: State,
+    value: Option<T>,
+    // what `send` hands to the function, as its argument the first time and from `yield_with` after that
+    sent: Option<R>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// A generator yielding values of type `T`, and resumed with values of type `R` (see `two_way`). If you drop it
+/// before its function has returned, the function never finishes and nothing on its stack is dropped, the same as
+/// a task cancelled by `Runtime::run_until`.
+pub struct Generator<T, R = ()> {
+    // boxed so the contexts don't move while we're switching, and so `Yielder` can point to it
+    inner: Box<Inner<T, R>>,
+}
+
+/// Passed to the generator's function, used to hand values to whoever resumed the generator.
+pub struct Yielder<T, R = ()> {
+    inner: *mut Inner<T, R>,
+}
+
+impl<T, R> Yielder<T, R> {
+    /// Hands `value` to the caller of `resume` and suspends the generator until it's resumed again. In a
+    /// `two_way` generator the value it's resumed with is dropped.
+    pub fn yield_(&self, value: T) {
+        self.yield_with(value);
+    }
+
+    /// Same as `yield_`, but returns the value the generator is resumed with, see `Generator::send`.
+    pub fn yield_with(&self, value: T) -> R {
+        unsafe {
+            (*self.inner).value = Some(value);
+            (*self.inner).state = State::Suspended;
+            switch(&mut (*self.inner).ctx, &(*self.inner).caller);
+            (*self.inner).sent.take().expect("a generator resumed without a value.")
+        }
+    }
+}
+
+// `init_task` can only start a `fn()`, so we leave the generator we're starting here for `entry` to pick up.
+// It's only ever set right before we switch to a new generator, which reads it right away.
+thread_local! {
+    static STARTING: Cell<Start> = const { Cell::new((not_started, ptr::null_mut())) };
+}
+
+unsafe fn not_started(_: *mut u8) {
+    unreachable!("a generator started without being resumed.");
+}
+
+fn entry() {
+    let (run, inner) = STARTING.with(|s| s.get());
+    unsafe { run(inner) };
+}
+
+// `run` never returns, it switches back to the caller for the last time instead
+fn returned() {
+    unreachable!("a generator returned from its stack.");
+}
+
+/// Runs the generator's function on its own stack. A panic can't unwind past the start of the stack, so we
+/// catch it here and `resume` rethrows it on the caller's stack.
+unsafe fn run<T, R>(inner: *mut u8) {
+    let inner = inner as *mut Inner<T, R>;
+    let body = (*inner).body.take().unwrap();
+    let first = (*inner).sent.take().unwrap();
+    let yielder = Yielder { inner };
+    if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| body(first, &yielder))) {
+        (*inner).panic = Some(e);
+    }
+    (*inner).state = State::Done;
+    switch(&mut (*inner).ctx, &(*inner).caller);
+}
+
+impl<T: 'static, R: 'static> Generator<T, R> {
+    /// A generator that's resumed with `send`. The first `send` starts `f` and passes its value as `f`'s
+    /// argument, every `send` after that passes its value to `f` as what `Yielder::yield_with` returns.
+    pub fn two_way<F>(f: F) -> Self
+    where
+        F: FnOnce(R, &Yielder<T, R>) + 'static,
+    {
+        Generator::two_way_with_stack_size(DEFAULT_STACK_SIZE, f)
+    }
+
+    /// Same as `two_way` but with a stack of `size` bytes instead of the default 64 KiB.
+    pub fn two_way_with_stack_size<F>(size: usize, f: F) -> Self
+    where
+        F: FnOnce(R, &Yielder<T, R>) + 'static,
+    {
+        if cfg!(feature = "sim") {
+            panic!("the sim backend doesn't run anything on the stacks we give it, there's no generator to run.");
+        }
+        let mut inner = Box::new(Inner {
+            ctx: TaskContext::default(),
+            caller: TaskContext::default(),
+            stack: vec![0_u8; size],
+            body: Some(Box::new(f)),
+            state: State::Suspended,
+            value: None,
+            sent: None,
+            panic: None,
+        });
+        stack::write_canary(&mut inner.stack);
+        unsafe {
+            arch::init_task(&mut inner.ctx, &mut inner.stack, entry, returned);
+        }
+        // we don't know if either side uses floating point
+        arch::set_save_fp(&mut inner.ctx, true);
+        arch::set_save_fp(&mut inner.caller, true);
+        Generator { inner }
+    }
+
+    /// Passes `value` to the generator and runs it until it yields the next value, see `two_way`. Returns
+    /// `None` (and drops `value`) once its function has returned. If the function panicked, the panic
+    /// continues here.
+    pub fn send(&mut self, value: R) -> Option<T> {
+        match self.inner.state {
+            State::Done => return None,
+            State::Running => panic!("a generator can't resume itself."),
+            State::Suspended => {}
+        }
+
+        if self.inner.body.is_some() {
+            let inner: *mut Inner<T, R> = &mut *self.inner;
+            STARTING.with(|s| s.set((run::<T, R>, inner as *mut u8)));
+        }
+        self.inner.sent = Some(value);
+        self.inner.state = State::Running;
+        unsafe {
+            let inner: *mut Inner<T, R> = &mut *self.inner;
+            switch(&mut (*inner).caller, &(*inner).ctx);
+        }
+
+        if !stack::canary_intact(&self.inner.stack) {
+            panic!("stack overflow detected: a generator overwrote its stack canary.");
+        }
+        if let Some(e) = self.inner.panic.take() {
+            panic::resume_unwind(e);
+        }
+        self.inner.value.take()
+    }
+
+    /// Returns true once the generator's function has returned.
+    pub fn is_done(&self) -> bool {
+        self.inner.state == State::Done
+    }
+}
+
+impl<T: 'static> Generator<T> {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: FnOnce(&Yielder<T>) + 'static,
+    {
+        Generator::with_stack_size(DEFAULT_STACK_SIZE, f)
+    }
+
+    /// Same as `new` but with a stack of `size` bytes instead of the default 64 KiB.
+    pub fn with_stack_size<F>(size: usize, f: F) -> Self
+    where
+        F: FnOnce(&Yielder<T>) + 'static,
+    {
+        Generator::two_way_with_stack_size(size, move |(), y| f(y))
+    }
+
+    /// Runs the generator until it yields the next value. Returns `None` once its function has returned. If
+    /// the function panicked, the panic continues here.
+    pub fn resume(&mut self) -> Option<T> {
+        self.send(())
+    }
+
+    /// A generator that yields `f(value)` for every value this one yields.
+    pub fn map<U, F>(mut self, mut f: F) -> Generator<U>
+    where
+        U: 'static,
+        F: FnMut(T) -> U + 'static,
+    {
+        Generator::new(move |y| {
+            while let Some(value) = self.resume() {
+                y.yield_(f(value));
+            }
+        })
+    }
+
+    /// A generator that only yields the values of this one that `keep` returns true for.
+    pub fn filter<F>(mut self, mut keep: F) -> Generator<T>
+    where
+        F: FnMut(&T) -> bool + 'static,
+    {
+        Generator::new(move |y| {
+            while let Some(value) = self.resume() {
+                if keep(&value) {
+                    y.yield_(value);
+                }
+            }
+        })
+    }
+
+    /// A generator that yields everything this one yields and then everything `next` yields.
+    pub fn chain(mut self, mut next: Generator<T>) -> Generator<T> {
+        Generator::new(move |y| {
+            while let Some(value) = self.resume() {
+                y.yield_(value);
+            }
+            while let Some(value) = next.resume() {
+                y.yield_(value);
+            }
+        })
+    }
+
+    /// A generator that yields pairs of values from this one and `other` until one of them is done.
+    pub fn zip<U: 'static>(mut self, mut other: Generator<U>) -> Generator<(T, U)> {
+        Generator::new(move |y| {
+            while let Some(a) = self.resume() {
+                match other.resume() {
+                    Some(b) => y.yield_((a, b)),
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+/// Lets a `for` loop consume a generator. Careful with the `Iterator` adapters, they're not generators: calling
+/// `map` on a generator uses ours, but `gen.take(3).map(..)` is `Iterator::map`.
+impl<T: 'static> Iterator for Generator<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.resume()
+    }
+}
@@ -0,0 +1,254 @@
+//! Task groups, so a bunch of tasks can be cancelled or waited for together. A server puts every connection in a
+//! group of its own, and whatever helpers the connection's task spawns end up in it too: a task spawned by a
+//! member of a group is a member as well. Closing the connection is cancelling the group, and none of the
+//! helpers are left behind.
+//!
+//! Groups nest. A group created by a member of another group is part of that group, so cancelling or waiting
+//! for the outer group covers the inner one too.
+//!
+//! Cancelling works like `OverBudget::Cancel`: a member is cancelled the next time it runs, at a point where it
+//! doesn't hold or wait for a `sync::Mutex`, and nothing on its stack is dropped. Members that are parked get
+//! woken up so they get there, and members that never ran don't run at all.
+use crate::{JoinHandle, Runtime, RUNTIME};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+#[derive(Default)]
+pub(crate) struct Groups {
+    groups: HashMap<usize, Group>,
+    next_id: usize,
+    // the group `TaskGroup::spawn` spawns into, see `t_spawn_entry`
+    pub(crate) spawning_into: Option<usize>,
+}
+
+struct Group {
+    // the group of the task that created this one
+    parent: Option<usize>,
+    // false once the `TaskGroup` is dropped, we forget about the group when nothing is left in it
+    open: bool,
+    // tasks in this group that haven't finished, and groups inside it we haven't forgotten about
+    members: usize,
+    children: usize,
+    // tasks in `TaskGroup::wait`
+    waiters: Vec<usize>,
+}
+
+impl Groups {
+    /// Forgets `id` if nothing needs it anymore, and then its parent if that was the last thing it needed.
+    fn release(&mut self, id: usize) {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let group = &self.groups[&id];
+            if group.open || group.members > 0 || group.children > 0 {
+                return;
+            }
+            next = self.groups.remove(&id).unwrap().parent;
+            if let Some(parent) = next {
+                self.groups.get_mut(&parent).unwrap().children -= 1;
+            }
+        }
+    }
+
+    /// True if `group` is `ancestor` or inside it.
+    fn is_within(&self, group: Option<usize>, ancestor: usize) -> bool {
+        let mut next = group;
+        while let Some(id) = next {
+            if id == ancestor {
+                return true;
+            }
+            next = self.groups.get(&id).and_then(|g| g.parent);
+        }
+        false
+    }
+}
+
+/// A group of tasks that are cancelled together. Dropping it cancels whatever is still running in it, call
+/// `wait` first to let them finish. A cancelled task keeps its slot until it runs one last time, so call `cancel`
+/// and then `wait` if you need the slots back right away.
+pub struct TaskGroup {
+    id: usize,
+    // like a `JoinHandle` it talks to the runtime on the current OS thread
+    _not_send: PhantomData<*const ()>,
+}
+
+impl TaskGroup {
+    /// Creates an empty group on the current runtime. If the current task is in a group, the new group is inside
+    /// that one.
+    pub fn new() -> Self {
+        let id = unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_new_group()
+        };
+        TaskGroup {
+            id,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Spawns `f` as a member of the group, see `Runtime::spawn`.
+    pub fn spawn(&self, f: fn()) -> JoinHandle {
+        self.spawn_into(|rt| rt.spawn(f))
+    }
+
+    /// Spawns `f(arg)` as a member of the group, see `Runtime::spawn_arg`.
+    pub fn spawn_arg(&self, f: fn(usize), arg: usize) -> JoinHandle {
+        self.spawn_into(|rt| rt.spawn_arg(f, arg))
+    }
+
+    /// Spawns `f(value)` as a member of the group, see `Runtime::spawn_with`.
+    pub fn spawn_with<T: Send + 'static>(&self, f: fn(T), value: T) -> JoinHandle {
+        self.spawn_into(|rt| rt.spawn_with(f, value))
+    }
+
+    fn spawn_into(&self, spawn: impl FnOnce(&mut Runtime) -> JoinHandle) -> JoinHandle {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).groups.spawning_into = Some(self.id);
+            spawn(&mut *rt_ptr)
+        }
+    }
+
+    /// Cancels every task in the group and in the groups inside it. If the current task is one of them, this
+    /// doesn't return (unless it holds a `sync::Mutex`, then it's cancelled once it lets go and yields).
+    pub fn cancel(&self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_cancel_group(self.id);
+        }
+    }
+
+    /// Parks the current task until every task in the group, and in the groups inside it, has finished or been
+    /// cancelled. The current task doesn't count if it's a member itself.
+    pub fn wait(&self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_wait_group(self.id);
+        }
+    }
+
+    /// The number of tasks in the group (and the groups inside it) that haven't finished yet.
+    pub fn alive_count(&self) -> usize {
+        unsafe {
+            let rt_ptr = RUNTIME as *const Runtime;
+            (*rt_ptr).t_group_members(self.id).count()
+        }
+    }
+}
+
+impl Default for TaskGroup {
+    fn default() -> Self {
+        TaskGroup::new()
+    }
+}
+
+impl Drop for TaskGroup {
+    fn drop(&mut self) {
+        self.cancel();
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_close_group(self.id);
+        }
+    }
+}
+
+impl Runtime {
+    fn t_new_group(&mut self) -> usize {
+        let parent = self.tasks[self.current].group;
+        let groups = &mut self.groups;
+        let id = groups.next_id;
+        groups.next_id += 1;
+        if let Some(parent) = parent {
+            groups.groups.get_mut(&parent).unwrap().children += 1;
+        }
+        groups.groups.insert(
+            id,
+            Group {
+                parent,
+                open: true,
+                members: 0,
+                children: 0,
+                waiters: vec![],
+            },
+        );
+        id
+    }
+
+    fn t_close_group(&mut self, id: usize) {
+        // `t_cancel_all` forgets about every group
+        if let Some(group) = self.groups.groups.get_mut(&id) {
+            group.open = false;
+            self.groups.release(id);
+        }
+    }
+
+    /// Makes task `id` a member of `group`.
+    pub(crate) fn t_join_group(&mut self, id: usize, group: Option<usize>) {
+        self.tasks[id].group = group;
+        if let Some(group) = group {
+            self.groups.groups.get_mut(&group).unwrap().members += 1;
+        }
+    }
+
+    /// Called when task `id` is done, one way or another. Wakes whoever waits for its group or a group it's in.
+    pub(crate) fn t_leave_group(&mut self, id: usize) {
+        let group = match self.tasks[id].group.take() {
+            Some(group) => group,
+            None => return,
+        };
+        self.groups.groups.get_mut(&group).unwrap().members -= 1;
+        let mut next = Some(group);
+        while let Some(group) = next {
+            let group = self.groups.groups.get_mut(&group).unwrap();
+            next = group.parent;
+            // they check for themselves if that was the last one
+            for waiter in std::mem::take(&mut group.waiters) {
+                self.t_unpark(waiter);
+            }
+        }
+        self.groups.release(group);
+    }
+
+    /// Forgets about every group, `t_cancel_all` just freed all their members.
+    pub(crate) fn t_forget_groups(&mut self) {
+        self.groups.groups.clear();
+        for task in &mut self.tasks {
+            task.group = None;
+        }
+    }
+
+    /// The tasks in group `id` or a group inside it that haven't finished.
+    fn t_group_members(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+        (1..self.tasks.len()).filter(move |&task| self.groups.is_within(self.tasks[task].group, id))
+    }
+
+    fn t_cancel_group(&mut self, id: usize) {
+        let members: Vec<usize> = self.t_group_members(id).collect();
+        let cancel_current = members.contains(&self.current);
+        for task in members {
+            if task != self.current {
+                self.t_cancel(task);
+            }
+        }
+        if cancel_current {
+            self.tasks[self.current].cancelled = true;
+            self.t_cancel_if_requested();
+        }
+    }
+
+    /// Takes task `id`, which is being cancelled, off the waiters of every group.
+    pub(crate) fn t_forget_group_wait(&mut self, id: usize) {
+        for group in self.groups.groups.values_mut() {
+            group.waiters.retain(|&waiter| waiter != id);
+        }
+    }
+
+    fn t_wait_group(&mut self, id: usize) {
+        let current = self.current;
+        while self.t_group_members(id).any(|task| task != current) {
+            if let Some(group) = self.groups.groups.get_mut(&id) {
+                group.waiters.push(current);
+            }
+            self.t_park();
+        }
+    }
+}
@@ -0,0 +1,178 @@
+#[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+use crate::reactor::Waker;
+use crate::sync::Event;
+use crate::wake_queue::{WakeQueue, Woken};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Work handed to the runtime from the "outside". The scheduler drains these every
+/// time it runs `t_yield` so they're picked up at the next switch.
+pub(crate) enum Injected {
+    Spawn(fn()),
+    Unpark(usize),
+    Notify(&'static Event, bool),
+}
+
+/// The injector is the only part of our runtime that is shared with other OS threads.
+/// Everything else lives on the thread that runs the `Runtime` and is accessed without
+/// any synchronization, so this queue is the one place where we need a real lock.
+///
+/// A signal handler can't take that lock: if the signal lands while the runtime's own thread
+/// holds it we deadlock. So next to the queue there's a lock-free `WakeQueue` and a preemption
+/// flag, which only need atomics. The runtime drains both in `t_yield`, before it looks at its
+/// tasks, so nothing a signal handler does ever changes the task table while we're using it.
+pub(crate) struct Injector {
+    queue: Mutex<VecDeque<Injected>>,
+    available: Condvar,
+    // Checking an atomic flag is a lot cheaper than taking the lock on every switch
+    pending: AtomicBool,
+    // unparks from signal handlers
+    wakes: WakeQueue,
+    // set by `RuntimeHandle::preempt`, cleared whenever the runtime yields
+    preempt: AtomicBool,
+    // The runtime might be blocked in the reactor instead of `wait`, then it needs this to wake
+    // up. `None` until the reactor hands it to us.
+    #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+    waker: Option<Waker>,
+}
+
+impl Injector {
+    pub(crate) fn new() -> Self {
+        Injector {
+            queue: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            pending: AtomicBool::new(false),
+            wakes: WakeQueue::new(),
+            preempt: AtomicBool::new(false),
+            #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+            waker: None,
+        }
+    }
+
+    #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+    pub(crate) fn with_waker(self, waker: Waker) -> Self {
+        Injector {
+            waker: Some(waker),
+            ..self
+        }
+    }
+
+    fn push(&self, item: Injected) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.push_back(item);
+        self.pending.store(true, Ordering::Release);
+        self.available.notify_one();
+        drop(queue);
+        self.wake_reactor();
+    }
+
+    /// Async-signal-safe: only atomics and, with a reactor, a `write` to its eventfd (or mio's waker).
+    fn push_from_signal(&self, id: usize) {
+        self.wakes.push(id);
+        self.pending.store(true, Ordering::Release);
+        self.wake_reactor();
+    }
+
+    fn wake_reactor(&self) {
+        #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+        {
+            if let Some(waker) = &self.waker {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Takes everything that has been injected so far. Returns an empty queue without
+    /// touching the lock if nothing is pending.
+    pub(crate) fn take(&self) -> VecDeque<Injected> {
+        if !self.pending.load(Ordering::Acquire) {
+            return VecDeque::new();
+        }
+        let mut queue = self.queue.lock().unwrap();
+        self.pending.store(false, Ordering::Release);
+        std::mem::take(&mut *queue)
+    }
+
+    /// Takes the next unpark pushed by a signal handler.
+    pub(crate) fn take_wake(&self) -> Option<Woken> {
+        self.wakes.take()
+    }
+
+    /// Returns true if somebody asked the running task to yield, and forgets about it.
+    pub(crate) fn take_preempt(&self) -> bool {
+        self.preempt.swap(false, Ordering::Relaxed)
+    }
+
+    pub(crate) fn preempt_requested(&self) -> bool {
+        self.preempt.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Blocks the OS thread running the runtime until something gets injected.
+    pub(crate) fn wait(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        while queue.is_empty() {
+            queue = self.available.wait(queue).unwrap();
+        }
+    }
+}
+
+/// A handle to a `Runtime` that can be sent to, and used from, other OS threads.
+///
+/// Our `Runtime` itself is not thread safe at all. It's only ever touched from the thread
+/// that runs it. The handle doesn't touch the runtime directly, it only pushes requests
+/// to an injector queue which the scheduler drains on every yield. This means that a
+/// request made through a handle is acted on the next time any task on the runtime yields.
+#[derive(Clone)]
+pub struct RuntimeHandle {
+    pub(crate) injector: Arc<Injector>,
+}
+
+impl RuntimeHandle {
+    /// Schedules a new task on the runtime. If all tasks are in use the request is kept in
+    /// the queue until one finishes instead of panicking like `Runtime::spawn` does.
+    pub fn spawn(&self, f: fn()) {
+        self.injector.push(Injected::Spawn(f));
+    }
+
+    /// Wakes the task with the given id if it's parked. If it's not parked yet, the next call
+    /// to `park_task` from that task returns immediately, the same way `std::thread::park` works.
+    ///
+    /// With wake batching (`Runtime::set_wake_batching`) a task waking a task on its own runtime skips
+    /// the injector, the wake waits on the runtime's batch until the task yields.
+    pub fn unpark(&self, id: usize) {
+        if !crate::batch::batch_wake(&self.injector, id) {
+            self.injector.push(Injected::Unpark(id));
+        }
+    }
+
+    /// Same as `unpark`, but safe to call from a signal handler: it doesn't take a lock or allocate. If the
+    /// runtime is blocked waiting for I/O it wakes up right away, otherwise the task is unparked the next time
+    /// any task yields. With more than a few dozen of these between two yields some of them turn into a wakeup
+    /// for every parked task, which is fine since `park` can return spuriously anyway.
+    pub fn unpark_from_signal(&self, id: usize) {
+        self.injector.push_from_signal(id);
+    }
+
+    /// Asks the task that's running right now to yield at its next checkpoint (`maybe_yield`), even if its
+    /// time slice isn't used up yet. Like `unpark_from_signal` it's safe to call from a signal handler, so a
+    /// timer signal can preempt tasks that call `maybe_yield` with a long time slice. A task that holds a
+    /// `preempt::NoPreempt` yields when it lets go of it instead.
+    pub fn preempt(&self) {
+        self.injector.preempt.store(true, Ordering::Relaxed);
+    }
+
+    /// Calls `notify_one` on the event from the runtime's thread.
+    pub fn notify_one(&self, event: &'static Event) {
+        self.injector.push(Injected::Notify(event, false));
+    }
+
+    /// Calls `notify_all` on the event from the runtime's thread.
+    pub fn notify_all(&self, event: &'static Event) {
+        self.injector.push(Injected::Notify(event, true));
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{Runtime, RUNTIME};
+use std::marker::PhantomData;
+
+/// Returned from `Runtime::spawn`. Works like `std::thread::JoinHandle`: you can wait for the task to
+/// finish with `join`, and if you drop the handle (or call `detach`) the task keeps running on its own
+/// and is reaped by the runtime as soon as it finishes.
+///
+/// The handle only stores the id of the task and the generation of the task it was spawned on. Task ids
+/// are reused once a task is reaped, so the generation is how we know if it's still the same task.
+pub struct JoinHandle {
+    id: usize,
+    generation: usize,
+    // The handle talks directly to the runtime on the current OS thread, so it must never be sent to another one
+    _not_send: PhantomData<*const ()>,
+}
+
+impl JoinHandle {
+    pub(crate) fn new(id: usize, generation: usize) -> Self {
+        JoinHandle {
+            id,
+            generation,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The id of the task this handle belongs to, `usize::MAX` if the spawn was rejected (see `Overload::Call`).
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Parks the current task until the task this handle belongs to has finished.
+    pub fn join(self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_join(self.id, self.generation);
+        }
+    }
+
+    /// Lets the task run on its own. This is exactly what happens when the handle is dropped, it's just a more
+    /// explicit way of saying it.
+    pub fn detach(self) {}
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_detach(self.id, self.generation);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! How long tasks wait to run. Once `Runtime::enable_latency_histograms` is called we keep two histograms for
+//! every task and two for the whole runtime:
+//!
+//! - the scheduling latency, from the moment a task becomes `Ready` (it's spawned, unparked or switched away
+//!   from while it could go on) until the scheduler picks it again,
+//! - the switch time, from the moment a task starts switching away until the task it switches to runs again.
+//!   A task that runs for the first time starts somewhere else than where the others come back from a switch,
+//!   so that switch isn't counted.
+//!
+//! Both end up in `Runtime::stats`. Averages hide exactly what's interesting about a scheduler, a task that
+//! waits 10 ms once in a while doesn't move them, which is why we keep the whole distribution and let you ask
+//! for its p99. `Histogram::write_hgrm` writes it in the percentile format of HdrHistogram, so it can be plotted
+//! next to the histograms of other schedulers with https://hdrhistogram.github.io/HdrHistogram/plotFiles.html.
+use crate::Runtime;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// Every power of two is split into this many buckets, so we're never off by more than 1/16 of a value
+const SUB_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+
+/// A histogram of durations, with nanosecond resolution up to 16 ns and about 6% beyond that. It only takes
+/// as much memory as its largest value needs, a histogram of values below a millisecond has about 300 buckets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    // in nanoseconds
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram::default()
+    }
+
+    pub fn record(&mut self, value: Duration) {
+        let nanos = value.as_nanos().min(u64::MAX as u128) as u64;
+        let i = bucket(nanos);
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+        self.min = if self.total == 0 { nanos } else { self.min.min(nanos) };
+        self.max = self.max.max(nanos);
+        self.total += 1;
+        self.sum += nanos as u128;
+    }
+
+    /// Adds everything `other` has recorded, to get one histogram for several tasks or runtimes.
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.total == 0 {
+            return;
+        }
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.min = if self.total == 0 {
+            other.min
+        } else {
+            self.min.min(other.min)
+        };
+        self.max = self.max.max(other.max);
+        self.total += other.total;
+        self.sum += other.sum;
+    }
+
+    /// How many values we've recorded.
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_nanos(self.min)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.total {
+            0 => Duration::from_secs(0),
+            total => Duration::from_nanos((self.sum / total as u128) as u64),
+        }
+    }
+
+    /// The value `percentile` percent of all values are at most, `percentile(99.0)` is the p99. It's the largest
+    /// value of the bucket it falls in, so it's rather too high than too low. Zero if we haven't recorded anything.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(highest(i).min(self.max));
+            }
+        }
+        Duration::from_secs(0)
+    }
+
+    /// Writes the distribution the way HdrHistogram's `outputPercentileDistribution` does (a `.hgrm` file),
+    /// one line for every bucket we've seen a value in, with values in microseconds.
+    pub fn write_hgrm(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:>12} {:>14} {:>10} {:>14}",
+            "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+        )?;
+        writeln!(out)?;
+        let micros = |nanos: u64| nanos as f64 / 1000.0;
+        let mut seen = 0;
+        let mut variance = 0.0;
+        let mean = self.sum as f64 / self.total.max(1) as f64;
+        for (i, &count) in self.counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            seen += count;
+            let value = highest(i).min(self.max);
+            variance += count as f64 * (value as f64 - mean).powi(2);
+            let percentile = seen as f64 / self.total as f64;
+            if seen < self.total {
+                let inverse = 1.0 / (1.0 - percentile);
+                writeln!(
+                    out,
+                    "{:12.3} {:14.12} {:10} {:14.2}",
+                    micros(value),
+                    percentile,
+                    seen,
+                    inverse
+                )?;
+            } else {
+                writeln!(out, "{:12.3} {:14.12} {:10}", micros(value), percentile, seen)?;
+            }
+        }
+        let deviation = (variance / self.total.max(1) as f64).sqrt();
+        writeln!(
+            out,
+            "#[Mean    = {:12.3}, StdDeviation   = {:12.3}]",
+            mean / 1000.0,
+            deviation / 1000.0
+        )?;
+        writeln!(
+            out,
+            "#[Max     = {:12.3}, Total count    = {:12}]",
+            micros(self.max),
+            self.total
+        )?;
+        let buckets = self.counts.len().div_ceil(SUB_BUCKETS);
+        writeln!(out, "#[Buckets = {:12}, SubBuckets     = {:12}]", buckets, SUB_BUCKETS)
+    }
+}
+
+/// The short version, `1000 values, min 120ns, mean 310ns, p50 290ns, p90 410ns, p99 1.2µs, p99.9 4.1µs, max 9µs`.
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.total == 0 {
+            return f.write_str("no values");
+        }
+        write!(
+            f,
+            "{} values, min {:?}, mean {:?}, p50 {:?}, p90 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+            self.total,
+            self.min(),
+            self.mean(),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(99.9),
+            self.max()
+        )
+    }
+}
+
+// Values below `SUB_BUCKETS` get a bucket each, every power of two above that is split into `SUB_BUCKETS`
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let power = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (power - SUB_BITS)) as usize & (SUB_BUCKETS - 1);
+    (power - SUB_BITS + 1) as usize * SUB_BUCKETS + sub
+}
+
+// The largest value that ends up in bucket `i`
+fn highest(i: usize) -> u64 {
+    if i < SUB_BUCKETS {
+        return i as u64;
+    }
+    let shift = (i / SUB_BUCKETS) as u32 - 1;
+    let lowest = ((SUB_BUCKETS + i % SUB_BUCKETS) as u64) << shift;
+    lowest + ((1 << shift) - 1)
+}
+
+/// The histograms of the whole runtime, see `Runtime::enable_latency_histograms`.
+pub(crate) struct Latencies {
+    pub(crate) scheduling: Histogram,
+    pub(crate) switches: Histogram,
+    // when the last switch started, see `t_switched`
+    switch_started: Instant,
+}
+
+impl Runtime {
+    /// Starts keeping the histograms of the `latency` module. It costs a few clock reads on every switch and
+    /// wakeup, which is why it's off until you ask for it. Calling it again starts over.
+    pub fn enable_latency_histograms(&mut self) {
+        self.latencies = Some(Latencies {
+            scheduling: Histogram::new(),
+            switches: Histogram::new(),
+            switch_started: Instant::now(),
+        });
+        let now = Instant::now();
+        for task in &mut self.tasks {
+            task.ready_since = now;
+            task.scheduling_latency = Histogram::new();
+            task.switch_time = Histogram::new();
+        }
+    }
+
+    /// Task `id` became `Ready` just now.
+    pub(crate) fn t_ready(&mut self, id: usize) {
+        if self.latencies.is_some() {
+            self.tasks[id].ready_since = Instant::now();
+        }
+    }
+
+    /// The scheduler picked the `Ready` task `id`.
+    pub(crate) fn t_picked(&mut self, id: usize) {
+        if let Some(latencies) = &mut self.latencies {
+            let task = &mut self.tasks[id];
+            let waited = task.ready_since.elapsed();
+            task.scheduling_latency.record(waited);
+            latencies.scheduling.record(waited);
+        }
+    }
+
+    /// The current task is about to switch away.
+    pub(crate) fn t_switching(&mut self) {
+        if let Some(latencies) = &mut self.latencies {
+            latencies.switch_started = Instant::now();
+        }
+    }
+
+    /// The current task is back from a switch.
+    pub(crate) fn t_switched(&mut self) {
+        if let Some(latencies) = &mut self.latencies {
+            let took = latencies.switch_started.elapsed();
+            self.tasks[self.current].switch_time.record(took);
+            latencies.switches.record(took);
+        }
+    }
+}
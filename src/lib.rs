@@ -0,0 +1,21 @@
+// The LoongArch backend is written with `naked_asm!`, see `src/arch/loongarch64.rs`
+#![cfg_attr(not(any(feature = "sim", target_arch = "loongarch64")), feature(llvm_asm))]
+#![cfg_attr(not(any(feature = "sim", target_arch = "loongarch64")), feature(naked_functions))]
+#![cfg_attr(not(feature = "static-alloc"), feature(thread_local))]
+#![cfg_attr(feature = "static-alloc", no_std)]
+
+#[cfg(all(feature = "sim", feature = "static-alloc"))]
+compile_error!("the `sim` backend runs tasks on OS threads, it can't work without the standard library.");
+#[cfg(all(feature = "mio", feature = "io-uring"))]
+compile_error!("`mio` and `io-uring` are two different reactors, pick one.");
+
+mod arch;
+mod stack;
+mod wake_queue;
+
+// Without an allocator (and usually without an operating system) we only have the small runtime in `bare`.
+#[cfg(feature = "static-alloc")]
+pub mod bare;
+
+#[cfg(not(feature = "static-alloc"))]
+include!("runtime.rs");
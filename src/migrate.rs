@@ -0,0 +1,114 @@
+//! Moving parked tasks from one runtime to another. `Runtime::migrate` hands a parked task (its stack, its
+//! context and everything else we know about it) to another runtime, and `Runtime::steal_from` takes whichever
+//! task another runtime can spare. That's enough to rebalance a busy runtime onto an idle one (see
+//! `examples/rebalance.rs`), and it's the move a work-stealing scheduler would make.
+//!
+//! The stack doesn't move in memory, only the `Vec` that owns it does, so everything on it stays where it is.
+//! What can't come along is anything the old runtime keeps about the task, so only a task that waits for
+//! nothing but an unpark can move:
+//!
+//! - It's parked in `coro::park` (or `channel::Receiver::recv`, which parks there). A task parked anywhere else
+//!   is waiting for a lock, a timer, I/O, another task or a group, and the old runtime would wake it under its
+//!   old id.
+//! - It doesn't hold a `sync::Mutex` or a `tls::MigrationGuard`.
+//! - Nobody can join it (its `JoinHandle` was dropped), it isn't in a `TaskGroup` and it has no deadline.
+//!
+//! The moved task gets a new id, so whoever was going to unpark it under the old one can't anymore. That's why
+//! it's woken up on the new runtime right away, like a spurious wakeup: `recv` checks for a message and tells
+//! the sender where it waits now, and a task using `coro::park` has to do the same.
+//!
+//! Both runtimes have to be on the calling OS thread, we can't reach into a runtime while another thread runs it.
+use crate::{Event, Runtime, State};
+use std::fmt;
+
+/// Why `Runtime::migrate` couldn't move a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateError {
+    /// There's no such task, or it isn't parked in `coro::park`.
+    NotParked,
+    /// It holds a `tls::MigrationGuard`.
+    Pinned,
+    /// It holds a `sync::Mutex`, somebody can join it, or it's in a group or has a deadline.
+    Bound,
+    /// Every task of the target runtime is in use.
+    NoRoom,
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let why = match self {
+            MigrateError::NotParked => "the task isn't parked in `coro::park`",
+            MigrateError::Pinned => "the task holds a `MigrationGuard`",
+            MigrateError::Bound => "the task is bound to its runtime",
+            MigrateError::NoRoom => "the target runtime has no free task",
+        };
+        f.write_str(why)
+    }
+}
+
+impl std::error::Error for MigrateError {}
+
+impl Runtime {
+    /// Moves the parked task `id` to `target` and returns its id there, see the `migrate` module for which tasks
+    /// can move. It's `Ready` on `target` and its slot here is free again.
+    pub fn migrate(&mut self, id: usize, target: &mut Runtime) -> Result<usize, MigrateError> {
+        self.t_check_movable(id)?;
+        let new_id = target.free.pop().ok_or(MigrateError::NoRoom)?;
+        let from = &mut self.tasks[id];
+        let to = &mut target.tasks[new_id];
+        std::mem::swap(&mut to.stack, &mut from.stack);
+        std::mem::swap(&mut to.ctx, &mut from.ctx);
+        to.reset();
+        to.detached = true;
+        to.priority = from.priority;
+        to.effective = from.priority;
+        to.budget = from.budget;
+        to.demoted = from.demoted;
+        to.locals = std::mem::take(&mut from.locals);
+        to.movable = true;
+        to.name = from.name.take();
+        to.run_time = from.run_time;
+        to.scheduled = from.scheduled;
+        to.state = State::Ready;
+        from.movable = false;
+
+        // whatever we were going to wake under the old id isn't there anymore, and neither is a channel's waiter
+        // (the task tells the channel where it waits now when it wakes up)
+        self.batched.retain(|&batched| batched != id);
+        self.t_forget_wait(id);
+        self.t_free(id);
+        target.t_ready(new_id);
+        target.debugger.update(&target.tasks[new_id]);
+        target.t_trace(Event::Spawn(new_id));
+        target.scheduler.spawned(new_id);
+        Ok(new_id)
+    }
+
+    /// Moves the first task of `other` that can move (see `migrate`) over here and returns its new id, or `None`
+    /// if there isn't one or we have no free task.
+    pub fn steal_from(&mut self, other: &mut Runtime) -> Option<usize> {
+        if self.free.is_empty() {
+            return None;
+        }
+        let id = (1..other.tasks.len()).find(|&id| other.t_check_movable(id).is_ok())?;
+        other.migrate(id, self).ok()
+    }
+
+    fn t_check_movable(&self, id: usize) -> Result<(), MigrateError> {
+        let task = match self.tasks.get(id) {
+            Some(task) if id != 0 && task.state == State::Parked && task.movable => task,
+            _ => return Err(MigrateError::NotParked),
+        };
+        if self.t_is_pinned(id) {
+            return Err(MigrateError::Pinned);
+        }
+        if !task.held.is_empty() || !task.detached || task.group.is_some() || task.deadline.is_some() {
+            return Err(MigrateError::Bound);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn t_set_movable(&mut self, movable: bool) {
+        self.tasks[self.current].movable = movable;
+    }
+}
@@ -0,0 +1,149 @@
+//! A live view of a runtime, like `top`. Every `Monitor::sample` takes the runtime's `Stats` and compares them
+//! with the last sample, which turns the totals into rates: how often every task was switched to and how much of
+//! the time it ran since then. `Sample` prints as a table, `cargo run --example top` redraws it twice a second
+//! while a few tasks do some work.
+//!
+//! A task can sample the runtime it runs on, so the monitor can be just another task. `Runtime::stats` only
+//! reads, the tasks being watched don't notice.
+use crate::{Runtime, Stats, TaskState, RUNTIME};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The stats of the runtime the current task runs on.
+pub fn stats() -> Stats {
+    unsafe {
+        let rt_ptr = RUNTIME as *const Runtime;
+        (*rt_ptr).stats()
+    }
+}
+
+pub struct Monitor {
+    last: Instant,
+    // `None` until the first sample, everything counts from zero until then
+    previous: Option<Stats>,
+}
+
+/// One line of the table.
+#[derive(Debug, Clone)]
+pub struct TaskSample {
+    pub id: usize,
+    pub name: Option<String>,
+    pub state: TaskState,
+    /// How many times per second the task was switched to since the last sample.
+    pub switches_per_sec: f64,
+    /// The share of the time since the last sample the task was running, from 0 to 1.
+    pub cpu: f64,
+    pub stack_used: Option<usize>,
+    pub stack_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// The time since the last sample.
+    pub interval: Duration,
+    pub switches_per_sec: f64,
+    /// Every task that isn't `Available`.
+    pub tasks: Vec<TaskSample>,
+}
+
+impl Monitor {
+    /// The first sample covers the time since this was called.
+    pub fn new() -> Self {
+        Monitor {
+            last: Instant::now(),
+            previous: None,
+        }
+    }
+
+    /// Samples the runtime the current task runs on.
+    pub fn sample(&mut self) -> Sample {
+        self.sample_stats(stats())
+    }
+
+    /// Same as `sample` with stats from `Runtime::stats`, for a monitor outside the runtime.
+    pub fn sample_stats(&mut self, stats: Stats) -> Sample {
+        let now = Instant::now();
+        let interval = now - self.last;
+        let secs = interval.as_secs_f64().max(f64::MIN_POSITIVE);
+        let previous = self.previous.as_ref();
+
+        let switches = stats.context_switches - previous.map_or(0, |p| p.context_switches);
+        let tasks = stats
+            .tasks
+            .iter()
+            .filter(|t| t.state != TaskState::Available)
+            .map(|t| {
+                let before = previous.and_then(|p| p.tasks.iter().find(|b| b.id == t.id));
+                // a task that got a slot since the last sample starts from zero
+                let (scheduled, run_time) = match before {
+                    Some(b) if b.scheduled <= t.scheduled => (t.scheduled - b.scheduled, t.run_time - b.run_time),
+                    _ => (t.scheduled, t.run_time),
+                };
+                TaskSample {
+                    id: t.id,
+                    name: t.name.clone(),
+                    state: t.state,
+                    switches_per_sec: scheduled as f64 / secs,
+                    cpu: (run_time.as_secs_f64() / secs).min(1.0),
+                    stack_used: t.stack_used,
+                    stack_size: t.stack_size,
+                }
+            })
+            .collect();
+
+        self.last = now;
+        self.previous = Some(stats);
+        Sample {
+            interval,
+            switches_per_sec: switches as f64 / secs,
+            tasks,
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::new()
+    }
+}
+
+/// ```text
+///    id  name              state        sw/s   cpu%  stack
+///     0  base              Ready         0.0    0.0  -
+///     1  monitor           Running       2.0    0.1  3.2 KiB / 2048 KiB
+///     2  cruncher          Ready      1980.4   91.6  1.1 KiB / 2048 KiB
+/// ```
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} tasks, {:.0} switches/s over the last {:.1}s",
+            self.tasks.len(),
+            self.switches_per_sec,
+            self.interval.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "{:>5}  {:<16}  {:<9}  {:>8}  {:>5}  stack",
+            "id", "name", "state", "sw/s", "cpu%"
+        )?;
+        for t in &self.tasks {
+            let name = t.name.as_deref().unwrap_or("-");
+            let state = format!("{:?}", t.state);
+            write!(
+                f,
+                "{:>5}  {:<16}  {:<9}  {:>8.1}  {:>5.1}  ",
+                t.id,
+                name,
+                state,
+                t.switches_per_sec,
+                t.cpu * 100.0
+            )?;
+            match t.stack_used {
+                Some(used) => writeln!(f, "{:.1} KiB / {} KiB", used as f64 / 1024.0, t.stack_size / 1024)?,
+                None => writeln!(f, "-")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Cooperative versions of the socket types in `std::net`. They look the same, but instead of blocking the
+//! OS thread when a socket isn't ready they park the current task and let the reactor wake it up again.
+//!
+//! All the actual work is done by the `std` types, we just put them in non-blocking mode and retry every
+//! call that fails with `WouldBlock` once the socket is ready (see `reactor::retry`).
+//!
+//! With the `io-uring` feature TCP sockets accept, read and write through io_uring instead. Then the kernel
+//! does the waiting, so those sockets stay in blocking mode. UDP always goes through epoll.
+use crate::reactor::{self, Interest};
+use crate::spawn_blocking;
+#[cfg(feature = "io-uring")]
+use crate::uring;
+use std::io::{self, Read, Write};
+use std::net::{self, Shutdown, SocketAddr, ToSocketAddrs};
+#[cfg(feature = "io-uring")]
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// true if TCP sockets go through io_uring
+fn use_uring() -> bool {
+    #[cfg(feature = "io-uring")]
+    {
+        uring::available()
+    }
+    #[cfg(not(feature = "io-uring"))]
+    {
+        false
+    }
+}
+
+pub struct TcpListener {
+    inner: net::TcpListener,
+}
+
+impl TcpListener {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let inner = net::TcpListener::bind(addr)?;
+        inner.set_nonblocking(!use_uring())?;
+        Ok(TcpListener { inner })
+    }
+
+    /// Parks the current task until a new connection comes in.
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        #[cfg(feature = "io-uring")]
+        {
+            if use_uring() {
+                let fd = uring::accept(self.as_raw_fd())?;
+                let stream = unsafe { net::TcpStream::from_raw_fd(fd) };
+                let addr = stream.peer_addr()?;
+                return Ok((TcpStream::from_std(stream)?, addr));
+            }
+        }
+        let (stream, addr) = reactor::retry(self.as_raw_fd(), Interest::Read, || self.inner.accept())?;
+        Ok((TcpStream::from_std(stream)?, addr))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+impl AsRawFd for TcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        reactor::deregister(self.as_raw_fd());
+    }
+}
+
+pub struct TcpStream {
+    inner: net::TcpStream,
+}
+
+impl TcpStream {
+    /// Connects to `addr`. The standard library doesn't give us a non-blocking `connect` (and looking up
+    /// the address can block as well), so we do this on the blocking pool while the current task is parked.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        let stream = spawn_blocking(move || net::TcpStream::connect(&addrs[..]))?;
+        TcpStream::from_std(stream)
+    }
+
+    fn from_std(inner: net::TcpStream) -> io::Result<Self> {
+        inner.set_nonblocking(!use_uring())?;
+        Ok(TcpStream { inner })
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.inner.shutdown(how)
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.inner.set_nodelay(nodelay)
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "io-uring")]
+        {
+            if use_uring() {
+                return uring::recv(self.as_raw_fd(), buf);
+            }
+        }
+        let inner = &self.inner;
+        reactor::retry(inner.as_raw_fd(), Interest::Read, || (&*inner).read(buf))
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "io-uring")]
+        {
+            if use_uring() {
+                return uring::send(self.as_raw_fd(), buf);
+            }
+        }
+        let inner = &self.inner;
+        reactor::retry(inner.as_raw_fd(), Interest::Write, || (&*inner).write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        reactor::deregister(self.as_raw_fd());
+    }
+}
+
+pub struct UdpSocket {
+    inner: net::UdpSocket,
+}
+
+impl UdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let inner = net::UdpSocket::bind(addr)?;
+        inner.set_nonblocking(true)?;
+        Ok(UdpSocket { inner })
+    }
+
+    /// Sets the default address for `send` and the only address `recv` accepts datagrams from. There's no
+    /// handshake for UDP, so unlike `TcpStream::connect` this never blocks.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.inner.connect(addr)
+    }
+
+    /// Parks the current task until the datagram is sent. Sending only has to wait if the socket's send
+    /// buffer is full.
+    pub fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses to send data to"))?;
+        reactor::retry(self.as_raw_fd(), Interest::Write, || self.inner.send_to(buf, addr))
+    }
+
+    /// Parks the current task until a datagram arrives.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        reactor::retry(self.as_raw_fd(), Interest::Read, || self.inner.recv_from(buf))
+    }
+
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        reactor::retry(self.as_raw_fd(), Interest::Write, || self.inner.send(buf))
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        reactor::retry(self.as_raw_fd(), Interest::Read, || self.inner.recv(buf))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+impl AsRawFd for UdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        reactor::deregister(self.as_raw_fd());
+    }
+}
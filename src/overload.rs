@@ -0,0 +1,77 @@
+//! What `spawn` does when every task slot is in use. By default it panics, which is fine for a program that
+//! knows how many tasks it has, but not for a server that spawns a task per connection: a flood of connections
+//! would take the whole server down. `Runtime::on_overload` picks something else:
+//!
+//! - `Overload::Wait` parks the spawning task until a task finishes and frees its slot. An accept loop that
+//!   spawns a task per connection simply stops accepting for a while, and the kernel's backlog takes the rest.
+//! - `Overload::Call(handler)` calls `handler` with the function we couldn't spawn and returns a `JoinHandle`
+//!   that doesn't belong to any task. The handler can count it, log it or run it inline, whatever makes sense.
+//!   `spawn_arg` and `spawn_with` have no `fn()` to give it, their tasks are dropped without calling it.
+//!
+//! A finished task only frees its slot once it's been joined or if it was detached, so waiting for a slot while
+//! holding the `JoinHandle`s of the tasks that use them up waits forever.
+use crate::{Entry, JoinHandle, Runtime};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Overload {
+    /// Panic with "no available task.", the default.
+    Panic,
+    /// Park the spawning task until a slot frees up.
+    Wait,
+    /// Call the handler with the function that didn't get a task, and don't spawn anything.
+    Call(fn(fn())),
+}
+
+impl Runtime {
+    /// Sets what `spawn` (and the other `spawn_*` methods) do when every task slot is in use.
+    pub fn on_overload(&mut self, overload: Overload) {
+        self.overload = overload;
+    }
+
+    /// Makes sure there's a free task slot for `entry`, waiting for one if we're supposed to. Returns false if it
+    /// went to the overload handler instead.
+    pub(crate) fn t_make_room(&mut self, entry: Entry) -> bool {
+        while self.free.is_empty() {
+            match self.overload {
+                Overload::Panic => panic!("no available task."),
+                Overload::Wait => {
+                    if !self.spawn_waiters.contains(&self.current) {
+                        self.spawn_waiters.push_back(self.current);
+                    }
+                    self.t_park();
+                }
+                Overload::Call(handler) => {
+                    // the handler only knows what to do with a `fn()`, a task with an argument is just dropped
+                    if let Entry::Plain(f) = entry {
+                        handler(f);
+                    }
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Called when a slot frees up. Wakes the task that has been waiting to spawn the longest.
+    pub(crate) fn t_slot_freed(&mut self) {
+        if let Some(waiter) = self.spawn_waiters.pop_front() {
+            self.t_unpark(waiter);
+        }
+    }
+
+    /// Takes task `id`, which is being cancelled, off the spawn waiters. If a slot already woke it up we pass
+    /// that on to the next waiter, it won't spawn anything now.
+    pub(crate) fn t_forget_spawn_wait(&mut self, id: usize) {
+        self.spawn_waiters.retain(|&waiter| waiter != id);
+        if !self.free.is_empty() {
+            self.t_slot_freed();
+        }
+    }
+}
+
+impl JoinHandle {
+    /// The handle `spawn` returns when `Overload::Call` took the function. Joining it returns right away.
+    pub(crate) fn rejected() -> Self {
+        JoinHandle::new(usize::MAX, 0)
+    }
+}
@@ -0,0 +1,147 @@
+//! A small data-parallel helper in the spirit of rayon. `parallel_for(range, chunk, f)` cuts `range` into chunks
+//! of `chunk` indices, spawns a worker task for every free task slot and calls `f` for every index. Every worker
+//! (and the task that called `parallel_for`, which pitches in) takes the next chunk nobody has taken yet, so a
+//! worker that got slow chunks simply takes fewer of them. It returns once every index has been handled.
+//!
+//! All workers run on the same OS thread, so this doesn't make a computation any faster by itself: it's
+//! concurrency, not parallelism. It pays off when `f` waits for something (I/O, `spawn_blocking`, a timer),
+//! since the other workers keep going while one of them is parked. Workers yield between chunks so they take
+//! turns even when `f` never does.
+//!
+//! The workers are members of the caller's `TaskGroup`, if it has one, and a worker cancelled with its group
+//! leaves the rest of the chunk it was working on behind. The caller picks that up once the workers are done,
+//! starting with the index the worker was in the middle of, so `f` can get called twice for that one index.
+use crate::{JoinHandle, Runtime, RUNTIME};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ops::Range;
+
+struct Job {
+    // borrowed from the caller of `parallel_for`, which doesn't return (or unwind) before every worker has
+    // finished, see `Workers`
+    f: &'static dyn Fn(usize),
+    next: Cell<usize>,
+    end: usize,
+    chunk: usize,
+    // what's left of the chunk every task is working on, by task id
+    claimed: Vec<Cell<(usize, usize)>>,
+}
+
+impl Job {
+    fn run(&self) {
+        let me = &self.claimed[crate::task_id()];
+        loop {
+            let start = self.next.get();
+            if start >= self.end {
+                return;
+            }
+            let end = self.end.min(start + self.chunk);
+            self.next.set(end);
+            self.run_chunk(me, start, end);
+            crate::yield_task();
+        }
+    }
+
+    fn run_chunk(&self, me: &Cell<(usize, usize)>, start: usize, end: usize) {
+        me.set((start, end));
+        for i in start..end {
+            (self.f)(i);
+            me.set((i + 1, end));
+        }
+    }
+
+    /// Runs what cancelled workers left of their chunks. Every worker has to be done by now.
+    fn run_leftovers(&self) {
+        let me = crate::task_id();
+        for (id, claimed) in self.claimed.iter().enumerate() {
+            let (start, end) = claimed.replace((0, 0));
+            if id != me && start < end {
+                self.run_chunk(&self.claimed[me], start, end);
+            }
+        }
+    }
+}
+
+/// The workers of a `parallel_for`. Dropping it waits for all of them, so they're done with the `Job` and the
+/// function on our stack before those go away, even when `f` panics on the caller. Nobody starts on another
+/// chunk then.
+struct Workers<'a> {
+    job: &'a Job,
+    handles: Vec<JoinHandle>,
+}
+
+impl Drop for Workers<'_> {
+    fn drop(&mut self) {
+        self.job.next.set(self.job.end);
+        for handle in self.handles.drain(..) {
+            // a worker that hasn't started yet has nothing to do, and mustn't find the job once it's gone
+            JOBS.with(|jobs| jobs.borrow_mut().remove(&handle.id()));
+            handle.join();
+        }
+    }
+}
+
+// We can only spawn a `fn()`, so every worker looks up the job it's meant to help with by its task id. A queue
+// wouldn't do: with two `parallel_for` running at once a worker could pick up the other one's job, and then
+// nobody waits for it to finish.
+thread_local! {
+    static JOBS: RefCell<HashMap<usize, *const Job>> = RefCell::new(HashMap::new());
+}
+
+fn worker() {
+    let job = JOBS.with(|jobs| jobs.borrow_mut().remove(&crate::task_id()));
+    if let Some(job) = job {
+        unsafe { (*job).run() };
+    }
+}
+
+impl Runtime {
+    /// Calls `f` for every index in `range`, `chunk` indices at a time, spread over as many tasks as there are
+    /// free task slots. Returns once `f` has been called for every index, even if workers were cancelled (see
+    /// the module documentation). It works from the base task as well as from any other task.
+    pub fn parallel_for<F>(&mut self, range: Range<usize>, chunk: usize, f: F)
+    where
+        F: Fn(usize),
+    {
+        if cfg!(feature = "sim") {
+            panic!("the sim backend runs every task on an OS thread of its own, workers can't find their job there.");
+        }
+        assert!(chunk > 0, "the chunks have to have at least one index.");
+        let f: &dyn Fn(usize) = &f;
+        let job = Job {
+            // We make sure the workers are done before `f` goes away, see the comment on `Job`.
+            f: unsafe { std::mem::transmute::<&dyn Fn(usize), &'static dyn Fn(usize)>(f) },
+            next: Cell::new(range.start),
+            end: range.end,
+            chunk,
+            claimed: (0..self.tasks.len()).map(|_| Cell::new((0, 0))).collect(),
+        };
+
+        let chunks = range.clone().step_by(chunk).len();
+        let mut workers = Workers {
+            job: &job,
+            handles: vec![],
+        };
+        // we take one chunk ourselves
+        while workers.handles.len() + 1 < chunks && !self.free.is_empty() {
+            let handle = self.spawn(worker);
+            JOBS.with(|jobs| jobs.borrow_mut().insert(handle.id(), &job as *const Job));
+            workers.handles.push(handle);
+        }
+
+        job.run();
+        drop(workers);
+        job.run_leftovers();
+    }
+}
+
+/// `Runtime::parallel_for` on the runtime running the current task.
+pub fn parallel_for<F>(range: Range<usize>, chunk: usize, f: F)
+where
+    F: Fn(usize),
+{
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).parallel_for(range, chunk, f);
+    }
+}
@@ -0,0 +1,116 @@
+//! A pool of long-lived worker tasks. `spawn` sets up a fresh context for every task and a task slot is taken
+//! until it's joined, which is a lot of ceremony for a job that runs for a microsecond. A `WorkerPool` spawns
+//! its workers once, and every worker runs job after job from a shared queue on the stack it already has.
+//!
+//! A job that panics only takes itself down: the worker catches the panic, counts it and goes on with the next
+//! job. Dropping the pool waits until every queued job has run and the workers have finished.
+use crate::sync::Event;
+use crate::{JoinHandle, Runtime, RUNTIME};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+struct Shared {
+    jobs: RefCell<VecDeque<Box<dyn FnOnce()>>>,
+    // notified for every job we queue, and for everyone once the pool closes
+    ready: Event,
+    closed: Cell<bool>,
+    panicked: Cell<usize>,
+}
+
+// We can only spawn a `fn()`, so every worker looks up the pool it works for by its task id, like the workers
+// of `parallel_for`.
+thread_local! {
+    static POOLS: RefCell<HashMap<usize, Rc<Shared>>> = RefCell::new(HashMap::new());
+}
+
+fn worker() {
+    let shared = POOLS.with(|pools| pools.borrow_mut().remove(&crate::task_id()));
+    let shared = match shared {
+        Some(shared) => shared,
+        None => return,
+    };
+
+    loop {
+        let job = shared.jobs.borrow_mut().pop_front();
+        match job {
+            Some(job) => {
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    shared.panicked.set(shared.panicked.get() + 1);
+                }
+            }
+            None if shared.closed.get() => return,
+            None => shared.ready.wait(),
+        }
+    }
+}
+
+pub struct WorkerPool {
+    shared: Rc<Shared>,
+    workers: Vec<JoinHandle>,
+}
+
+impl WorkerPool {
+    /// Spawns `workers` worker tasks on the runtime running the current task. They take up their task slots
+    /// until the pool is dropped.
+    pub fn new(workers: usize) -> Self {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).worker_pool(workers)
+        }
+    }
+
+    /// Queues `f` to run on one of the workers. Never blocks.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + 'static,
+    {
+        assert!(!self.workers.is_empty(), "a pool without workers never runs anything.");
+        self.shared.jobs.borrow_mut().push_back(Box::new(f));
+        self.shared.ready.notify_one();
+    }
+
+    /// How many jobs are queued and haven't been picked up by a worker yet.
+    pub fn queued(&self) -> usize {
+        self.shared.jobs.borrow().len()
+    }
+
+    /// How many jobs panicked so far.
+    pub fn panicked(&self) -> usize {
+        self.shared.panicked.get()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.closed.set(true);
+        self.shared.ready.notify_all();
+        for handle in self.workers.drain(..) {
+            handle.join();
+        }
+    }
+}
+
+impl Runtime {
+    /// Spawns a `WorkerPool` with `workers` workers on this runtime.
+    pub fn worker_pool(&mut self, workers: usize) -> WorkerPool {
+        if cfg!(feature = "sim") {
+            panic!("the sim backend runs every task on an OS thread of its own, workers can't find their pool there.");
+        }
+        let shared = Rc::new(Shared {
+            jobs: RefCell::new(VecDeque::new()),
+            ready: Event::new(),
+            closed: Cell::new(false),
+            panicked: Cell::new(0),
+        });
+        let workers = (0..workers)
+            .map(|_| {
+                let handle = self.spawn_named(worker, "pool worker");
+                POOLS.with(|pools| pools.borrow_mut().insert(handle.id(), shared.clone()));
+                handle
+            })
+            .collect();
+        WorkerPool { shared, workers }
+    }
+}
@@ -0,0 +1,96 @@
+//! Preemption, and keeping the scheduler safe from it.
+//!
+//! We never switch tasks from a signal handler. The handler could land anywhere: in the middle of `malloc`, or
+//! in `t_yield` while it's changing the task table, and a switch from there would leave it half done. So the
+//! only things a signal handler may do are `RuntimeHandle::unpark_from_signal` and `RuntimeHandle::preempt`,
+//! which set a flag or push onto a lock-free queue and never touch the runtime. The runtime looks at both at
+//! safe points: every time it yields, and at the checkpoints in `maybe_yield`.
+//!
+//! A task can turn the checkpoints off for a while with `disable`: as long as it holds the `NoPreempt` it gets
+//! back, `maybe_yield` never yields, and a preemption that was asked for in the meantime happens when it lets
+//! go. Yielding on purpose (`yield_now`, a lock, I/O...) still works.
+//!
+//! The scheduler itself calls code we don't control: `Scheduler::pick`, the budget handler. If one of those
+//! yields, `t_yield` would run again in the middle of `t_yield`, so we mark the time we spend in there and
+//! panic if that happens instead of corrupting the task table.
+use crate::{Runtime, RUNTIME};
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+thread_local! {
+    // how many `NoPreempt`s the running task holds
+    static DISABLED: Cell<usize> = const { Cell::new(0) };
+    // set while `t_yield` works on the task table
+    static SCHEDULING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Keeps `maybe_yield` from yielding until it's dropped. They nest, and they belong to the task that took
+/// them: another task that runs in the meantime can still be preempted.
+pub struct NoPreempt {
+    // it counts for the OS thread it was taken on
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Turns off preemption at checkpoints for the current task until the guard is dropped.
+pub fn disable() -> NoPreempt {
+    DISABLED.with(|disabled| disabled.set(disabled.get() + 1));
+    NoPreempt { _not_send: PhantomData }
+}
+
+/// Returns false while the current task holds a `NoPreempt`.
+pub fn is_enabled() -> bool {
+    DISABLED.with(|disabled| disabled.get() == 0)
+}
+
+impl Drop for NoPreempt {
+    fn drop(&mut self) {
+        let left = DISABLED.with(|disabled| {
+            disabled.set(disabled.get() - 1);
+            disabled.get()
+        });
+        if left == 0 {
+            unsafe {
+                if RUNTIME != 0 {
+                    let rt_ptr = RUNTIME as *mut Runtime;
+                    (*rt_ptr).t_preempt_if_requested();
+                }
+            }
+        }
+    }
+}
+
+/// How many `NoPreempt`s the running task holds. `t_yield` keeps it on the task's stack while other tasks run.
+pub(crate) fn depth() -> usize {
+    DISABLED.with(|disabled| disabled.get())
+}
+
+pub(crate) fn set_depth(depth: usize) {
+    DISABLED.with(|disabled| disabled.set(depth));
+}
+
+/// Marks that we're working on the task table until it's dropped.
+pub(crate) struct Scheduling(());
+
+impl Scheduling {
+    pub(crate) fn enter() -> Self {
+        if SCHEDULING.with(|scheduling| scheduling.replace(true)) {
+            panic!("a task yielded while the scheduler was running (from a `Scheduler` or a budget handler?).");
+        }
+        Scheduling(())
+    }
+}
+
+impl Drop for Scheduling {
+    fn drop(&mut self) {
+        SCHEDULING.with(|scheduling| scheduling.set(false));
+    }
+}
+
+impl Runtime {
+    /// Yields if somebody asked for it with `RuntimeHandle::preempt`.
+    pub(crate) fn t_preempt_if_requested(&mut self) {
+        if self.injector.preempt_requested() {
+            self.t_yield();
+        }
+    }
+}
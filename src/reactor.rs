@@ -0,0 +1,220 @@
+//! Our reactor. Tasks that want to read from or write to a file descriptor that isn't ready yet register
+//! it here and park. The runtime asks the reactor which file descriptors are ready whenever it gets a
+//! chance, and when it has nothing else to do it blocks the OS thread until one is.
+//!
+//! On Linux the reactor uses epoll directly (see `reactor/epoll.rs`). With the `mio` feature it sits on top
+//! of `mio::Poll` instead (see `reactor/mio_poll.rs`), which also knows kqueue, so the reactor and the `net`
+//! module work on macOS and the BSDs too.
+//!
+//! Besides sockets (see the `net` module) we have wrappers for timerfd and eventfd on Linux, so a task can also
+//! wait for a timer or for a signal from another OS thread with the same machinery.
+#[cfg(target_os = "linux")]
+use crate::sys;
+use crate::{Runtime, State, RUNTIME};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+#[cfg(not(feature = "mio"))]
+mod epoll;
+#[cfg(feature = "mio")]
+mod mio_poll;
+#[cfg(not(feature = "mio"))]
+pub(crate) use epoll::{Reactor, Waker};
+#[cfg(feature = "mio")]
+pub(crate) use mio_poll::{Reactor, Waker};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interest {
+    Read,
+    Write,
+}
+
+// the tasks waiting for a file descriptor to become readable or writable
+#[derive(Default)]
+struct Waiters {
+    read: Option<usize>,
+    write: Option<usize>,
+}
+
+impl Runtime {
+    /// Parks the current task until `fd` is ready for `interest`.
+    fn t_wait_io(&mut self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        let me = self.current;
+        self.reactor.register(fd, interest, me)?;
+        // `Reactor::poll` removes us before we're unparked, anything else is a spurious wakeup
+        while self.reactor.is_waiting(fd, interest, me) {
+            self.t_park();
+        }
+        Ok(())
+    }
+
+    /// Checks for ready file descriptors without blocking.
+    pub(crate) fn t_poll_io(&mut self) {
+        if !self.reactor.has_waiters() {
+            return;
+        }
+        for id in self.reactor.poll(Some(Duration::from_millis(0))) {
+            self.t_wake_io(id);
+        }
+    }
+
+    /// Blocks the OS thread until a file descriptor is ready or something gets injected.
+    pub(crate) fn t_wait_io_or_inject(&mut self) {
+        for id in self.reactor.poll(None) {
+            self.t_wake_io(id);
+        }
+    }
+
+    /// Unparks task `id` because what it waited for is ready, and asks the scheduler if it gets a boost.
+    fn t_wake_io(&mut self, id: usize) {
+        let parked = self.tasks[id].state == State::Parked;
+        self.t_unpark(id);
+        if parked && self.scheduler.woken_by_io(id) {
+            self.tasks[id].boosted = true;
+            self.io_boosts += 1;
+        }
+    }
+}
+
+/// Parks the current task until `fd` is readable.
+pub(crate) fn wait_readable(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_wait_io(fd, Interest::Read)
+    }
+}
+
+/// Parks the current task until `fd` is writable.
+pub(crate) fn wait_writable(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_wait_io(fd, Interest::Write)
+    }
+}
+
+pub(crate) fn deregister(fd: RawFd) {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).reactor.deregister(fd);
+    }
+}
+
+/// Runs `f` until it doesn't fail with `WouldBlock` anymore, parking the current task until `fd` is ready
+/// for `interest` in between. All our wrappers around non-blocking file descriptors are built on this.
+pub(crate) fn retry<T>(
+    fd: RawFd,
+    interest: Interest,
+    mut f: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    loop {
+        match f() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => match interest {
+                Interest::Read => wait_readable(fd)?,
+                Interest::Write => wait_writable(fd)?,
+            },
+            res => return res,
+        }
+    }
+}
+
+/// A timer the current task can wait for without blocking the other tasks (a Linux timerfd).
+#[cfg(target_os = "linux")]
+pub struct TimerFd {
+    fd: RawFd,
+}
+
+#[cfg(target_os = "linux")]
+impl TimerFd {
+    pub fn new() -> io::Result<Self> {
+        let flags = sys::TFD_CLOEXEC | sys::TFD_NONBLOCK;
+        let fd = sys::cvt(unsafe { sys::timerfd_create(sys::CLOCK_MONOTONIC, flags) })?;
+        Ok(TimerFd { fd })
+    }
+
+    /// Makes the timer expire after `after`, and then every `interval` if it's given. Setting
+    /// `after` to zero disarms the timer.
+    pub fn set(&self, after: Duration, interval: Option<Duration>) -> io::Result<()> {
+        let spec = sys::itimerspec {
+            it_interval: timespec(interval.unwrap_or_default()),
+            it_value: timespec(after),
+        };
+        sys::cvt(unsafe { sys::timerfd_settime(self.fd, 0, &spec, std::ptr::null_mut()) })
+            .map(|_| ())
+    }
+
+    /// Parks the current task until the timer expires. Returns how many times it expired since
+    /// the last call, which can be more than one for a periodic timer if we're late.
+    pub fn wait(&self) -> io::Result<u64> {
+        retry(self.fd, Interest::Read, || sys::read_u64(self.fd))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        deregister(self.fd);
+        unsafe { sys::close(self.fd) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn timespec(d: Duration) -> sys::timespec {
+    sys::timespec {
+        tv_sec: d.as_secs() as i64,
+        tv_nsec: d.subsec_nanos() as i64,
+    }
+}
+
+/// A counter tasks can wait on (a Linux eventfd). Unlike `sync::Event` it's a real file descriptor, so
+/// any OS thread (or another process it's passed to) can `notify` it directly. Only drop it on the
+/// runtime's thread though, since dropping it removes it from the reactor.
+#[cfg(target_os = "linux")]
+pub struct EventFd {
+    fd: RawFd,
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl Send for EventFd {}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for EventFd {}
+
+#[cfg(target_os = "linux")]
+impl EventFd {
+    pub fn new() -> io::Result<Self> {
+        let fd = sys::cvt(unsafe { sys::eventfd(0, sys::EFD_CLOEXEC | sys::EFD_NONBLOCK) })?;
+        Ok(EventFd { fd })
+    }
+
+    /// Adds `n` to the counter, which wakes the task waiting for it.
+    pub fn notify(&self, n: u64) -> io::Result<()> {
+        sys::eventfd_write(self.fd, n)
+    }
+
+    /// Parks the current task until the counter isn't zero, then returns it and resets it to zero.
+    pub fn wait(&self) -> io::Result<u64> {
+        retry(self.fd, Interest::Read, || sys::read_u64(self.fd))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        deregister(self.fd);
+        unsafe { sys::close(self.fd) };
+    }
+}
@@ -0,0 +1,239 @@
+//! The reactor on top of epoll, which we use on Linux unless the `mio` feature is on.
+//!
+//! We register every file descriptor with `EPOLLONESHOT`. That way the kernel only tells us about it once,
+//! and we only re-arm it when there's still a task waiting for it, which means we never have to deal with
+//! events nobody asked for.
+use super::{Interest, Waiters};
+use crate::sys;
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+// the token we use for our own eventfd which other OS threads write to when they inject something
+const WAKE_TOKEN: u64 = u64::MAX;
+// the token we use for the io_uring, which is readable when there are completions
+#[cfg(feature = "io-uring")]
+const URING_TOKEN: u64 = u64::MAX - 1;
+const MAX_EVENTS: usize = 64;
+
+/// Wakes the reactor up from another OS thread by writing to its eventfd.
+pub(crate) struct Waker(RawFd);
+
+impl Waker {
+    pub(crate) fn wake(&self) {
+        let _ = sys::eventfd_write(self.0, 1);
+    }
+}
+
+pub(crate) struct Reactor {
+    epoll: RawFd,
+    // an eventfd that the injector writes to so we wake up from `epoll_wait`
+    wake: RawFd,
+    waiters: HashMap<RawFd, Waiters>,
+    // `None` if the feature is on but the kernel doesn't support io_uring
+    #[cfg(feature = "io-uring")]
+    pub(crate) uring: Option<crate::uring::Uring>,
+}
+
+impl Reactor {
+    pub(crate) fn new() -> io::Result<Self> {
+        let epoll = sys::cvt(unsafe { sys::epoll_create1(sys::EPOLL_CLOEXEC) })?;
+        let wake = sys::cvt(unsafe { sys::eventfd(0, sys::EFD_CLOEXEC | sys::EFD_NONBLOCK) })?;
+        let mut event = sys::epoll_event {
+            events: sys::EPOLLIN,
+            data: WAKE_TOKEN,
+        };
+        sys::cvt(unsafe { sys::epoll_ctl(epoll, sys::EPOLL_CTL_ADD, wake, &mut event) })?;
+
+        #[cfg(feature = "io-uring")]
+        let uring = match crate::uring::Uring::new() {
+            Ok(uring) => {
+                let mut event = sys::epoll_event {
+                    events: sys::EPOLLIN,
+                    data: URING_TOKEN,
+                };
+                let fd = uring.fd();
+                sys::cvt(unsafe { sys::epoll_ctl(epoll, sys::EPOLL_CTL_ADD, fd, &mut event) })?;
+                Some(uring)
+            }
+            Err(_) => None,
+        };
+
+        Ok(Reactor {
+            epoll,
+            wake,
+            waiters: HashMap::new(),
+            #[cfg(feature = "io-uring")]
+            uring,
+        })
+    }
+
+    /// What other OS threads use to wake us up from `epoll_wait`.
+    pub(crate) fn waker(&self) -> Waker {
+        Waker(self.wake)
+    }
+
+    /// Returns true if any task is waiting for a file descriptor (or an io_uring operation).
+    pub(crate) fn has_waiters(&self) -> bool {
+        #[cfg(feature = "io-uring")]
+        {
+            if self.uring.as_ref().map_or(false, |u| u.in_flight() > 0) {
+                return true;
+            }
+        }
+        self.waiters
+            .values()
+            .any(|w| w.read.is_some() || w.write.is_some())
+    }
+
+    pub(crate) fn is_waiting(&self, fd: RawFd, interest: Interest, id: usize) -> bool {
+        match self.waiters.get(&fd) {
+            Some(w) => match interest {
+                Interest::Read => w.read == Some(id),
+                Interest::Write => w.write == Some(id),
+            },
+            None => false,
+        }
+    }
+
+    /// Registers task `id` as waiting for `fd`. Only one task can wait for each direction at a time.
+    pub(crate) fn register(&mut self, fd: RawFd, interest: Interest, id: usize) -> io::Result<()> {
+        let known = self.waiters.contains_key(&fd);
+        let waiters = self.waiters.entry(fd).or_default();
+        let slot = match interest {
+            Interest::Read => &mut waiters.read,
+            Interest::Write => &mut waiters.write,
+        };
+        if let Some(other) = *slot {
+            if other != id {
+                return Err(io::Error::other(format!(
+                    "task {} is already waiting for fd {}",
+                    other, fd
+                )));
+            }
+        }
+        let previous = slot.replace(id);
+        let op = if known { sys::EPOLL_CTL_MOD } else { sys::EPOLL_CTL_ADD };
+        let result = self.arm(fd, op);
+        if result.is_err() {
+            // Nobody is going to wait for `fd` after all (`epoll_ctl` refuses regular files for one). If we kept
+            // the waiter `has_waiters` would keep us waiting for an event that never comes.
+            if known {
+                let waiters = self.waiters.get_mut(&fd).unwrap();
+                match interest {
+                    Interest::Read => waiters.read = previous,
+                    Interest::Write => waiters.write = previous,
+                }
+            } else {
+                self.waiters.remove(&fd);
+            }
+        }
+        result
+    }
+
+    // (re-)arms `fd` for everything someone's waiting for
+    fn arm(&mut self, fd: RawFd, op: i32) -> io::Result<()> {
+        let waiters = &self.waiters[&fd];
+        let mut events = sys::EPOLLONESHOT;
+        if waiters.read.is_some() {
+            events |= sys::EPOLLIN | sys::EPOLLRDHUP;
+        }
+        if waiters.write.is_some() {
+            events |= sys::EPOLLOUT;
+        }
+        let mut event = sys::epoll_event {
+            events,
+            data: fd as u64,
+        };
+        sys::cvt(unsafe { sys::epoll_ctl(self.epoll, op, fd, &mut event) }).map(|_| ())
+    }
+
+    /// Forgets about everything task `id` waits for. Returns true if the kernel might still write to
+    /// memory the task gave it (an io_uring operation that hasn't completed yet).
+    pub(crate) fn forget_task(&mut self, id: usize) -> bool {
+        for waiters in self.waiters.values_mut() {
+            if waiters.read == Some(id) {
+                waiters.read = None;
+            }
+            if waiters.write == Some(id) {
+                waiters.write = None;
+            }
+        }
+        #[cfg(feature = "io-uring")]
+        {
+            if let Some(uring) = self.uring.as_mut() {
+                return uring.forget_task(id);
+            }
+        }
+        false
+    }
+
+    /// Forgets about `fd`. This has to be called before it's closed, since the kernel might reuse the
+    /// number for the next file descriptor we open.
+    pub(crate) fn deregister(&mut self, fd: RawFd) {
+        if self.waiters.remove(&fd).is_some() {
+            unsafe { sys::epoll_ctl(self.epoll, sys::EPOLL_CTL_DEL, fd, std::ptr::null_mut()) };
+        }
+    }
+
+    /// Waits up to `timeout` (forever if it's `None`) for file descriptors to become ready and returns
+    /// the ids of the tasks waiting for them.
+    pub(crate) fn poll(&mut self, timeout: Option<Duration>) -> Vec<usize> {
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        let mut events = [sys::epoll_event { events: 0, data: 0 }; MAX_EVENTS];
+        let n = loop {
+            let n = unsafe {
+                sys::epoll_wait(self.epoll, events.as_mut_ptr(), MAX_EVENTS as i32, timeout)
+            };
+            match sys::cvt(n) {
+                Ok(n) => break n as usize,
+                Err(e) if e.raw_os_error() == Some(sys::EINTR) => continue,
+                Err(e) => panic!("epoll_wait failed: {}", e),
+            }
+        };
+
+        let mut woken = vec![];
+        for event in &events[..n] {
+            let (flags, token) = (event.events, event.data);
+            if token == WAKE_TOKEN {
+                // we just needed to wake up, the injector itself tells us what happened
+                let _ = sys::read_u64(self.wake);
+                continue;
+            }
+            #[cfg(feature = "io-uring")]
+            {
+                if token == URING_TOKEN {
+                    woken.extend(self.uring.as_mut().map_or(vec![], |u| u.reap()));
+                    continue;
+                }
+            }
+            let fd = token as RawFd;
+            let waiters = match self.waiters.get_mut(&fd) {
+                Some(waiters) => waiters,
+                None => continue,
+            };
+            // errors and hangups wake both sides, the next read or write tells them what went wrong
+            let failed = flags & (sys::EPOLLERR | sys::EPOLLHUP) != 0;
+            if failed || flags & (sys::EPOLLIN | sys::EPOLLRDHUP) != 0 {
+                woken.extend(waiters.read.take());
+            }
+            if failed || flags & sys::EPOLLOUT != 0 {
+                woken.extend(waiters.write.take());
+            }
+            if waiters.read.is_some() || waiters.write.is_some() {
+                let _ = self.arm(fd, sys::EPOLL_CTL_MOD);
+            }
+        }
+        woken
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        unsafe {
+            sys::close(self.wake);
+            sys::close(self.epoll);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! The reactor on top of `mio::Poll` (the `mio` feature). Mio uses epoll on Linux and kqueue on macOS and the
+//! BSDs, so this is what gets our sockets going on those.
+//!
+//! Mio doesn't have `EPOLLONESHOT`, its events are edge triggered: we hear about a file descriptor when it
+//! becomes ready, not for as long as it is. That's all we need, since a task only waits after a call failed
+//! with `WouldBlock`. Every time a task registers we re-register the file descriptor, which makes mio check
+//! whether it's ready already, so we can't miss an edge that came between the `WouldBlock` and the register.
+use super::{Interest, Waiters};
+use mio::unix::SourceFd;
+use mio::{Events, Poll, Token};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+// the token of mio's waker, which other OS threads wake when they inject something
+const WAKE_TOKEN: Token = Token(usize::MAX);
+const MAX_EVENTS: usize = 64;
+
+/// Wakes the reactor up from another OS thread.
+pub(crate) struct Waker(Arc<mio::Waker>);
+
+impl Waker {
+    pub(crate) fn wake(&self) {
+        let _ = self.0.wake();
+    }
+}
+
+pub(crate) struct Reactor {
+    poll: Poll,
+    events: Events,
+    waker: Arc<mio::Waker>,
+    waiters: HashMap<RawFd, Waiters>,
+}
+
+impl Reactor {
+    pub(crate) fn new() -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(mio::Waker::new(poll.registry(), WAKE_TOKEN)?);
+        Ok(Reactor {
+            poll,
+            events: Events::with_capacity(MAX_EVENTS),
+            waker,
+            waiters: HashMap::new(),
+        })
+    }
+
+    /// What other OS threads use to wake us up from `Poll::poll`.
+    pub(crate) fn waker(&self) -> Waker {
+        Waker(self.waker.clone())
+    }
+
+    /// Returns true if any task is waiting for a file descriptor.
+    pub(crate) fn has_waiters(&self) -> bool {
+        self.waiters
+            .values()
+            .any(|w| w.read.is_some() || w.write.is_some())
+    }
+
+    pub(crate) fn is_waiting(&self, fd: RawFd, interest: Interest, id: usize) -> bool {
+        match self.waiters.get(&fd) {
+            Some(w) => match interest {
+                Interest::Read => w.read == Some(id),
+                Interest::Write => w.write == Some(id),
+            },
+            None => false,
+        }
+    }
+
+    /// Registers task `id` as waiting for `fd`. Only one task can wait for each direction at a time.
+    pub(crate) fn register(&mut self, fd: RawFd, interest: Interest, id: usize) -> io::Result<()> {
+        let known = self.waiters.contains_key(&fd);
+        let waiters = self.waiters.entry(fd).or_default();
+        let slot = match interest {
+            Interest::Read => &mut waiters.read,
+            Interest::Write => &mut waiters.write,
+        };
+        if let Some(other) = *slot {
+            if other != id {
+                return Err(io::Error::other(format!(
+                    "task {} is already waiting for fd {}",
+                    other, fd
+                )));
+            }
+        }
+        let previous = slot.replace(id);
+
+        // we always have at least the direction we just registered for
+        let interests = match (waiters.read.is_some(), waiters.write.is_some()) {
+            (true, true) => mio::Interest::READABLE | mio::Interest::WRITABLE,
+            (true, false) => mio::Interest::READABLE,
+            _ => mio::Interest::WRITABLE,
+        };
+        let registry = self.poll.registry();
+        let result = if known {
+            registry.reregister(&mut SourceFd(&fd), Token(fd as usize), interests)
+        } else {
+            registry.register(&mut SourceFd(&fd), Token(fd as usize), interests)
+        };
+        if result.is_err() {
+            // Same as with epoll: nobody waits for `fd` after all, and mio doesn't know it unless it knew it before
+            if known {
+                let waiters = self.waiters.get_mut(&fd).unwrap();
+                match interest {
+                    Interest::Read => waiters.read = previous,
+                    Interest::Write => waiters.write = previous,
+                }
+            } else {
+                self.waiters.remove(&fd);
+            }
+        }
+        result
+    }
+
+    /// Forgets about everything task `id` waits for. Unlike io_uring, mio never writes to memory a task gave
+    /// it, so this always returns false.
+    pub(crate) fn forget_task(&mut self, id: usize) -> bool {
+        for waiters in self.waiters.values_mut() {
+            if waiters.read == Some(id) {
+                waiters.read = None;
+            }
+            if waiters.write == Some(id) {
+                waiters.write = None;
+            }
+        }
+        false
+    }
+
+    /// Forgets about `fd`. This has to be called before it's closed, since the kernel might reuse the
+    /// number for the next file descriptor we open.
+    pub(crate) fn deregister(&mut self, fd: RawFd) {
+        if self.waiters.remove(&fd).is_some() {
+            let _ = self.poll.registry().deregister(&mut SourceFd(&fd));
+        }
+    }
+
+    /// Waits up to `timeout` (forever if it's `None`) for file descriptors to become ready and returns
+    /// the ids of the tasks waiting for them.
+    pub(crate) fn poll(&mut self, timeout: Option<Duration>) -> Vec<usize> {
+        loop {
+            match self.poll.poll(&mut self.events, timeout) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => panic!("polling for events failed: {}", e),
+            }
+        }
+
+        let mut woken = vec![];
+        for event in self.events.iter() {
+            if event.token() == WAKE_TOKEN {
+                // we just needed to wake up, the injector itself tells us what happened
+                continue;
+            }
+            let fd = event.token().0 as RawFd;
+            let waiters = match self.waiters.get_mut(&fd) {
+                Some(waiters) => waiters,
+                None => continue,
+            };
+            // errors and hangups wake both sides, the next read or write tells them what went wrong
+            let failed = event.is_error();
+            if failed || event.is_readable() || event.is_read_closed() {
+                woken.extend(waiters.read.take());
+            }
+            if failed || event.is_writable() || event.is_write_closed() {
+                woken.extend(waiters.write.take());
+            }
+        }
+        woken
+    }
+}
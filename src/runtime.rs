@@ -0,0 +1,1152 @@
+// The runtime we use on top of an operating system. `lib.rs` includes this file unless we're built with the
+// `static-alloc` feature, so everything in here lives at the root of the crate.
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub mod actor;
+mod batch;
+mod blocking;
+mod budget;
+pub mod channel;
+mod checkpoint;
+mod clock;
+pub mod coro;
+mod deadline;
+mod deadlock;
+mod debugger;
+pub mod fork;
+pub mod fs;
+pub mod futex;
+pub mod generator;
+mod group;
+mod handle;
+mod join;
+pub mod latency;
+mod migrate;
+pub mod monitor;
+#[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+pub mod net;
+mod overload;
+pub mod parallel;
+mod pool;
+pub mod preempt;
+#[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+pub mod reactor;
+pub mod scheduler;
+#[cfg(target_os = "linux")]
+pub mod signal;
+mod snapshot;
+mod stats;
+pub mod sync;
+#[cfg(target_os = "linux")]
+mod sys;
+pub mod tls;
+mod trace;
+#[cfg(target_os = "linux")]
+mod trim;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+pub mod workers;
+use arch::{switch, TaskContext};
+use blocking::BlockingPool;
+pub use budget::{BudgetExceeded, OverBudget};
+pub use checkpoint::maybe_yield;
+use checkpoint::Checkpoints;
+use clock::Clock;
+pub use deadline::DeadlineMiss;
+pub use deadlock::{Deadlock, WaitsFor};
+use futex::Futexes;
+use group::Groups;
+pub use group::TaskGroup;
+pub use handle::RuntimeHandle;
+use handle::{Injected, Injector};
+pub use join::JoinHandle;
+use latency::{Histogram, Latencies};
+pub use migrate::MigrateError;
+pub use overload::Overload;
+pub use pool::WorkerPool;
+use scheduler::{Candidate, RoundRobin, Scheduler};
+use stack::Stack;
+pub use stack::StackPolicy;
+pub use stats::{Stats, TaskState, TaskStats};
+use trace::{Event, Trace};
+use wake_queue::Woken;
+
+// In our simple example we set most constraints here.
+const DEFAULT_STACK_SIZE: usize = 1024 * 1024 * 2;
+const MAX_TASKS: usize = 4;
+const DEFAULT_PRIORITY: usize = 0;
+// Every OS thread can run its own runtime, so this is the runtime of the OS thread we're running on
+#[thread_local]
+static mut RUNTIME: usize = 0;
+
+pub struct Runtime {
+    tasks: Vec<Task>,
+    current: usize,
+    injector: Arc<Injector>,
+    // spawns injected through a handle while all tasks were in use
+    deferred: VecDeque<fn()>,
+    blocking: BlockingPool,
+    // ids of all `Available` tasks, spawn takes the next one from here
+    free: Vec<usize>,
+    // detached tasks that finished but haven't been moved to `free` yet
+    dead: Vec<usize>,
+    // called when we find a cycle of tasks waiting for each other
+    deadlock_handler: fn(&Deadlock),
+    // called when a task finishes after its deadline, see the `deadline` module
+    deadline_handler: fn(&DeadlineMiss),
+    deadlines_missed: u64,
+    // called when a task has used up its CPU time, see the `budget` module
+    budget_handler: fn(&BudgetExceeded) -> OverBudget,
+    budgets_exceeded: u64,
+    // the main task of `run_until` and its exit code once it has returned
+    main: Option<fn() -> i32>,
+    exit_code: Option<i32>,
+    // what `coro::sleep` and `coro::now` go by, see the `clock` module
+    clock: Clock,
+    // tasks waiting in `futex::wait_on`
+    futexes: Futexes,
+    // see the `group` module
+    groups: Groups,
+    // how we protect the stacks of new tasks, and the policy `spawn_with_stack_policy` spawns with
+    stack_policy: StackPolicy,
+    spawning_with: Option<StackPolicy>,
+    // see the `batch` module, `batched` are the tasks woken since the last yield
+    batch_wakes: bool,
+    batched: Vec<usize>,
+    // picks the next task in `t_yield`
+    scheduler: Box<dyn Scheduler>,
+    // reused by `t_yield` so we don't allocate on every switch
+    candidates: Vec<Candidate>,
+    // since when the current task has been running without being charged for it, see `t_account`
+    switched_in: Instant,
+    context_switches: u64,
+    // how many I/O wakeups the scheduler boosted
+    io_boosts: u64,
+    // when `maybe_yield` yields
+    checkpoints: Checkpoints,
+    // see `Runtime::enable_trace`
+    trace: Option<Trace>,
+    // see `Runtime::enable_latency_histograms`
+    latencies: Option<Latencies>,
+    // what `spawn` does when there's no free task, and the tasks waiting for one with `Overload::Wait`
+    overload: Overload,
+    spawn_waiters: VecDeque<usize>,
+    // lets a debugger find our tasks
+    debugger: debugger::Registration,
+    // waits for file descriptors, see the `reactor` module
+    #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+    reactor: reactor::Reactor,
+}
+
+/// What a new task starts in.
+#[derive(Clone, Copy)]
+enum Entry {
+    Plain(fn()),
+    // see `Runtime::spawn_arg`
+    WithArg(fn(usize), usize),
+}
+
+#[derive(PartialEq, Eq, Debug)]
+enum State {
+    Available,
+    Running,
+    Ready,
+    Parked,
+    Finished,
+}
+
+struct Task {
+    id: usize,
+    stack: Stack,
+    ctx: TaskContext,
+    state: State,
+    // set if someone unparked us while we weren't parked so the next park returns at once
+    unparked: bool,
+    // bumped every time we spawn a new task here so a `JoinHandle` can tell if it's still ours
+    generation: usize,
+    // nobody will join us, so we can be reaped as soon as we finish
+    detached: bool,
+    // the task waiting in `JoinHandle::join` for us to finish
+    joiner: Option<usize>,
+    // the priority we were spawned with, higher runs first
+    priority: usize,
+    // the priority we're scheduled with, can be higher than `priority` while we hold a mutex someone waits for
+    effective: usize,
+    // the mutexes we hold and the one we're waiting for, used for priority inheritance
+    held: Vec<*const sync::RawMutex>,
+    blocked_on: Option<*const sync::RawMutex>,
+    // the `sync::Event` or channel we're parked in, so we can be taken off its list when we're cancelled
+    waits_in: Option<sync::WaitList>,
+    // the task we're waiting for in `JoinHandle::join`
+    joining: Option<usize>,
+    // when the task should be done by in runtime time, see `Runtime::spawn_with_deadline`
+    deadline: Option<Duration>,
+    // how long we may run in total, see `Runtime::spawn_with_budget`. `None` again once we've run longer.
+    budget: Option<Duration>,
+    // what the budget handler decided once we ran out
+    cancelled: bool,
+    demoted: bool,
+    // the reactor woke us up and the scheduler wants us to run first, see `Scheduler::woken_by_io`
+    boosted: bool,
+    // the `TaskGroup` we're in, `None` once we're done
+    group: Option<usize>,
+    // how many `tls::MigrationGuard`s we hold, and our `tls::TaskLocal` values by the address of their key
+    pinned: usize,
+    // how many `tls::with` closures we're in
+    #[cfg(feature = "tls-check")]
+    tls_borrows: usize,
+    locals: Vec<(usize, Box<dyn std::any::Any + Send>)>,
+    // parked in `park_task`, where `Runtime::migrate` can move us to another runtime
+    movable: bool,
+    // see `Runtime::spawn_named`
+    name: Option<String>,
+    // see `Runtime::stats`
+    run_time: Duration,
+    scheduled: u64,
+    // since when we've been `Ready`, and how long we waited to run and for our switches, see the `latency` module
+    ready_since: Instant,
+    scheduling_latency: Histogram,
+    switch_time: Histogram,
+}
+
+impl Task {
+    fn new(id: usize) -> Self {
+        // We don't allocate the stack here. We do that the first time we spawn something on this
+        // task so a runtime with a lot of tasks doesn't use a lot of memory before it needs to.
+        // The important part is that once allocated it MUST NOT move in memory while the task is alive.
+        Task {
+            id,
+            stack: Stack::default(),
+            ctx: TaskContext::default(),
+            state: State::Available,
+            unparked: false,
+            generation: 0,
+            detached: false,
+            joiner: None,
+            priority: DEFAULT_PRIORITY,
+            effective: DEFAULT_PRIORITY,
+            held: vec![],
+            blocked_on: None,
+            waits_in: None,
+            joining: None,
+            deadline: None,
+            budget: None,
+            cancelled: false,
+            demoted: false,
+            boosted: false,
+            group: None,
+            pinned: 0,
+            #[cfg(feature = "tls-check")]
+            tls_borrows: 0,
+            locals: vec![],
+            movable: false,
+            name: None,
+            run_time: Duration::from_secs(0),
+            scheduled: 0,
+            ready_since: Instant::now(),
+            scheduling_latency: Histogram::new(),
+            switch_time: Histogram::new(),
+        }
+    }
+
+    /// Forgets everything about the last task that ran here but its stack and context, for a new one.
+    fn reset(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.unparked = false;
+        self.detached = false;
+        self.joiner = None;
+        self.priority = DEFAULT_PRIORITY;
+        self.effective = DEFAULT_PRIORITY;
+        self.held.clear();
+        self.blocked_on = None;
+        self.waits_in = None;
+        self.joining = None;
+        self.deadline = None;
+        self.budget = None;
+        self.cancelled = false;
+        self.demoted = false;
+        self.boosted = false;
+        self.group = None;
+        self.pinned = 0;
+        #[cfg(feature = "tls-check")]
+        {
+            self.tls_borrows = 0;
+        }
+        // A task that returns drops its locals in `t_return`, so these belong to a cancelled task and we leak them
+        // on purpose. We're running on another task now: a `Drop` that looks at `coro::current`, uses a
+        // `TaskLocal` or yields would do it as the wrong task, and nothing else of a cancelled task is dropped.
+        std::mem::forget(std::mem::take(&mut self.locals));
+        self.movable = false;
+        self.name = None;
+        self.run_time = Duration::from_secs(0);
+        self.scheduled = 0;
+        self.scheduling_latency = Histogram::new();
+        self.switch_time = Histogram::new();
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        // This will be our base task, which will be initialized in the `running` state. It runs on
+        // the stack of the OS thread that calls `run`, so it doesn't need a stack of its own.
+        let mut base_task = Task {
+            id: 0,
+            stack: Stack::default(),
+            ctx: TaskContext::default(),
+            state: State::Running,
+            unparked: false,
+            generation: 0,
+            detached: false,
+            joiner: None,
+            priority: DEFAULT_PRIORITY,
+            effective: DEFAULT_PRIORITY,
+            held: vec![],
+            blocked_on: None,
+            waits_in: None,
+            joining: None,
+            deadline: None,
+            budget: None,
+            cancelled: false,
+            demoted: false,
+            boosted: false,
+            group: None,
+            pinned: 0,
+            #[cfg(feature = "tls-check")]
+            tls_borrows: 0,
+            locals: vec![],
+            movable: false,
+            name: Some("base".to_string()),
+            run_time: Duration::from_secs(0),
+            scheduled: 0,
+            ready_since: Instant::now(),
+            scheduling_latency: Histogram::new(),
+            switch_time: Histogram::new(),
+        };
+        // we have no idea what the code that calls `run` does, so we always save its FP registers
+        arch::set_save_fp(&mut base_task.ctx, true);
+
+        // We initialize the rest of our tasks.
+        let mut tasks = vec![base_task];
+        let mut available_tasks: Vec<Task> = (1..MAX_TASKS).map(|i| Task::new(i)).collect();
+        tasks.append(&mut available_tasks);
+        let debugger = debugger::Registration::new(&tasks);
+
+        let injector = Injector::new();
+        #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+        let reactor = reactor::Reactor::new().expect("failed to create the reactor.");
+        #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+        let injector = injector.with_waker(reactor.waker());
+        let injector = Arc::new(injector);
+
+        Runtime {
+            tasks,
+            current: 0,
+            injector,
+            deferred: VecDeque::new(),
+            blocking: BlockingPool::new(),
+            free: (1..MAX_TASKS).rev().collect(),
+            dead: vec![],
+            deadlock_handler: deadlock::panic_on_deadlock,
+            deadline_handler: deadline::ignore_miss,
+            deadlines_missed: 0,
+            budget_handler: budget::cancel_over_budget,
+            budgets_exceeded: 0,
+            main: None,
+            exit_code: None,
+            clock: Clock::real(),
+            futexes: Futexes::default(),
+            groups: Groups::default(),
+            stack_policy: StackPolicy::default(),
+            spawning_with: None,
+            scheduler: Box::new(RoundRobin),
+            candidates: Vec::with_capacity(MAX_TASKS),
+            batch_wakes: false,
+            batched: Vec::with_capacity(MAX_TASKS),
+            switched_in: Instant::now(),
+            context_switches: 0,
+            io_boosts: 0,
+            checkpoints: Checkpoints::new(),
+            trace: None,
+            latencies: None,
+            overload: Overload::Panic,
+            spawn_waiters: VecDeque::new(),
+            debugger,
+            #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+            reactor,
+        }
+    }
+
+    /// Returns a handle that other OS threads can use to spawn new tasks on this runtime
+    /// or wake tasks that are parked.
+    pub fn handle(&self) -> RuntimeHandle {
+        RuntimeHandle {
+            injector: self.injector.clone(),
+        }
+    }
+
+    /// The number of spawned tasks that haven't finished yet (not counting our base task).
+    pub fn alive_count(&self) -> usize {
+        self.tasks[1..]
+            .iter()
+            .filter(|t| matches!(t.state, State::Ready | State::Running | State::Parked))
+            .count()
+    }
+
+    /// The number of spawned tasks waiting in the `Ready` state to get scheduled.
+    pub fn ready_count(&self) -> usize {
+        self.tasks[1..].iter().filter(|t| t.state == State::Ready).count()
+    }
+
+    /// This is cheating a bit, but we need a pointer to our Runtime stored so we can call yield on it even if
+    /// we don't have a reference to it.
+    pub fn init(&self) {
+        unsafe {
+            let r_ptr: *const Runtime = self;
+            RUNTIME = r_ptr as usize;
+        }
+    }
+
+    /// This is where we start running our runtime. If it is our base task, we call yield until
+    /// it returns false (which means that there are no tasks scheduled) and we are done.
+    ///
+    /// There is one exception. If some tasks are parked and something can still wake them (a file
+    /// descriptor they wait for or a handle held by another OS thread) we block the OS thread until that
+    /// happens and start over (see `t_wait_for_work`).
+    ///
+    /// Every time the base task gets its turn we also check if any file descriptors became ready, so
+    /// tasks waiting for I/O don't have to wait until everybody else is parked.
+    ///
+    /// It returns once there's nothing left to do, right away if nothing was spawned. The runtime is ready
+    /// for more: spawn some more tasks and call `run` again. Tasks that are parked with nothing left to wake
+    /// them stay parked, and keep their slots, until somebody unparks them in a later run.
+    pub fn run(&mut self) {
+        loop {
+            #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+            self.t_poll_io();
+
+            if self.t_yield() {
+                continue;
+            }
+            if !self.t_wait_for_work() {
+                break;
+            }
+        }
+    }
+
+    /// Lets the other tasks run until the scheduler picks the base task again and returns false if none of them
+    /// was ready. With `RoundRobin` and tasks that all have the same priority that's one turn for every ready
+    /// task, in the same order every time. It never blocks: it doesn't wait for I/O or for timers, but when no
+    /// task is ready it moves a virtual clock forward so sleeping tasks are ready for the next step.
+    ///
+    /// That makes it the tool for tests that want to check what happens one round at a time. It can only be
+    /// called from the base task, just like `run`.
+    pub fn step(&mut self) -> bool {
+        assert_eq!(self.current, 0, "only the base task can step the runtime.");
+        self.t_yield() || self.t_advance_clock()
+    }
+
+    /// Runs `main` as a task and returns its exit code as soon as it returns, without waiting for the other
+    /// tasks. That's what you want for a server that has workers running in the background: when `main`
+    /// decides it's time to stop, we stop.
+    ///
+    /// Every task still alive when `main` returns is cancelled. We can't unwind a task we're not running on,
+    /// so a cancelled task simply never gets scheduled again and its slot is reused. That means none of its
+    /// destructors run: anything it held (a `sync::Mutex`, a socket...) stays as it was.
+    pub fn run_until(&mut self, main: fn() -> i32) -> i32 {
+        self.main = Some(main);
+        self.exit_code = None;
+        let handle = self.spawn_named(run_main, "main");
+
+        while self.exit_code.is_none() {
+            #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+            self.t_poll_io();
+
+            if !self.t_yield() && !self.t_wait_for_work() {
+                panic!("the main task is parked and nothing can wake it.");
+            }
+        }
+
+        drop(handle);
+        self.t_cancel_all();
+        self.main = None;
+        self.exit_code.take().unwrap()
+    }
+
+    /// Frees every task but the base task no matter what state it's in, and forgets about spawns
+    /// injected through a handle that didn't get a task yet.
+    fn t_cancel_all(&mut self) {
+        self.deferred.clear();
+        self.dead.clear();
+        self.batched.clear();
+        self.spawn_waiters.clear();
+        for id in 1..self.tasks.len() {
+            if self.tasks[id].state == State::Available {
+                continue;
+            }
+            #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+            {
+                // If the kernel is still working on a request from this task it might write to its stack
+                // later, so we leak the stack instead of handing it to the next task.
+                if self.reactor.forget_task(id) {
+                    std::mem::forget(std::mem::take(&mut self.tasks[id].stack));
+                }
+            }
+            self.clock.forget(id);
+            self.futexes.forget(id);
+            self.t_forget_wait(id);
+            self.t_free(id);
+        }
+        self.t_forget_groups();
+    }
+
+    /// Called when there's no task ready to run. Blocks the OS thread until something happens that might
+    /// change that and returns true, or returns false right away if nothing can ever wake a parked task.
+    fn t_wait_for_work(&mut self) -> bool {
+        if self.injector.is_pending() {
+            return true;
+        }
+
+        // with a virtual clock, sleeping is just waiting for everyone else to be done
+        if self.t_advance_clock() {
+            return true;
+        }
+
+        self.t_check_deadlock();
+
+        // We've got nothing to do until a file descriptor is ready or another OS thread wakes us, so
+        // this is a good time to give the stacks of the tasks we're not using back to the allocator.
+        #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+        {
+            if self.reactor.has_waiters() {
+                self.t_release_stacks();
+                self.t_wait_io_or_inject();
+                self.switched_in = Instant::now();
+                return true;
+            }
+        }
+
+        let parked = self.tasks.iter().any(|t| t.state == State::Parked);
+        if !parked || Arc::strong_count(&self.injector) == 1 {
+            return false;
+        }
+
+        self.t_release_stacks();
+        // A signal handler can't wake the condition variable in `Injector::wait`, but it can wake the reactor.
+        #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+        self.t_wait_io_or_inject();
+        #[cfg(not(any(target_os = "linux", all(unix, feature = "mio"))))]
+        self.injector.wait();
+        // the time we spent waiting doesn't count as running
+        self.switched_in = Instant::now();
+        true
+    }
+
+    /// This is our return function. The only place we use this is in our `guard` function.
+    /// If the current task is not our base task we set its state to Finished. It means
+    /// we're finished with it. Then we yield which will schedule a new task to be run.
+    ///
+    /// We can't make it `Available` right away. We're still running on its stack, and if a new task
+    /// got spawned on it before we switch away (which can happen when `t_yield` drains the injector)
+    /// `switch` would save our registers on top of the new task's context. So detached tasks go on
+    /// the `dead` list which the next `t_yield` cleans up (see `t_reap`). Tasks that have a
+    /// `JoinHandle` stay `Finished` until they're joined or the handle is dropped.
+    fn t_return(&mut self) {
+        if self.current != 0 {
+            let id = self.current;
+            // we're still running, so their `Drop` can do whatever it likes
+            drop(std::mem::take(&mut self.tasks[id].locals));
+            self.t_check_deadline(id);
+            self.t_finish(id);
+            self.t_yield();
+        }
+    }
+
+    /// Marks task `id` as `Finished` and wakes up whoever waits for it. Usually that's the task we're running
+    /// on, `t_cancel` also uses it for tasks that never ran.
+    fn t_finish(&mut self, id: usize) {
+        self.tasks[id].state = State::Finished;
+        self.t_trace(Event::Finish(id));
+        if let Some(joiner) = self.tasks[id].joiner.take() {
+            self.t_unpark(joiner);
+        }
+        if self.tasks[id].detached {
+            self.dead.push(id);
+        }
+        self.t_leave_group(id);
+    }
+
+    /// Cancels task `id`, which isn't the one we're running on. A task that never ran finishes right away, any
+    /// other task is cancelled by `t_cancel_if_requested` when it runs next, so we wake it up if it's parked.
+    fn t_cancel(&mut self, id: usize) {
+        let task = &mut self.tasks[id];
+        if task.state == State::Available || task.state == State::Finished {
+            return;
+        }
+        task.cancelled = true;
+        if task.scheduled == 0 {
+            self.t_finish(id);
+        } else if task.state == State::Parked {
+            self.t_unpark(id);
+        }
+    }
+
+    /// Cancels the current task if someone asked for it (an exceeded budget or a `TaskGroup`) and it's somewhere
+    /// we can leave it: running (not on its way to park), not holding a lock and not waiting for one. Never
+    /// returns if it does.
+    fn t_cancel_if_requested(&mut self) {
+        let task = &self.tasks[self.current];
+        if task.cancelled && task.state == State::Running && task.held.is_empty() && task.blocked_on.is_none() {
+            self.t_cancel_current();
+        }
+    }
+
+    /// Cancels the task we're running on. We forget everything it waits for, the same as `t_cancel_all` does,
+    /// and then it finishes like it returned.
+    fn t_cancel_current(&mut self) {
+        let id = self.current;
+        #[cfg(any(target_os = "linux", all(unix, feature = "mio")))]
+        {
+            if self.reactor.forget_task(id) {
+                std::mem::forget(std::mem::take(&mut self.tasks[id].stack));
+            }
+        }
+        self.clock.forget(id);
+        self.futexes.forget(id);
+        self.t_forget_wait(id);
+        self.t_forget_spawn_wait(id);
+        self.t_forget_group_wait(id);
+        // or the task we joined would unpark whatever task gets our slot next when it finishes
+        if let Some(joined) = self.tasks[id].joining.take() {
+            if self.tasks[joined].joiner == Some(id) {
+                self.tasks[joined].joiner = None;
+            }
+        }
+        self.t_return();
+    }
+
+    /// Our reaper. Moves every task on the `dead` list that we're not currently running on to the
+    /// freelist so `spawn` can use it again.
+    fn t_reap(&mut self) {
+        if self.dead.is_empty() {
+            return;
+        }
+        let current = self.current;
+        let reaped: Vec<usize> = self.dead.iter().copied().filter(|&id| id != current).collect();
+        self.dead.retain(|&id| id == current);
+        for id in reaped {
+            self.t_free(id);
+        }
+    }
+
+    fn t_free(&mut self, id: usize) {
+        self.tasks[id].state = State::Available;
+        self.debugger.update(&self.tasks[id]);
+        self.free.push(id);
+        self.t_slot_freed();
+    }
+
+    /// Drops the stacks of all available tasks. `spawn` allocates a new one when it needs it.
+    fn t_release_stacks(&mut self) {
+        for &id in &self.free {
+            self.tasks[id].stack = Stack::default();
+        }
+    }
+
+    /// Called when a `JoinHandle` is dropped. If the task already finished we can free it right away,
+    /// if not we mark it as detached so it's reaped as soon as it finishes.
+    fn t_detach(&mut self, id: usize, generation: usize) {
+        let task = match self.tasks.get_mut(id) {
+            Some(task) => task,
+            None => return,
+        };
+        if task.generation != generation {
+            return;
+        }
+        match task.state {
+            State::Available => (),
+            State::Finished => self.t_free(id),
+            _ => task.detached = true,
+        }
+    }
+
+    /// Parks the current task until the task with the given id finishes, and then frees it.
+    fn t_join(&mut self, id: usize, generation: usize) {
+        assert_ne!(id, self.current, "a task can't join itself.");
+        loop {
+            let task = match self.tasks.get_mut(id) {
+                Some(task) => task,
+                // the handle of a spawn that was rejected, see `Overload::Call`
+                None => return,
+            };
+            if task.generation != generation || task.state == State::Available {
+                return;
+            }
+            if task.state == State::Finished {
+                break;
+            }
+            task.joiner = Some(self.current);
+            self.tasks[self.current].joining = Some(id);
+            self.t_park();
+            self.tasks[self.current].joining = None;
+        }
+        self.t_free(id);
+    }
+
+    /// This is the heart of our runtime. Here we go through all tasks and collect the ones that could run: every
+    /// task in the `Ready` state, and the current task if it's still running. If there are none, we're all done.
+    /// Which one runs next is up to our scheduler (see the `scheduler` module). The default one picks the task
+    /// with the highest priority, and if several tasks have the same priority the first one after the current
+    /// task, which gives us a round-robin algorithm.
+    ///
+    /// If the scheduler picks another task we change the state of the current task from `Running` to `Ready`.
+    /// Then we call switch which will save the current context (the old context) and load the new context
+    /// into the CPU which then resumes based on the context it was just passed.
+    ///
+    /// Before we look for a task to run we handle everything injected from other OS threads
+    /// through a `RuntimeHandle` since that might make more tasks `Ready`.
+    fn t_yield(&mut self) -> bool {
+        #[cfg(feature = "tls-check")]
+        self.t_check_tls();
+        let scheduling = preempt::Scheduling::enter();
+        self.t_reap();
+        self.drain_injector();
+        self.t_wake_batched();
+        self.t_account();
+        // cancelling switches away for good, which is the end of our time in the scheduler too
+        drop(scheduling);
+        self.t_cancel_if_requested();
+        let scheduling = preempt::Scheduling::enter();
+
+        // We start right after the current task and end with the current task itself
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.clear();
+        let mut pos = self.current;
+        for _ in 0..self.tasks.len() {
+            pos += 1;
+            if pos == self.tasks.len() {
+                pos = 0;
+            }
+            let task = &self.tasks[pos];
+            if task.state == State::Ready || (pos == self.current && task.state == State::Running) {
+                candidates.push(Candidate {
+                    id: pos,
+                    priority: task.effective,
+                    deadline: task.deadline,
+                    boosted: task.boosted,
+                });
+            }
+        }
+        self.t_skip_demoted(&mut candidates);
+        // tasks the reactor just woke up and the scheduler boosted go first
+        if candidates.iter().any(|c| c.boosted) {
+            candidates.retain(|c| c.boosted);
+        }
+        // the current task running on its own doesn't count, `false` means nothing else can run
+        let ready = candidates.iter().any(|c| self.tasks[c.id].state == State::Ready);
+        let next = if ready {
+            Some(self.scheduler.pick(self.current, &candidates))
+        } else {
+            None
+        };
+        self.candidates = candidates;
+
+        let pos = match next {
+            Some(pos) => pos,
+            None => return false,
+        };
+
+        // The current task keeps running, or it was unparked while draining the injector. Either
+        // way there's no reason to switch at all, we just keep on running it.
+        self.tasks[pos].boosted = false;
+        if self.tasks[pos].state == State::Ready {
+            self.t_picked(pos);
+        }
+        if pos == self.current {
+            self.tasks[pos].state = State::Running;
+            return true;
+        }
+
+        if self.tasks[self.current].state == State::Running {
+            self.tasks[self.current].state = State::Ready;
+            self.t_ready(self.current);
+        }
+
+        self.t_check_stack(self.current);
+
+        let old_pos = self.current;
+        self.context_switches += 1;
+        self.tasks[pos].scheduled += 1;
+
+        self.tasks[pos].state = State::Running;
+        self.current = pos;
+        self.t_trace(Event::Switch { from: old_pos, to: pos });
+
+        // The `NoPreempt`s we hold wait on our stack while the others run, a task starts out without any.
+        let no_preempt = preempt::depth();
+        preempt::set_depth(0);
+        drop(scheduling);
+        self.t_switching();
+        unsafe {
+            switch(&mut self.tasks[old_pos].ctx, &self.tasks[pos].ctx);
+        }
+        preempt::set_depth(no_preempt);
+        // We might have been cancelled while we were away, or moved to another runtime (see `Runtime::migrate`),
+        // then `self` isn't ours anymore and we have to look it up again.
+        let rt = unsafe { &mut *(RUNTIME as *mut Runtime) };
+        rt.t_switched();
+        rt.t_cancel_if_requested();
+
+        // NOTE: this might look strange and it is. Normally we would just mark this as `unreachable!()` but our compiler
+        // is too smart for it's own good so it optimized our code away on release builds. Curiously this happens on windows
+        // and not on linux. This is a common problem in tests so Rust has a `black_box` function in the `test` crate that
+        // will "pretend" to use a value we give it to prevent the compiler from eliminating code. I'll just do this instead,
+        // this code will never be run anyways and if it did it would always be `true`.
+        rt.tasks.len() > 0
+    }
+
+    /// Tells the scheduler how long the current task has been running since we last checked. We do this every
+    /// time the task yields, not just when we switch away from it, or a task that keeps getting picked would
+    /// never be charged for it.
+    fn t_account(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.switched_in;
+        self.switched_in = now;
+        self.checkpoints.reset();
+        let task = &mut self.tasks[self.current];
+        task.run_time += elapsed;
+        self.scheduler.ran(self.current, task.effective, elapsed);
+        self.t_check_budget();
+    }
+
+    /// Replaces the scheduler that picks the next task to run (`scheduler::RoundRobin` by default).
+    pub fn set_scheduler(&mut self, scheduler: Box<dyn Scheduler>) {
+        self.scheduler = scheduler;
+    }
+
+    /// How the stacks of the tasks we spawn from now on are protected, `StackPolicy::Canary` by default. Panics if
+    /// `policy` needs guard pages and we're not on Linux.
+    pub fn set_stack_policy(&mut self, policy: StackPolicy) {
+        if policy.has_guard_page() && !cfg!(target_os = "linux") {
+            panic!("guard pages are only supported on Linux, use `StackPolicy::Canary`.");
+        }
+        self.stack_policy = policy;
+    }
+
+    /// Panics if the task we're about to switch away from has overwritten the canary at the bottom of its
+    /// stack (see `StackPolicy::Canary`). If it did, it has most likely written past the end of its stack as
+    /// well and we can't trust anything in memory anymore, so there's no point in trying to continue.
+    fn t_check_stack(&self, id: usize) {
+        let stack = &self.tasks[id].stack;
+        // our base task runs on the stack of the OS thread which has its own protection
+        if !stack.is_empty() && !stack::intact(stack, stack.policy()) {
+            panic!("stack overflow detected: task {} overwrote its stack canary.", id);
+        }
+    }
+
+    /// Parks the current task. It won't be scheduled again until someone unparks it. If we
+    /// were unparked before we got here we return right away and consume that wakeup.
+    ///
+    /// Only the base task can end up with nothing else to run while it's parked (any other task
+    /// always has the base task to go back to), in that case we block the OS thread until
+    /// something can wake us. If nothing can, we'd wait forever, so we panic instead.
+    fn t_park(&mut self) {
+        #[cfg(feature = "tls-check")]
+        self.t_check_tls();
+        if self.tasks[self.current].unparked {
+            self.tasks[self.current].unparked = false;
+            return;
+        }
+
+        self.tasks[self.current].state = State::Parked;
+        self.t_trace(Event::Park(self.current));
+        // the runtime we wake up on, which isn't this one if we were parked in `park_task` and got migrated
+        let mut rt = self as *mut Runtime;
+        unsafe {
+            while (*rt).t_is_parked() {
+                if !(*rt).t_yield() && !(*rt).t_wait_for_work() {
+                    panic!("task {} is parked and nothing can wake it.", (*rt).current);
+                }
+                rt = RUNTIME as *mut Runtime;
+            }
+            (*rt).t_cancel_if_requested();
+        }
+    }
+
+    fn t_is_parked(&self) -> bool {
+        self.tasks[self.current].state == State::Parked
+    }
+
+    /// Makes a parked task `Ready`. If it's running or ready we remember the wakeup instead.
+    fn t_unpark(&mut self, id: usize) {
+        if let Some(task) = self.tasks.get_mut(id) {
+            match task.state {
+                State::Parked => {
+                    task.state = State::Ready;
+                    self.t_ready(id);
+                    self.t_trace(Event::Wake(id));
+                }
+                State::Available | State::Finished => (),
+                _ => task.unparked = true,
+            }
+        }
+    }
+
+    /// Sends `f` off to our blocking pool and parks the current task until it's done. The job
+    /// holds a `RuntimeHandle` which it uses to wake us up again when the result is ready.
+    ///
+    /// If `f` panics we catch it on the pool thread (so the thread survives) and resume the
+    /// panic here instead, just like `JoinHandle::join` would report it for a normal thread.
+    fn t_spawn_blocking<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let result = Arc::new(Mutex::new(None));
+        let slot = result.clone();
+        let handle = self.handle();
+        let id = self.current;
+
+        self.blocking.execute(Box::new(move || {
+            let res = panic::catch_unwind(AssertUnwindSafe(f));
+            *slot.lock().unwrap() = Some(res);
+            handle.unpark(id);
+        }));
+
+        loop {
+            if let Some(res) = result.lock().unwrap().take() {
+                match res {
+                    Ok(r) => return r,
+                    Err(e) => panic::resume_unwind(e),
+                }
+            }
+            self.t_park();
+        }
+    }
+
+    /// Handles everything other OS threads have sent us. If we're asked to spawn a task and all
+    /// our tasks are in use we keep the request around and retry when a task becomes available.
+    fn drain_injector(&mut self) {
+        for item in self.injector.take() {
+            match item {
+                Injected::Spawn(f) => self.deferred.push_back(f),
+                Injected::Unpark(id) => self.t_unpark(id),
+                Injected::Notify(event, all) => self.t_notify(event, all),
+            }
+        }
+        while let Some(woken) = self.injector.take_wake() {
+            match woken {
+                Woken::Key(id) => self.t_unpark(id),
+                Woken::Everyone => {
+                    for id in 0..self.tasks.len() {
+                        if self.tasks[id].state == State::Parked {
+                            self.t_unpark(id);
+                        }
+                    }
+                }
+            }
+        }
+        // whoever asked for it gets what they wanted, we're yielding
+        self.injector.take_preempt();
+
+        // nobody can join a task spawned through a handle, so they're all detached
+        while !self.deferred.is_empty() && !self.free.is_empty() {
+            let f = self.deferred.pop_front().unwrap();
+            let id = self.t_spawn(Entry::Plain(f), self.stack_policy);
+            self.tasks[id].detached = true;
+        }
+    }
+
+    /// While `yield` is the logically interesting function I think this the technically most interesting.
+    ///
+    /// When we spawn a new task we take the next available task from our freelist. If we run out of tasks we
+    /// panic by default, `on_overload` can make us wait for a task to finish instead (see the `overload` module).
+    /// If the task doesn't have a stack (we drop them when we're idle) we allocate a new one.
+    ///
+    /// When we find an available task we hand its stack and context over to `arch::init_task`. How
+    /// the stack needs to look differs between CPU architectures (and calling conventions), but it
+    /// always does the same thing: it makes sure we start executing the function we pass inn when we
+    /// are scheduled to run, and that our `guard` function gets called if that function returns.
+    ///
+    /// Lastly we set the state as `Ready` which means we have work to do and is ready to do it.
+    ///
+    /// We return a `JoinHandle` which can be used to wait for the task to finish. If you drop it the task
+    /// is detached, which means it's reaped as soon as it finishes.
+    pub fn spawn(&mut self, f: fn()) -> JoinHandle {
+        self.spawn_with_priority(f, DEFAULT_PRIORITY)
+    }
+
+    /// Same as `spawn` but the task gets the given priority. With the default scheduler tasks with a higher
+    /// priority always run before tasks with a lower priority, so a task with a high priority that never parks
+    /// will starve the rest. `scheduler::Fair` gives them a bigger share of CPU time instead.
+    pub fn spawn_with_priority(&mut self, f: fn(), priority: usize) -> JoinHandle {
+        self.t_spawn_with(f, |task| {
+            task.priority = priority;
+            task.effective = priority;
+        })
+    }
+
+    /// Same as `spawn` but the task gets a name. It shows up in the `Debug` output of the runtime, in `Stats` and
+    /// in `coro::current().name()`, which beats trying to remember which task number 3 is.
+    pub fn spawn_named(&mut self, f: fn(), name: &str) -> JoinHandle {
+        self.t_spawn_with(f, |task| task.name = Some(name.to_string()))
+    }
+
+    /// Same as `spawn` but switching to and from the task skips the callee saved floating point registers,
+    /// which makes every context switch a bit cheaper (see `benches/switch.rs`). Only use it for tasks that
+    /// never use floating point: if the task keeps an FP value in one of these registers while it yields, it
+    /// comes back with whatever the last task left there.
+    pub fn spawn_without_fp(&mut self, f: fn()) -> JoinHandle {
+        self.t_spawn_with(f, |task| arch::set_save_fp(&mut task.ctx, false))
+    }
+
+    /// Same as `spawn` but the task's stack is protected the way `policy` says instead of the runtime's default
+    /// (see `set_stack_policy`). That way the one task that recurses deeply can get a guard page while the rest
+    /// keep their canaries.
+    pub fn spawn_with_stack_policy(&mut self, f: fn(), policy: StackPolicy) -> JoinHandle {
+        self.spawning_with = Some(policy);
+        self.spawn(f)
+    }
+
+    /// Spawns a task that starts in `f(arg)`. A task can't capture anything, so this is how a bunch of
+    /// tasks running the same function tell each other apart: give each one an id, an index, or a pointer to
+    /// what it should work on. We don't stash `arg` anywhere, it sits in the new task's context until the
+    /// entry trampoline hands it to `f` in the first argument register.
+    pub fn spawn_arg(&mut self, f: fn(usize), arg: usize) -> JoinHandle {
+        self.t_spawn_entry(Entry::WithArg(f, arg), |_| ())
+    }
+
+    /// Same as `spawn_arg` for arguments that don't fit in a `usize`. `value` is boxed, and the box is what
+    /// the task gets as its argument. If the task is cancelled before it gets to run the box is leaked, if the
+    /// spawn is rejected (see `Overload::Call`) it's dropped right away.
+    pub fn spawn_with<T: Send + 'static>(&mut self, f: fn(T), value: T) -> JoinHandle {
+        let boxed = Box::into_raw(Box::new((f, value)));
+        let handle = self.spawn_arg(call_boxed::<T>, boxed as usize);
+        if handle.id() == usize::MAX {
+            drop(unsafe { Box::from_raw(boxed) });
+        }
+        handle
+    }
+
+    /// Spawns `f` and lets `setup` change the task before it first runs. What every `spawn_*` method uses, so
+    /// they all wait for a free task (or don't) the same way.
+    fn t_spawn_with(&mut self, f: fn(), setup: impl FnOnce(&mut Task)) -> JoinHandle {
+        self.t_spawn_entry(Entry::Plain(f), setup)
+    }
+
+    fn t_spawn_entry(&mut self, entry: Entry, setup: impl FnOnce(&mut Task)) -> JoinHandle {
+        // a task spawned by a member of a group is a member too, unless it's spawned into a group of its own
+        let group = self.groups.spawning_into.take().or(self.tasks[self.current].group);
+        let policy = self.spawning_with.take().unwrap_or(self.stack_policy);
+        if !self.t_make_room(entry) {
+            return JoinHandle::rejected();
+        }
+        let id = self.t_spawn(entry, policy);
+        self.t_join_group(id, group);
+        setup(&mut self.tasks[id]);
+        self.debugger.update(&self.tasks[id]);
+        JoinHandle::new(id, self.tasks[id].generation)
+    }
+
+    fn t_spawn(&mut self, entry: Entry, policy: StackPolicy) -> usize {
+        let id = self.free.pop().expect("no available task.");
+        let available = &mut self.tasks[id];
+        if available.stack.is_empty() || available.stack.policy() != policy {
+            available.stack = Stack::new(DEFAULT_STACK_SIZE, policy);
+        }
+        stack::prepare(&mut available.stack, policy);
+
+        unsafe {
+            match entry {
+                Entry::Plain(f) => arch::init_task(&mut available.ctx, &mut available.stack, f, guard),
+                Entry::WithArg(f, arg) => {
+                    arch::init_task_with_arg(&mut available.ctx, &mut available.stack, f, arg, guard)
+                }
+            }
+        }
+        arch::set_save_fp(&mut available.ctx, true);
+        available.reset();
+        available.state = State::Ready;
+        self.t_ready(id);
+        self.debugger.update(&self.tasks[id]);
+        self.t_trace(Event::Spawn(id));
+        self.scheduler.spawned(id);
+        id
+    }
+}
+
+/// This is our guard function. The entry trampoline in the `arch` module calls it when the function
+/// we spawned returns. All this function does is set the state of our current task and then `yield`
+/// which will then schedule a new task to be run.
+fn guard() {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_return();
+    };
+}
+
+/// The function behind `spawn_with`: `arg` is the box with the function and its argument.
+fn call_boxed<T>(arg: usize) {
+    let (f, value) = *unsafe { Box::from_raw(arg as *mut (fn(T), T)) };
+    f(value);
+}
+
+/// The task `run_until` spawns. It runs the main function we were given and stores its exit code.
+fn run_main() {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        let main = (*rt_ptr).main.expect("no main task to run.");
+        let code = main();
+        (*rt_ptr).exit_code = Some(code);
+    };
+}
+
+/// We know that Runtime is alive the length of the program and that we only access from one core
+/// (so no datarace). We yield execution of the current task  by dereferencing a pointer to our
+/// Runtime and then calling `t_yield`
+pub fn yield_task() {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_yield();
+    };
+}
+
+/// Parks the current task until someone calls `unpark` with its id through a `RuntimeHandle`.
+/// Like `std::thread::park` this can return spuriously, so always check the condition you're
+/// waiting for in a loop.
+pub fn park_task() {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_set_movable(true);
+        (*rt_ptr).t_park();
+        // we might be on another runtime now
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_set_movable(false);
+    };
+}
+
+/// Runs `f` on a separate OS thread and parks the current task until it returns. Use this for
+/// anything that blocks (file I/O, `thread::sleep`, DNS lookups...). If you call it directly
+/// from a task, the whole runtime stops until the call returns since we only have one OS thread.
+/// While we wait, all other tasks keep running.
+pub fn spawn_blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_spawn_blocking(f)
+    }
+}
+
+/// Returns the id of the task that's currently running. This is the id you pass to
+/// `RuntimeHandle::unpark`.
+pub fn task_id() -> usize {
+    unsafe {
+        let rt_ptr = RUNTIME as *const Runtime;
+        (*rt_ptr).current
+    }
+}
@@ -0,0 +1,165 @@
+//! The part of the runtime that decides which task runs next. `t_yield` collects every task that could run and
+//! asks the scheduler to pick one, everything else (states, switching, bookkeeping) stays in the runtime.
+//!
+//! We have three of them:
+//!
+//! - `RoundRobin` (the default) always runs the task with the highest priority and takes turns among tasks
+//!   with the same priority. Simple and predictable, but a busy high priority task starves everyone else.
+//! - `Fair` works like Linux's CFS: it keeps track of how much CPU time every task got, scaled by a weight
+//!   that depends on its priority (its "virtual runtime"), and always runs the task that got the least.
+//!   Every task makes progress, higher priorities just get a bigger share.
+//! - `Edf` (earliest deadline first) always runs the task whose deadline is closest, the classic real-time
+//!   policy. Tasks without a deadline only run when no task with a deadline can.
+//!
+//! Use `Runtime::set_scheduler` to pick one and `Runtime::stats` to see what difference it makes.
+//!
+//! A scheduler also decides whether a task the reactor wakes up gets a boost (see `Scheduler::woken_by_io`): a
+//! boosted task runs before every task that isn't, so a connection that has data waiting doesn't queue up behind
+//! tasks that are busy computing. `RoundRobin` and `Fair` boost every I/O wakeup, `Edf` none.
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A task that could run next.
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    pub id: usize,
+    /// The priority the task runs with right now (it can be higher than the one it was spawned with
+    /// while it holds a mutex someone important waits for).
+    pub priority: usize,
+    /// When the task should be done by, if it was spawned with `Runtime::spawn_with_deadline`.
+    pub deadline: Option<Duration>,
+    /// The reactor woke the task up and the scheduler boosted it, see `Scheduler::woken_by_io`. The runtime only
+    /// passes boosted candidates to `pick` while there are any, so this is for schedulers that want to know.
+    pub boosted: bool,
+}
+
+pub trait Scheduler {
+    /// Picks the next task among `candidates`, which is never empty. They're ordered the way round-robin
+    /// would visit them: starting right after `current` and ending with `current` itself if it can keep
+    /// running.
+    fn pick(&mut self, current: usize, candidates: &[Candidate]) -> usize;
+
+    /// Called when a new task is spawned with id `id`.
+    fn spawned(&mut self, _id: usize) {}
+
+    /// Called every time a task yields (or parks, or finishes) with how long it ran since the last call.
+    fn ran(&mut self, _id: usize, _priority: usize, _elapsed: Duration) {}
+
+    /// Called when the reactor wakes task `id` up because the file descriptor it waited for is ready (or its
+    /// io_uring operation completed). Return true to boost it: until it has run, it goes before every task that
+    /// isn't boosted. By default every I/O wakeup is boosted.
+    fn woken_by_io(&mut self, _id: usize) -> bool {
+        true
+    }
+}
+
+/// Runs the task with the highest priority. If several have the same priority we take the first one,
+/// and since the candidates start right after the current task that's round-robin. The current task comes
+/// last, so it only keeps running if it's more important than everyone else.
+#[derive(Debug, Default)]
+pub struct RoundRobin;
+
+impl Scheduler for RoundRobin {
+    fn pick(&mut self, _current: usize, candidates: &[Candidate]) -> usize {
+        let mut best = candidates[0];
+        for &c in &candidates[1..] {
+            if c.priority > best.priority {
+                best = c;
+            }
+        }
+        best.id
+    }
+}
+
+// The weight of a task with priority 0. Every priority level is worth 25% more CPU time than the one below,
+// which is the same ratio Linux uses between two nice levels.
+const BASE_WEIGHT: f64 = 1024.0;
+const WEIGHT_STEP: f64 = 1.25;
+// How much of a head start a task that has been parked for a while gets over the others
+const WAKEUP_CREDIT: Duration = Duration::from_millis(3);
+
+/// A fair scheduler in the style of CFS. Every task has a virtual runtime: the time it has been running,
+/// divided by its weight. We always run the task with the smallest virtual runtime, so over time every
+/// task gets CPU time in proportion to its weight.
+///
+/// A task that has been parked doesn't collect virtual runtime, so when it wakes up it would get to run
+/// until it caught up with everyone else. Like CFS we don't allow that: it starts at most
+/// `WAKEUP_CREDIT` behind the task that has run the least.
+#[derive(Debug, Default)]
+pub struct Fair {
+    // virtual runtime in nanoseconds by task id
+    vruntime: HashMap<usize, u64>,
+    // the smallest virtual runtime of any runnable task, it never goes back
+    min_vruntime: u64,
+}
+
+impl Fair {
+    pub fn new() -> Self {
+        Fair::default()
+    }
+
+    fn weight(priority: usize) -> f64 {
+        BASE_WEIGHT * WEIGHT_STEP.powi(priority.min(64) as i32)
+    }
+}
+
+impl Scheduler for Fair {
+    fn pick(&mut self, _current: usize, candidates: &[Candidate]) -> usize {
+        let floor = self.min_vruntime.saturating_sub(WAKEUP_CREDIT.as_nanos() as u64);
+        let mut best: Option<(u64, usize)> = None;
+        for c in candidates {
+            let v = self.vruntime.entry(c.id).or_insert(floor);
+            *v = (*v).max(floor);
+            // `<` keeps the first one on ties, which means round-robin between equals
+            match best {
+                Some((min, _)) if min <= *v => {}
+                _ => best = Some((*v, c.id)),
+            }
+        }
+        let (min, id) = best.unwrap();
+        self.min_vruntime = self.min_vruntime.max(min);
+        id
+    }
+
+    fn spawned(&mut self, id: usize) {
+        // a new task starts where the others are, otherwise it would run until it caught up with them
+        self.vruntime.insert(id, self.min_vruntime);
+    }
+
+    fn ran(&mut self, id: usize, priority: usize, elapsed: Duration) {
+        let scaled = elapsed.as_nanos() as f64 * BASE_WEIGHT / Fair::weight(priority);
+        *self.vruntime.entry(id).or_insert(self.min_vruntime) += scaled as u64;
+    }
+}
+
+/// Earliest deadline first: runs the task with the closest deadline. On a single CPU this meets every deadline
+/// if there's any order that does. Tasks without a deadline are scheduled with `RoundRobin` whenever no task
+/// with a deadline is ready.
+///
+/// Nobody preempts a task, so it's only as good as the tasks are at yielding: a task that doesn't yield keeps
+/// running even when one with an earlier deadline becomes ready.
+#[derive(Debug, Default)]
+pub struct Edf;
+
+impl Scheduler for Edf {
+    fn pick(&mut self, current: usize, candidates: &[Candidate]) -> usize {
+        let mut best: Option<(Duration, usize)> = None;
+        for c in candidates {
+            if let Some(deadline) = c.deadline {
+                match best {
+                    Some((earliest, _)) if earliest <= deadline => {}
+                    _ => best = Some((deadline, c.id)),
+                }
+            }
+        }
+        match best {
+            Some((_, id)) => id,
+            None => RoundRobin.pick(current, candidates),
+        }
+    }
+
+    /// Deadlines come first, even before I/O: a boost could make a task miss its deadline.
+    fn woken_by_io(&mut self, _id: usize) -> bool {
+        false
+    }
+}
@@ -0,0 +1,141 @@
+//! Turns POSIX signals into something a task can wait for. Without this a Ctrl-C kills the process wherever
+//! it is, maybe right in the middle of a context switch, and nobody gets to clean up.
+//!
+//! We use the classic self-pipe trick. The signal handler can't touch the runtime (it can interrupt it at any
+//! point), and there's very little it's allowed to do at all, but `write` is one of them. So all it does is
+//! write the signal number to a pipe, and the task waiting for signals reads it from the other end through
+//! the reactor like any other file descriptor.
+//!
+//! We could use a signalfd instead, but that only works if the signal is blocked in every thread of the
+//! process, including the ones in our blocking pool and any thread the user starts.
+use crate::reactor::{self, Interest};
+use crate::sys;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+// the end of the pipe our handler writes to, -1 if nobody listens for signals
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGHUP
+    Hangup,
+    /// SIGINT, what you get from Ctrl-C
+    Interrupt,
+    /// SIGTERM, what `kill` sends by default
+    Terminate,
+    /// SIGUSR1
+    User1,
+    /// SIGUSR2
+    User2,
+}
+
+impl Signal {
+    fn number(self) -> i32 {
+        match self {
+            Signal::Hangup => 1,
+            Signal::Interrupt => 2,
+            Signal::User1 => 10,
+            Signal::User2 => 12,
+            Signal::Terminate => 15,
+        }
+    }
+
+    fn from_number(signum: i32) -> Option<Signal> {
+        [
+            Signal::Hangup,
+            Signal::Interrupt,
+            Signal::User1,
+            Signal::User2,
+            Signal::Terminate,
+        ]
+        .iter()
+        .copied()
+        .find(|s| s.number() == signum)
+    }
+}
+
+/// Catches the given signals while it's alive and lets a task wait for them. Dropping it restores the
+/// default behavior. Only one `Signals` can exist at a time.
+pub struct Signals {
+    read_fd: RawFd,
+    write_fd: RawFd,
+    signals: Vec<Signal>,
+}
+
+impl Signals {
+    pub fn new(signals: &[Signal]) -> io::Result<Signals> {
+        let mut fds = [0; 2];
+        sys::cvt(unsafe { sys::pipe2(fds.as_mut_ptr(), sys::O_NONBLOCK | sys::O_CLOEXEC) })?;
+        let (read_fd, write_fd) = (fds[0], fds[1]);
+
+        if WRITE_FD
+            .compare_exchange(-1, write_fd, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            unsafe {
+                sys::close(read_fd);
+                sys::close(write_fd);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "signals are already being caught",
+            ));
+        }
+
+        for signal in signals {
+            unsafe { sys::signal(signal.number(), on_signal as extern "C" fn(i32) as usize) };
+        }
+        Ok(Signals {
+            read_fd,
+            write_fd,
+            signals: signals.to_vec(),
+        })
+    }
+
+    /// Parks the current task until one of our signals arrives and returns it. Signals that arrive
+    /// while nobody waits are queued (as long as the pipe has room).
+    pub fn wait(&self) -> io::Result<Signal> {
+        loop {
+            let mut signum = 0u8;
+            let read = || {
+                let res = unsafe { sys::read(self.read_fd, &mut signum, 1) };
+                sys::cvt(res as i32)
+            };
+            reactor::retry(self.read_fd, Interest::Read, read)?;
+            if let Some(signal) = Signal::from_number(signum as i32) {
+                return Ok(signal);
+            }
+        }
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for signal in &self.signals {
+            unsafe { sys::signal(signal.number(), sys::SIG_DFL) };
+        }
+        WRITE_FD.store(-1, Ordering::Release);
+        reactor::deregister(self.read_fd);
+        unsafe {
+            sys::close(self.read_fd);
+            sys::close(self.write_fd);
+        }
+    }
+}
+
+/// Our signal handler. Only async-signal-safe functions are allowed in here, and we must not change
+/// `errno` under the feet of the code we interrupted.
+extern "C" fn on_signal(signum: i32) {
+    let fd = WRITE_FD.load(Ordering::Acquire);
+    if fd < 0 {
+        return;
+    }
+    unsafe {
+        let errno = *sys::__errno_location();
+        let byte = signum as u8;
+        sys::write(fd, &byte, 1);
+        *sys::__errno_location() = errno;
+    }
+}
@@ -0,0 +1,126 @@
+//! Snapshots of parked tasks (experimental). `Runtime::snapshot` copies a parked task's registers and the part
+//! of its stack it's using into a `Vec<u8>`, and `Runtime::restore` turns such a snapshot into a new task that
+//! carries on from where the original was when we took it. A long computation that parks now and then can be
+//! checkpointed that way, and started over from the last checkpoint if something goes wrong.
+//!
+//! A snapshot is a copy of a stack, nothing more, so the rules of `fork_current` (see `src/fork.rs`) apply,
+//! and some more. That's why both `snapshot` and `restore` are `unsafe`, their `# Safety` sections sum it up:
+//!
+//! - Only the stack is saved. Anything on it that points to the heap (a `Vec`, a `String`, a `Box`...) points
+//!   to memory the original task owns, which is gone or changed by the time we restore. A task that wants to
+//!   be restored keeps its state in plain values on its stack.
+//! - It can only be restored in the same process, by the same runtime: the stack is full of return addresses
+//!   into our code and pointers to the runtime.
+//! - Take snapshots of tasks that parked themselves with `coro::park`. The restored task gets a new id, so a
+//!   task waiting for an event, a lock or a timer would wait under its old id and never wake up.
+//!
+//! The format is ours and may change any time: a header, the saved registers and then the stack.
+use crate::fork::{bounds, relocate_context, relocate_stack};
+use crate::{JoinHandle, Runtime, State, DEFAULT_STACK_SIZE};
+use std::convert::TryInto;
+use std::io;
+
+const MAGIC: u64 = u64::from_ne_bytes(*b"GTSNAP01");
+
+// The restored task starts where the original was parked, never in this function.
+fn restored() {}
+
+impl Runtime {
+    /// Takes a snapshot of the parked task `id`. Panics if it isn't parked.
+    ///
+    /// # Safety
+    ///
+    /// The snapshot is only good for `restore`, and taking one is where the caller promises what `restore` relies
+    /// on: task `id` parked itself with `coro::park`, and nothing on its stack owns heap memory or anything else
+    /// its `Drop` gives back, or points to memory the task doesn't keep on its stack.
+    pub unsafe fn snapshot(&self, id: usize) -> Vec<u8> {
+        if cfg!(feature = "sim") {
+            panic!("the sim backend doesn't run tasks on their stacks, there's nothing to save.");
+        }
+        assert!(
+            id != 0 && self.tasks.get(id).map(|t| t.state == State::Parked).unwrap_or(false),
+            "only a parked task can be snapshotted."
+        );
+        let task = &self.tasks[id];
+        let (low, high) = bounds(&task.stack);
+        let used = &task.stack[crate::arch::stack_pointer(&task.ctx) - low..];
+
+        let mut bytes = vec![];
+        for word in &[
+            MAGIC,
+            self as *const Runtime as u64,
+            low as u64,
+            high as u64,
+            task.priority as u64,
+        ] {
+            bytes.extend_from_slice(&word.to_ne_bytes());
+        }
+        let ctx = task.ctx.words();
+        bytes.extend_from_slice(&(ctx.len() as u64).to_ne_bytes());
+        for word in ctx.iter() {
+            bytes.extend_from_slice(&word.to_ne_bytes());
+        }
+        bytes.extend_from_slice(used);
+        bytes
+    }
+
+    /// Spawns a task that carries on from the `snapshot`, see the module documentation for what that means. Fails
+    /// with `InvalidData` if `snapshot` doesn't look like a snapshot this runtime took.
+    ///
+    /// # Safety
+    ///
+    /// The restored task runs on a copy of a stack that holds whatever the original had on it, so the caller has
+    /// to make sure that's still good:
+    ///
+    /// - `snapshot` was taken by `snapshot` on this very runtime, in this process, and not changed since. We only
+    ///   check the runtime's address, and a new runtime can have the address of one that's gone.
+    /// - Everything `snapshot` asks for held when it was taken: the task was parked in `coro::park`, and nothing
+    ///   on its stack owns heap memory or points to memory that isn't on the stack. The original task may have
+    ///   freed all of that by now, and the restored one would use it or free it again.
+    /// - Nothing on the stack relies on a word that looks like an address inside the original stack keeping its
+    ///   value. We move every such word to the new stack, like `fork_current` does.
+    pub unsafe fn restore(&mut self, snapshot: &[u8]) -> io::Result<JoinHandle> {
+        if cfg!(feature = "sim") {
+            panic!("the sim backend doesn't run tasks on their stacks, there's nothing to restore.");
+        }
+        let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+        let mut words = snapshot
+            .chunks_exact(8)
+            .map(|word| u64::from_ne_bytes(word.try_into().unwrap()));
+        let mut next = || words.next().ok_or_else(|| invalid("the snapshot is cut short"));
+
+        if next()? != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        if next()? != self as *const Runtime as u64 {
+            return Err(invalid("the snapshot was taken by another runtime"));
+        }
+        let (low, high) = (next()? as usize, next()? as usize);
+        let priority = next()? as usize;
+        let ctx_len = next()? as usize;
+        if high.checked_sub(low) != Some(DEFAULT_STACK_SIZE) || ctx_len != self.tasks[0].ctx.words().len() {
+            return Err(invalid("the snapshot doesn't fit this runtime"));
+        }
+        let ctx = (0..ctx_len).map(|_| next()).collect::<io::Result<Vec<u64>>>()?;
+        let used = &snapshot[(6 + ctx_len) * 8..];
+        if used.len() > DEFAULT_STACK_SIZE {
+            return Err(invalid("the snapshot has a broken stack"));
+        }
+
+        let handle = self.t_spawn_with(restored, |task| {
+            task.priority = priority;
+            task.effective = priority;
+        });
+        if handle.id() == usize::MAX {
+            return Ok(handle);
+        }
+        let task = &mut self.tasks[handle.id()];
+        let (to_low, _) = bounds(&task.stack);
+        let start = task.stack.len() - used.len();
+        task.stack[start..].copy_from_slice(used);
+        task.ctx.words_mut().copy_from_slice(&ctx);
+        relocate_stack(&mut task.stack[start..], (low, high), to_low);
+        relocate_context(&mut task.ctx, (low, high), to_low);
+        Ok(handle)
+    }
+}
@@ -0,0 +1,214 @@
+//! Helpers for the stacks we allocate for our tasks.
+//!
+//! Our stacks are just a `Vec<u8>` so nothing stops a task from growing its stack past the end and
+//! into whatever memory comes before it. We can't prevent that without help from the OS (guard pages),
+//! but we can notice it: we write a known pattern (a "canary") at the very bottom of the stack when
+//! we spawn a task and check that it's still there every time we switch away from it. This works
+//! everywhere, even on bare metal without any memory protection.
+//!
+//! Which of the two a task gets is its `StackPolicy`. On Linux the full runtime can map a stack with a guard
+//! page below it instead of allocating a `Vec` (see `Stack`), then an overflow crashes with a segfault right
+//! where it happens instead of being noticed at the next switch, after it has trampled over somebody else's
+//! memory.
+
+// The pattern we write, repeated `CANARY_WORDS` times
+const CANARY: u64 = 0x5AFE_57AC_C0FF_EE00;
+const CANARY_WORDS: usize = 4;
+/// How many bytes at the bottom of every stack belong to the canary.
+pub(crate) const CANARY_BYTES: usize = CANARY_WORDS * 8;
+// What `StackPolicy::GuardPageAndPoison` fills a stack with
+const POISON: u8 = 0xA5;
+
+/// How a task's stack is protected against overflows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StackPolicy {
+    /// No protection at all, for when you know how deep your tasks go.
+    Plain,
+    /// A canary at the bottom of the stack that we check every time we switch away from the task. An overflow
+    /// panics, but only once the task yields. The default, and the only protection we have without an OS.
+    #[default]
+    Canary,
+    /// An inaccessible page below the stack, so an overflow is a segfault right away. Linux only.
+    GuardPage,
+    /// A guard page, and the whole stack is filled with a pattern every time a task is spawned on it. A task
+    /// never sees what the last task left there, and `TaskStats::stack_peak` can tell how deep it has been.
+    GuardPageAndPoison,
+}
+
+impl StackPolicy {
+    /// Every policy we support here, for tests that should pass with any of them.
+    #[cfg(all(target_os = "linux", not(feature = "static-alloc")))]
+    pub const AVAILABLE: &'static [StackPolicy] = &[
+        StackPolicy::Plain,
+        StackPolicy::Canary,
+        StackPolicy::GuardPage,
+        StackPolicy::GuardPageAndPoison,
+    ];
+    #[cfg(not(all(target_os = "linux", not(feature = "static-alloc"))))]
+    pub const AVAILABLE: &'static [StackPolicy] = &[StackPolicy::Plain, StackPolicy::Canary];
+
+    pub(crate) fn has_guard_page(self) -> bool {
+        matches!(self, StackPolicy::GuardPage | StackPolicy::GuardPageAndPoison)
+    }
+}
+
+/// Gets `stack` ready for a new task: writes the canary or the poison, whatever `policy` needs.
+pub(crate) fn prepare(stack: &mut [u8], policy: StackPolicy) {
+    match policy {
+        StackPolicy::Canary => write_canary(stack),
+        StackPolicy::GuardPageAndPoison => stack.fill(POISON),
+        StackPolicy::Plain | StackPolicy::GuardPage => (),
+    }
+}
+
+/// Returns `false` if the task on `stack` has written past its end, as far as `policy` lets us tell. A guard
+/// page never gets that far.
+pub(crate) fn intact(stack: &[u8], policy: StackPolicy) -> bool {
+    policy != StackPolicy::Canary || canary_intact(stack)
+}
+
+/// How many bytes at the top of a poisoned stack a task has written to so far. A task that writes the poison
+/// itself fools us, so it's a lower bound.
+#[cfg(not(feature = "static-alloc"))]
+pub(crate) fn peak(stack: &[u8]) -> usize {
+    stack.len() - stack.iter().position(|&b| b != POISON).unwrap_or(stack.len())
+}
+
+/// Writes the canary at the bottom (the "low" address) of the stack. Stacks grow downwards so this
+/// is the last part of the stack a task would ever use.
+pub(crate) fn write_canary(stack: &mut [u8]) {
+    for word in stack.chunks_exact_mut(8).take(CANARY_WORDS) {
+        word.copy_from_slice(&CANARY.to_ne_bytes());
+    }
+}
+
+/// Returns `false` if anything overwrote the canary.
+pub(crate) fn canary_intact(stack: &[u8]) -> bool {
+    stack
+        .chunks_exact(8)
+        .take(CANARY_WORDS)
+        .all(|word| word == CANARY.to_ne_bytes())
+}
+
+#[cfg(not(feature = "static-alloc"))]
+pub(crate) use memory::Stack;
+
+#[cfg(not(feature = "static-alloc"))]
+mod memory {
+    use super::StackPolicy;
+    use std::ops::{Deref, DerefMut};
+
+    /// A task's stack. It's a `Vec` unless the task has a guard page, then it's memory we map ourselves with an
+    /// extra page below it that nobody may touch. Either way it derefs to the part the task runs on, and that
+    /// doesn't move in memory for as long as the `Stack` lives, even when the `Stack` itself moves.
+    pub(crate) struct Stack {
+        policy: StackPolicy,
+        memory: Memory,
+    }
+
+    enum Memory {
+        Heap(Vec<u8>),
+        // `len` bytes at `base`, the first `guard` of them are the guard page
+        #[cfg(target_os = "linux")]
+        Mapped {
+            base: *mut u8,
+            len: usize,
+            guard: usize,
+        },
+    }
+
+    impl Stack {
+        /// A stack of `size` usable bytes, protected the way `policy` says. Panics if `policy` wants a guard page
+        /// and we can't have one.
+        pub(crate) fn new(size: usize, policy: StackPolicy) -> Self {
+            let memory = if policy.has_guard_page() {
+                map_with_guard_page(size)
+            } else {
+                Memory::Heap(vec![0_u8; size])
+            };
+            Stack { policy, memory }
+        }
+
+        pub(crate) fn policy(&self) -> StackPolicy {
+            self.policy
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn map_with_guard_page(size: usize) -> Memory {
+        use crate::sys;
+
+        let guard = unsafe { sys::sysconf(sys::_SC_PAGESIZE) as usize };
+        let len = guard + ((size + guard - 1) & !(guard - 1));
+        let prot = sys::PROT_READ | sys::PROT_WRITE;
+        let base = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                prot,
+                sys::MAP_PRIVATE | sys::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if base as usize == sys::MAP_FAILED {
+            panic!("failed to map a stack: {}", std::io::Error::last_os_error());
+        }
+        if unsafe { sys::mprotect(base, guard, sys::PROT_NONE) } != 0 {
+            let err = std::io::Error::last_os_error();
+            unsafe { sys::munmap(base, len) };
+            panic!("failed to protect the guard page of a stack: {}", err);
+        }
+        Memory::Mapped { base, len, guard }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn map_with_guard_page(_size: usize) -> Memory {
+        panic!("guard pages are only supported on Linux, use `StackPolicy::Canary`.");
+    }
+
+    impl Default for Stack {
+        /// No stack at all, `spawn` allocates one when it needs it.
+        fn default() -> Self {
+            Stack {
+                policy: StackPolicy::default(),
+                memory: Memory::Heap(Vec::new()),
+            }
+        }
+    }
+
+    impl Deref for Stack {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            match &self.memory {
+                Memory::Heap(stack) => stack,
+                #[cfg(target_os = "linux")]
+                Memory::Mapped { base, len, guard } => unsafe {
+                    std::slice::from_raw_parts(base.add(*guard), len - guard)
+                },
+            }
+        }
+    }
+
+    impl DerefMut for Stack {
+        fn deref_mut(&mut self) -> &mut [u8] {
+            match &mut self.memory {
+                Memory::Heap(stack) => stack,
+                #[cfg(target_os = "linux")]
+                Memory::Mapped { base, len, guard } => unsafe {
+                    std::slice::from_raw_parts_mut(base.add(*guard), *len - *guard)
+                },
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Drop for Stack {
+        fn drop(&mut self) {
+            if let Memory::Mapped { base, len, .. } = self.memory {
+                unsafe { crate::sys::munmap(base, len) };
+            }
+        }
+    }
+}
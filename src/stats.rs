@@ -0,0 +1,169 @@
+//! Numbers about what the scheduler has been doing, mostly useful to compare schedulers, and the `Debug`
+//! output of `Runtime` that shows what every task is up to.
+use crate::arch;
+use crate::latency::Histogram;
+use crate::{stack, Runtime, StackPolicy, State};
+use std::fmt;
+use std::time::Duration;
+
+/// What a task is doing, see `TaskStats::state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Nothing is running on the slot anymore.
+    Available,
+    Running,
+    Ready,
+    Parked,
+    /// It's done, but its `JoinHandle` hasn't been joined or dropped yet.
+    Finished,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskStats {
+    pub id: usize,
+    /// See `Runtime::spawn_named`.
+    pub name: Option<String>,
+    pub priority: usize,
+    /// See `Runtime::spawn_with_deadline`.
+    pub deadline: Option<Duration>,
+    /// How long the task has been running in total (wall time between being switched to and away from).
+    pub run_time: Duration,
+    /// How many times the scheduler switched to the task.
+    pub scheduled: u64,
+    pub state: TaskState,
+    /// How many bytes of its stack the task uses right now. `None` for the base task, which runs on the stack of
+    /// the OS thread, and for `Available` slots.
+    pub stack_used: Option<usize>,
+    pub stack_size: usize,
+    /// The most of its stack the task has used so far. Only tasks spawned with `StackPolicy::GuardPageAndPoison`
+    /// keep track of that, it's `None` for the rest.
+    pub stack_peak: Option<usize>,
+    /// How long the task waited between becoming `Ready` and running, and how long its switches took. Empty
+    /// unless `Runtime::enable_latency_histograms` was called, see the `latency` module.
+    pub scheduling_latency: Histogram,
+    pub switch_time: Histogram,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// How many context switches the runtime has done in total.
+    pub context_switches: u64,
+    /// How many tasks finished after their deadline.
+    pub deadlines_missed: u64,
+    /// How many tasks used up their budget, see `Runtime::spawn_with_budget`.
+    pub budgets_exceeded: u64,
+    /// How many I/O wakeups the scheduler boosted, see `Scheduler::woken_by_io`.
+    pub io_boosts: u64,
+    /// The same as `TaskStats::scheduling_latency` and `TaskStats::switch_time`, for all tasks together.
+    pub scheduling_latency: Histogram,
+    pub switch_time: Histogram,
+    /// One entry for the base task and every task that has been spawned, including the ones that have
+    /// finished. Tasks reuse the ids of finished tasks, and the numbers start from zero when they do.
+    pub tasks: Vec<TaskStats>,
+}
+
+impl Runtime {
+    /// A snapshot of the numbers above. It only reads, so a task can call it on its own runtime while the others
+    /// are in the middle of whatever they're doing (see `monitor::stats`).
+    pub fn stats(&self) -> Stats {
+        let tasks = self
+            .tasks
+            .iter()
+            .filter(|t| t.state != State::Available || t.scheduled > 0)
+            .map(|t| TaskStats {
+                id: t.id,
+                name: t.name.clone(),
+                priority: t.priority,
+                deadline: t.deadline,
+                run_time: t.run_time,
+                scheduled: t.scheduled,
+                state: match t.state {
+                    State::Available => TaskState::Available,
+                    State::Running => TaskState::Running,
+                    State::Ready => TaskState::Ready,
+                    State::Parked => TaskState::Parked,
+                    State::Finished => TaskState::Finished,
+                },
+                stack_used: match t.state {
+                    State::Available => None,
+                    _ => self.t_stack_used(t.id),
+                },
+                stack_size: t.stack.len(),
+                stack_peak: match t.state {
+                    State::Available => None,
+                    _ if t.stack.policy() == StackPolicy::GuardPageAndPoison => Some(stack::peak(&t.stack)),
+                    _ => None,
+                },
+                scheduling_latency: t.scheduling_latency.clone(),
+                switch_time: t.switch_time.clone(),
+            })
+            .collect();
+        Stats {
+            context_switches: self.context_switches,
+            deadlines_missed: self.deadlines_missed,
+            budgets_exceeded: self.budgets_exceeded,
+            io_boosts: self.io_boosts,
+            scheduling_latency: self
+                .latencies
+                .as_ref()
+                .map_or_else(Histogram::new, |l| l.scheduling.clone()),
+            switch_time: self
+                .latencies
+                .as_ref()
+                .map_or_else(Histogram::new, |l| l.switches.clone()),
+            tasks,
+        }
+    }
+}
+
+impl Runtime {
+    pub(crate) fn t_name(&self, id: usize) -> Option<String> {
+        self.tasks[id].name.clone()
+    }
+
+    /// How many bytes of its stack task `id` uses right now, `None` for the base task which runs on the stack of
+    /// the OS thread. For the task we're running on, that's wherever our own stack pointer is at the moment.
+    fn t_stack_used(&self, id: usize) -> Option<usize> {
+        let stack = &self.tasks[id].stack;
+        if stack.is_empty() {
+            return None;
+        }
+        let top = stack.as_ptr() as usize + stack.len();
+        let sp = if id == self.current {
+            let here = 0_u8;
+            &here as *const u8 as usize
+        } else {
+            arch::stack_pointer(&self.tasks[id].ctx)
+        };
+        Some(top.saturating_sub(sp))
+    }
+}
+
+/// Prints a table of every task that isn't `Available`:
+///
+/// ```text
+/// Runtime (running task 2, 1041 context switches)
+///    id  name              state      stack
+///     0  base              Ready      -
+///     2  consumer          Running    1.1 KiB / 2048 KiB
+/// ```
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Runtime (running task {}, {} context switches)",
+            self.current, self.context_switches
+        )?;
+        writeln!(f, "{:>5}  {:<16}  {:<9}  stack", "id", "name", "state")?;
+        for t in self.tasks.iter().filter(|t| t.state != State::Available) {
+            let name = t.name.as_deref().unwrap_or("-");
+            let state = format!("{:?}", t.state);
+            write!(f, "{:>5}  {:<16}  {:<9}  ", t.id, name, state)?;
+            match self.t_stack_used(t.id) {
+                Some(used) => writeln!(f, "{:.1} KiB / {} KiB", used as f64 / 1024.0, t.stack.len() / 1024)?,
+                None => writeln!(f, "-")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+//! Synchronization primitives for tasks. These never block the OS thread, a task that has to wait
+//! is parked so the other tasks keep running.
+use crate::{Runtime, RUNTIME};
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const UNLOCKED: usize = usize::MAX;
+
+/// The runtime (its address) whose tasks are using a `Mutex` or an `Event` right now. Both are `Sync` so they
+/// can live in a `static`, but their state is plain `Cell`s only one runtime may touch at a time. A runtime claims
+/// one before it touches it and lets go once it's back to where it started (unlocked and nobody waiting, or no
+/// permit and nobody waiting), so a `static` can still be used by one runtime after the other. A runtime that
+/// finds it claimed by another one panics instead of racing it.
+struct Claim(AtomicUsize);
+
+impl Claim {
+    const fn new() -> Self {
+        Claim(AtomicUsize::new(0))
+    }
+
+    fn take(&self, runtime: &Runtime, what: &str) {
+        let me = runtime as *const Runtime as usize;
+        if let Err(other) = self.0.compare_exchange(0, me, Ordering::Acquire, Ordering::Relaxed) {
+            assert_eq!(
+                other, me,
+                "a `sync::{}` was used by two runtimes at the same time, only tasks of one runtime may share it.",
+                what
+            );
+        }
+    }
+
+    fn release(&self) {
+        self.0.store(0, Ordering::Release);
+    }
+}
+
+/// The part of a `Mutex` that doesn't depend on `T`. Tasks keep pointers to the ones they hold or wait
+/// for so the runtime can find the waiters when it calculates priorities.
+pub(crate) struct RawMutex {
+    claim: Claim,
+    // the id of the task holding the lock
+    owner: Cell<usize>,
+    // the ids of the tasks waiting for it in the order they arrived
+    waiters: UnsafeCell<Vec<usize>>,
+}
+
+impl RawMutex {
+    pub(crate) fn owner(&self) -> usize {
+        self.owner.get()
+    }
+}
+
+/// A mutex for tasks. If it's locked, `lock` parks the current task until it's our turn.
+///
+/// When the lock is released we hand it directly to the waiting task with the highest priority (the one
+/// that waited the longest if several have the same priority) instead of letting everyone race for it.
+///
+/// The mutex also implements priority inheritance: while a task waits for the lock, the task holding it
+/// runs with the waiter's priority if that's higher than its own. Without this a low priority task holding
+/// the lock could be kept from running by a medium priority task forever, and the high priority task
+/// waiting for the lock would be stuck behind both of them (the classic priority inversion problem).
+///
+/// Since we only spawn `fn()` the way to share a mutex between tasks is to put it in a `static`. That's why
+/// it implements `Sync`, but it's only meant to be used by tasks running on the same runtime. Using it from a
+/// second runtime (on another OS thread or not) while it's locked or has waiters panics.
+pub struct Mutex<T> {
+    raw: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            raw: RawMutex {
+                claim: Claim::new(),
+                owner: Cell::new(UNLOCKED),
+                waiters: UnsafeCell::new(Vec::new()),
+            },
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Locks the mutex, parking the current task until it's available.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_lock(&self.raw);
+        }
+        MutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Locks the mutex if it's available without parking.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            self.raw.claim.take(&*rt_ptr, "Mutex");
+        }
+        if self.raw.owner.get() != UNLOCKED {
+            return None;
+        }
+        Some(self.lock())
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    // it has to be unlocked on the runtime that locked it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_unlock(&self.mutex.raw);
+        }
+    }
+}
+
+/// A lightweight way for tasks to wait for something another task (or another OS thread through a
+/// `RuntimeHandle`) tells them has happened.
+///
+/// `notify_one` wakes the task that has waited the longest. If no task is waiting it stores a permit instead,
+/// so the next call to `wait` returns right away (we only store one, notifying twice before anyone waits is
+/// the same as notifying once). `notify_all` wakes every task that's waiting right now but doesn't store a
+/// permit.
+///
+/// Just like `Mutex` it's meant to be shared through a `static` between tasks on the same runtime, using it from a
+/// second runtime while it has waiters or a permit panics. Other OS threads must go through
+/// `RuntimeHandle::notify_one`/`notify_all`.
+pub struct Event {
+    claim: Claim,
+    permit: Cell<bool>,
+    waiters: UnsafeCell<Vec<usize>>,
+}
+
+unsafe impl Sync for Event {}
+
+impl Default for Event {
+    fn default() -> Self {
+        Event::new()
+    }
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Event {
+            claim: Claim::new(),
+            permit: Cell::new(false),
+            waiters: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// Parks the current task until the event is notified.
+    pub fn wait(&self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_wait_event(self);
+        }
+    }
+
+    pub fn notify_one(&self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_notify(self, false);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_notify(self, true);
+        }
+    }
+}
+
+/// A list of waiters a parked task put itself on (an `Event`'s or a channel's) and how to take it off again. A
+/// cancelled task never gets to do that itself, and its id would stay on the list until a notification finds it
+/// and is lost on a finished task, or wakes whatever task got its slot next.
+#[derive(Clone, Copy)]
+pub(crate) struct WaitList {
+    list: *const (),
+    forget: unsafe fn(*const (), usize),
+}
+
+impl WaitList {
+    /// `forget(list, id)` takes task `id` off `list`. `list` has to outlive the wait, which it does as long as
+    /// the waiting task borrows it.
+    pub(crate) fn new<L>(list: &L, forget: unsafe fn(*const (), usize)) -> Self {
+        WaitList {
+            list: list as *const L as *const (),
+            forget,
+        }
+    }
+}
+
+// Only called by the runtime that claimed the event, it's on its list
+unsafe fn forget_event_waiter(event: *const (), id: usize) {
+    let event = &*(event as *const Event);
+    (*event.waiters.get()).retain(|&waiter| waiter != id);
+    event.release_if_unused();
+}
+
+impl Event {
+    fn release_if_unused(&self) {
+        if !self.permit.get() && unsafe { (*self.waiters.get()).is_empty() } {
+            self.claim.release();
+        }
+    }
+}
+
+impl Runtime {
+    fn t_wait_event(&mut self, event: &Event) {
+        event.claim.take(self, "Event");
+        if event.permit.replace(false) {
+            event.release_if_unused();
+            return;
+        }
+
+        let me = self.current;
+        unsafe { (*event.waiters.get()).push(me) };
+        self.tasks[me].waits_in = Some(WaitList::new(event, forget_event_waiter));
+        // `t_notify` removes us from the list before it unparks us, anything else is a spurious wakeup. It
+        // might have let go of the event doing that, so we claim it again before we look.
+        while unsafe { (*event.waiters.get()).contains(&me) } {
+            self.t_park();
+            event.claim.take(self, "Event");
+        }
+        self.tasks[me].waits_in = None;
+        event.release_if_unused();
+    }
+
+    /// Sets the list the current task waits on while it's parked, see `WaitList`.
+    pub(crate) fn t_set_waits_in(&mut self, list: Option<WaitList>) {
+        self.tasks[self.current].waits_in = list;
+    }
+
+    /// Takes task `id` off the list it waits on, if any. Used when it's cancelled or moved to another runtime.
+    pub(crate) fn t_forget_wait(&mut self, id: usize) {
+        if let Some(list) = self.tasks[id].waits_in.take() {
+            unsafe { (list.forget)(list.list, id) };
+        }
+    }
+
+    pub(crate) fn t_notify(&mut self, event: &Event, all: bool) {
+        event.claim.take(self, "Event");
+        let waiters = unsafe { &mut *event.waiters.get() };
+        if waiters.is_empty() {
+            if !all {
+                event.permit.set(true);
+            }
+            event.release_if_unused();
+            return;
+        }
+
+        let woken: Vec<usize> = if all {
+            std::mem::take(waiters)
+        } else {
+            vec![waiters.remove(0)]
+        };
+        event.release_if_unused();
+        for id in woken {
+            self.t_unpark(id);
+        }
+    }
+
+    fn t_lock(&mut self, raw: &RawMutex) {
+        let me = self.current;
+        let lock = raw as *const RawMutex;
+        raw.claim.take(self, "Mutex");
+        assert_ne!(
+            raw.owner.get(),
+            me,
+            "task {} tried to lock a mutex it already holds.",
+            me
+        );
+
+        if raw.owner.get() == UNLOCKED {
+            raw.owner.set(me);
+            self.tasks[me].held.push(lock);
+            return;
+        }
+
+        unsafe { (*raw.waiters.get()).push(me) };
+        self.tasks[me].blocked_on = Some(lock);
+        self.t_inherit(lock, self.tasks[me].effective);
+
+        // `t_unlock` makes us the owner before it unparks us, anything else is a spurious wakeup
+        while raw.owner.get() != me {
+            self.t_park();
+        }
+    }
+
+    fn t_unlock(&mut self, raw: &RawMutex) {
+        raw.claim.take(self, "Mutex");
+        let owner = raw.owner.get();
+        let lock = raw as *const RawMutex;
+        self.tasks[owner].held.retain(|&l| l != lock);
+
+        let waiters = unsafe { &mut *raw.waiters.get() };
+        let mut next = None;
+        if waiters.is_empty() {
+            raw.owner.set(UNLOCKED);
+            raw.claim.release();
+        } else {
+            let mut best = 0;
+            for i in 1..waiters.len() {
+                if self.tasks[waiters[i]].effective > self.tasks[waiters[best]].effective {
+                    best = i;
+                }
+            }
+            let id = waiters.remove(best);
+            raw.owner.set(id);
+            self.tasks[id].blocked_on = None;
+            self.tasks[id].held.push(lock);
+            // there might be other tasks waiting behind it that it now inherits a priority from
+            self.tasks[id].effective = self.t_effective_priority(id);
+            self.t_unpark(id);
+            next = Some(id);
+        }
+
+        // We might have been running on a borrowed priority which we have to give back now
+        self.tasks[owner].effective = self.t_effective_priority(owner);
+
+        // If we just woke up a task that's more important than us, let it run right away (unless we batch
+        // wakes, then it waits until we yield)
+        if let Some(id) = next {
+            if owner == self.current && !self.batch_wakes && self.tasks[id].effective > self.tasks[owner].effective {
+                self.t_yield();
+            }
+        }
+    }
+
+    /// Lends `priority` to the owner of `lock`. If the owner is waiting for another mutex itself we
+    /// have to lend it to that owner as well, and so on, or the chain would still be stuck.
+    fn t_inherit(&mut self, mut lock: *const RawMutex, priority: usize) {
+        loop {
+            let owner = unsafe { (*lock).owner.get() };
+            if self.tasks[owner].effective >= priority {
+                break;
+            }
+            self.tasks[owner].effective = priority;
+            match self.tasks[owner].blocked_on {
+                Some(next) => lock = next,
+                None => break,
+            }
+        }
+    }
+
+    /// A task runs with its own priority or the highest priority of any task waiting for a mutex it holds.
+    fn t_effective_priority(&self, id: usize) -> usize {
+        let task = &self.tasks[id];
+        let mut priority = task.priority;
+        for &lock in &task.held {
+            let waiters = unsafe { &*(*lock).waiters.get() };
+            for &waiter in waiters {
+                priority = priority.max(self.tasks[waiter].effective);
+            }
+        }
+        priority
+    }
+}
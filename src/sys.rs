@@ -0,0 +1,127 @@
+//! The few Linux system calls our reactor needs. We link to the C library anyway (the standard library
+//! does), so all we need to do is declare the functions and the constants and structs they use. The
+//! values come from the Linux headers (`sys/epoll.h`, `sys/eventfd.h`, `sys/timerfd.h`, `signal.h`, `sys/mman.h`,
+//! `unistd.h`, `sched.h` and, for the `io-uring` feature, `linux/io_uring.h`).
+#![allow(non_camel_case_types, dead_code)]
+
+pub(crate) const EPOLL_CLOEXEC: i32 = 0x80000;
+pub(crate) const EPOLL_CTL_ADD: i32 = 1;
+pub(crate) const EPOLL_CTL_DEL: i32 = 2;
+pub(crate) const EPOLL_CTL_MOD: i32 = 3;
+
+pub(crate) const EPOLLIN: u32 = 0x001;
+pub(crate) const EPOLLOUT: u32 = 0x004;
+pub(crate) const EPOLLERR: u32 = 0x008;
+pub(crate) const EPOLLHUP: u32 = 0x010;
+pub(crate) const EPOLLRDHUP: u32 = 0x2000;
+pub(crate) const EPOLLONESHOT: u32 = 1 << 30;
+
+pub(crate) const EFD_CLOEXEC: i32 = 0x80000;
+pub(crate) const EFD_NONBLOCK: i32 = 0x800;
+
+pub(crate) const CLOCK_MONOTONIC: i32 = 1;
+pub(crate) const TFD_CLOEXEC: i32 = 0x80000;
+pub(crate) const TFD_NONBLOCK: i32 = 0x800;
+
+pub(crate) const O_NONBLOCK: i32 = 0x800;
+pub(crate) const O_CLOEXEC: i32 = 0x80000;
+
+pub(crate) const SIG_DFL: usize = 0;
+
+pub(crate) const EINTR: i32 = 4;
+pub(crate) const EAGAIN: i32 = 11;
+
+pub(crate) const PROT_NONE: i32 = 0x0;
+pub(crate) const PROT_READ: i32 = 0x1;
+pub(crate) const PROT_WRITE: i32 = 0x2;
+pub(crate) const MAP_SHARED: i32 = 0x01;
+pub(crate) const MAP_PRIVATE: i32 = 0x02;
+pub(crate) const MAP_ANONYMOUS: i32 = 0x20;
+// what `mmap` returns when it fails, `(void *) -1`
+pub(crate) const MAP_FAILED: usize = usize::MAX;
+pub(crate) const MAP_POPULATE: i32 = 0x8000;
+pub(crate) const MADV_DONTNEED: i32 = 4;
+
+pub(crate) const _SC_PAGESIZE: i32 = 30;
+
+// glibc's `cpu_set_t` has room for 1024 CPUs
+pub(crate) const CPU_SETSIZE: usize = 1024;
+
+// glibc doesn't have wrappers for these, so we go through `syscall`. The numbers are the same on every
+// architecture we support since they were added after the syscall tables were unified.
+pub(crate) const SYS_IO_URING_SETUP: i64 = 425;
+pub(crate) const SYS_IO_URING_ENTER: i64 = 426;
+
+/// On x86_64 the kernel packs this struct, everywhere else it has the normal C layout.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(target_arch = "x86_64", repr(C, packed))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+pub(crate) struct epoll_event {
+    pub(crate) events: u32,
+    pub(crate) data: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub(crate) struct timespec {
+    pub(crate) tv_sec: i64,
+    pub(crate) tv_nsec: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub(crate) struct itimerspec {
+    pub(crate) it_interval: timespec,
+    pub(crate) it_value: timespec,
+}
+
+extern "C" {
+    pub(crate) fn epoll_create1(flags: i32) -> i32;
+    pub(crate) fn epoll_ctl(epfd: i32, op: i32, fd: i32, event: *mut epoll_event) -> i32;
+    pub(crate) fn epoll_wait(epfd: i32, events: *mut epoll_event, maxevents: i32, timeout: i32) -> i32;
+    pub(crate) fn eventfd(initval: u32, flags: i32) -> i32;
+    pub(crate) fn timerfd_create(clockid: i32, flags: i32) -> i32;
+    pub(crate) fn timerfd_settime(
+        fd: i32,
+        flags: i32,
+        new_value: *const itimerspec,
+        old_value: *mut itimerspec,
+    ) -> i32;
+    pub(crate) fn read(fd: i32, buf: *mut u8, count: usize) -> isize;
+    pub(crate) fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    pub(crate) fn close(fd: i32) -> i32;
+    pub(crate) fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+    pub(crate) fn munmap(addr: *mut u8, len: usize) -> i32;
+    pub(crate) fn mprotect(addr: *mut u8, len: usize, prot: i32) -> i32;
+    pub(crate) fn madvise(addr: *mut u8, len: usize, advice: i32) -> i32;
+    pub(crate) fn sysconf(name: i32) -> i64;
+    pub(crate) fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+    pub(crate) fn sched_getcpu() -> i32;
+    pub(crate) fn syscall(number: i64, ...) -> i64;
+    pub(crate) fn pipe2(fds: *mut i32, flags: i32) -> i32;
+    pub(crate) fn signal(signum: i32, handler: usize) -> usize;
+    pub(crate) fn __errno_location() -> *mut i32;
+}
+
+/// Turns the `-1` most system calls return on errors into an `io::Error` with the value of `errno`.
+pub(crate) fn cvt(res: i32) -> std::io::Result<i32> {
+    if res < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+/// Adds one to the counter of an eventfd, which makes it readable.
+pub(crate) fn eventfd_write(fd: i32, value: u64) -> std::io::Result<()> {
+    let bytes = value.to_ne_bytes();
+    let res = unsafe { write(fd, bytes.as_ptr(), bytes.len()) };
+    cvt(res as i32).map(|_| ())
+}
+
+/// Reads an 8 byte counter from an eventfd or timerfd, which resets it.
+pub(crate) fn read_u64(fd: i32) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    let res = unsafe { read(fd, bytes.as_mut_ptr(), bytes.len()) };
+    cvt(res as i32).map(|_| u64::from_ne_bytes(bytes))
+}
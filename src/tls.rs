@@ -0,0 +1,169 @@
+//! Thread locals and tasks that don't stay on one OS thread.
+//!
+//! A `thread_local!` belongs to the OS thread, not to the task. With one runtime per thread that's the same
+//! thing, but in M:N mode a task that moves to another worker keeps whatever it had on its stack, so a
+//! reference into a thread local it took before it yielded now points at the old thread's copy, which that
+//! thread keeps using at the same time. Nothing stops you from doing that, `LocalKey::with` only makes sure the
+//! reference doesn't outlive the closure, and yielding inside the closure is allowed.
+//!
+//! There are two ways out:
+//!
+//! - `TaskLocal` is the task's own version of a thread local: every task gets its own value, it moves with the
+//!   task, and it's dropped when the task returns. Use it for anything that belongs to the task.
+//! - `pin` returns a `MigrationGuard`, and as long as a task holds one the runtime doesn't move it to another
+//!   OS thread. The guard isn't `Send`, so it can't leave the task's stack either.
+//!
+//! To find the places that need one of those, go through `tls::with(&KEY, f)` instead of `KEY.with(f)`. It's the
+//! same thing, but with the `tls-check` feature the runtime counts how many of these closures a task is in, and
+//! a task that yields (or parks, or waits for anything else) inside one panics right there, unless it holds a
+//! `MigrationGuard`. We can't see `KEY.with` itself, so it only catches what goes through `tls::with`.
+use crate::{Runtime, RUNTIME};
+use std::any::Any;
+use std::marker::PhantomData;
+use std::thread::LocalKey;
+
+/// Keeps the current task on its OS thread until it's dropped. They nest.
+pub struct MigrationGuard {
+    // it belongs to the task and the thread it was taken on
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Pins the current task to the OS thread it's running on until the guard is dropped.
+pub fn pin() -> MigrationGuard {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_pin();
+    }
+    MigrationGuard { _not_send: PhantomData }
+}
+
+/// Returns true while the current task holds a `MigrationGuard`.
+pub fn is_pinned() -> bool {
+    unsafe {
+        let rt_ptr = RUNTIME as *const Runtime;
+        (*rt_ptr).t_is_pinned((*rt_ptr).current)
+    }
+}
+
+impl Drop for MigrationGuard {
+    fn drop(&mut self) {
+        unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_unpin();
+        }
+    }
+}
+
+/// `key.with(f)`. With the `tls-check` feature, the current task panics if it yields before `f` returns, unless
+/// it holds a `MigrationGuard`.
+pub fn with<T: 'static, R>(key: &'static LocalKey<T>, f: impl FnOnce(&T) -> R) -> R {
+    #[cfg(feature = "tls-check")]
+    let _borrow = Borrow::new();
+    key.with(f)
+}
+
+/// Counts as a reference to a thread local for `t_check_tls` until it's dropped, even if `f` panics.
+#[cfg(feature = "tls-check")]
+struct Borrow;
+
+#[cfg(feature = "tls-check")]
+impl Borrow {
+    fn new() -> Self {
+        Borrow::add(1);
+        Borrow
+    }
+
+    fn add(borrows: isize) {
+        unsafe {
+            if RUNTIME != 0 {
+                let rt_ptr = RUNTIME as *mut Runtime;
+                (*rt_ptr).t_borrow_tls(borrows);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tls-check")]
+impl Drop for Borrow {
+    fn drop(&mut self) {
+        Borrow::add(-1);
+    }
+}
+
+/// A value every task has its own copy of, created by `init` the first time the task uses it. Declare it in a
+/// `static` like a `thread_local!`:
+///
+/// ```ignore
+/// static REQUESTS: TaskLocal<Cell<usize>> = TaskLocal::new(|| Cell::new(0));
+/// REQUESTS.with(|requests| requests.set(requests.get() + 1));
+/// ```
+///
+/// The values move with their task, so they have to be `Send`. A task that returns drops its values, a
+/// cancelled task doesn't.
+pub struct TaskLocal<T: Send + 'static> {
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> TaskLocal<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        TaskLocal { init }
+    }
+
+    /// Calls `f` with the current task's value.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        let value = unsafe {
+            let rt_ptr = RUNTIME as *mut Runtime;
+            (*rt_ptr).t_task_local(self as *const TaskLocal<T> as usize, self.init)
+        };
+        // it's boxed, so it stays put while the task adds more values
+        f(unsafe { &*value })
+    }
+}
+
+impl Runtime {
+    fn t_pin(&mut self) {
+        self.tasks[self.current].pinned += 1;
+    }
+
+    fn t_unpin(&mut self) {
+        self.tasks[self.current].pinned -= 1;
+    }
+
+    /// True if task `id` holds a `MigrationGuard`, then it has to stay on this OS thread.
+    pub(crate) fn t_is_pinned(&self, id: usize) -> bool {
+        self.tasks[id].pinned > 0
+    }
+
+    /// The current task's value of the `TaskLocal` at `key`.
+    fn t_task_local<T: Send + 'static>(&mut self, key: usize, init: fn() -> T) -> *const T {
+        let current = self.current;
+        if let Some((_, value)) = self.tasks[current].locals.iter().find(|(k, _)| *k == key) {
+            return value.downcast_ref::<T>().unwrap();
+        }
+        let value: Box<dyn Any + Send> = Box::new(init());
+        let ptr = value.downcast_ref::<T>().unwrap() as *const T;
+        self.tasks[current].locals.push((key, value));
+        ptr
+    }
+
+    /// Panics if the current task is about to be suspended inside `tls::with` while it isn't pinned.
+    #[cfg(feature = "tls-check")]
+    pub(crate) fn t_check_tls(&self) {
+        let task = &self.tasks[self.current];
+        // the base task runs on the OS thread's own stack, it never goes anywhere. `t_park` checks before the task
+        // is `Parked`, so we don't panic with the task in a state it can't run in.
+        if self.current != 0 && task.state == crate::State::Running && task.tls_borrows > 0 && task.pinned == 0 {
+            panic!(
+                "task {} yielded inside `tls::with`, holding a reference to a thread local. It might move to \
+                 another OS thread, use a `tls::TaskLocal` or hold a `tls::MigrationGuard`.",
+                self.current
+            );
+        }
+    }
+
+    #[cfg(feature = "tls-check")]
+    fn t_borrow_tls(&mut self, borrows: isize) {
+        let task = &mut self.tasks[self.current];
+        task.tls_borrows = (task.tls_borrows as isize + borrows) as usize;
+    }
+}
@@ -0,0 +1,128 @@
+//! A log of what the scheduler did, to look at afterwards. Once `Runtime::enable_trace` is called we record every
+//! spawn, context switch, park, wakeup and finished task with a timestamp in a ring buffer, so a long running
+//! program keeps the most recent events and forgets the oldest ones.
+//!
+//! `Runtime::dump_trace` writes the buffer in the Chrome trace event format. Open the file in `chrome://tracing`
+//! or https://ui.perfetto.dev and every task shows up as a thread, with a bar for every time it ran and a marker
+//! for everything else that happened to it. It's the easiest way to see how tasks actually interleave.
+use crate::Runtime;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Event {
+    Spawn(usize),
+    Switch { from: usize, to: usize },
+    Park(usize),
+    Wake(usize),
+    Finish(usize),
+}
+
+pub(crate) struct Trace {
+    start: Instant,
+    events: VecDeque<(Duration, Event)>,
+    capacity: usize,
+}
+
+impl Trace {
+    fn record(&mut self, event: Event) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back((self.start.elapsed(), event));
+    }
+}
+
+impl Runtime {
+    /// Starts recording scheduling events, keeping the last `capacity` of them. Calling it again starts over.
+    pub fn enable_trace(&mut self, capacity: usize) {
+        assert!(capacity > 0, "a trace needs room for at least one event.");
+        self.trace = Some(Trace {
+            start: Instant::now(),
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        });
+    }
+
+    pub(crate) fn t_trace(&mut self, event: Event) {
+        if let Some(trace) = &mut self.trace {
+            trace.record(event);
+        }
+    }
+
+    /// Writes the recorded events as Chrome trace format JSON. Writes an empty trace if tracing isn't enabled.
+    pub fn dump_trace(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut events = vec![];
+        for task in &self.tasks {
+            let name = task.name.clone().unwrap_or_else(|| format!("task {}", task.id));
+            events.push(format!(
+                "{{\"name\": \"thread_name\", \"ph\": \"M\", \"pid\": 1, \"tid\": {}, \"args\": {{\"name\": {}}}}}",
+                task.id,
+                json_string(&name)
+            ));
+        }
+
+        if let Some(trace) = &self.trace {
+            // We only know when a task started running once we see the switch to it. For whichever task was
+            // running when the oldest event we still have happened, we just start there.
+            let mut since = trace.events.front().map(|(at, _)| *at).unwrap_or_default();
+            let mut running = self.current;
+            for &(at, event) in &trace.events {
+                let (name, id) = match event {
+                    Event::Switch { from, to } => {
+                        events.push(slice(from, since, at));
+                        since = at;
+                        running = to;
+                        continue;
+                    }
+                    Event::Spawn(id) => ("spawn", id),
+                    Event::Park(id) => ("park", id),
+                    Event::Wake(id) => ("wake", id),
+                    Event::Finish(id) => ("finish", id),
+                };
+                events.push(format!(
+                    "{{\"name\": \"{}\", \"ph\": \"i\", \"s\": \"t\", \"pid\": 1, \"tid\": {}, \"ts\": {:.3}}}",
+                    name,
+                    id,
+                    micros(at)
+                ));
+            }
+            events.push(slice(running, since, trace.start.elapsed()));
+        }
+
+        writeln!(out, "{{\"displayTimeUnit\": \"ns\", \"traceEvents\": [")?;
+        writeln!(out, "{}", events.join(",\n"))?;
+        writeln!(out, "]}}")
+    }
+}
+
+/// A bar from `start` to `end` on the row of task `id`, for the time it was running.
+fn slice(id: usize, start: Duration, end: Duration) -> String {
+    format!(
+        "{{\"name\": \"running\", \"ph\": \"X\", \"pid\": 1, \"tid\": {}, \"ts\": {:.3}, \"dur\": {:.3}}}",
+        id,
+        micros(start),
+        micros(end - start)
+    )
+}
+
+// the trace format counts in microseconds
+fn micros(d: Duration) -> f64 {
+    d.as_nanos() as f64 / 1000.0
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
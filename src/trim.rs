@@ -0,0 +1,51 @@
+//! Giving the memory of idle stacks back to the OS. Every task gets a 2 MB stack, but the OS only hands us a
+//! page once we touch it, so a task that never goes deep only costs the few pages it uses. The trouble is a
+//! task that went deep once, say while it parsed a big request, and then sits parked waiting for the next one:
+//! it keeps every page it ever touched.
+//!
+//! `Runtime::trim` tells the kernel (with `madvise(MADV_DONTNEED)`) that we don't need the part of a parked
+//! task's stack below its stack pointer. A parked task isn't using anything down there, and if it goes that
+//! deep again after it wakes up the kernel simply hands it fresh zeroed pages. The task itself keeps its slot,
+//! its stack and everything on it above the stack pointer.
+use crate::{arch, stack, sys, Runtime, StackPolicy, State};
+
+// The part right below the stack pointer we leave alone: on x86_64 a function that doesn't call anything may
+// keep its locals there (the "red zone").
+const RED_ZONE: usize = 128;
+
+fn page_size() -> usize {
+    unsafe { sys::sysconf(sys::_SC_PAGESIZE) as usize }
+}
+
+impl Runtime {
+    /// Releases the stack memory every parked task has touched but isn't using right now. Returns how many
+    /// bytes we told the kernel it can have back, which is more than the memory we actually free if the task
+    /// never touched some of those pages.
+    ///
+    /// It's cheap enough to call from a task every few seconds in a server with lots of idle connections.
+    pub fn trim(&mut self) -> usize {
+        let page = page_size();
+        let mut released = 0;
+        for task in &mut self.tasks[1..] {
+            if task.state != State::Parked || task.stack.is_empty() {
+                continue;
+            }
+            // Whole pages only, between the canary at the bottom and whatever is in use at the top. A poisoned
+            // stack would read back as zeroes, so we leave the part it never touched alone or its peak is gone.
+            let mut bottom = task.stack.as_mut_ptr() as usize + stack::CANARY_BYTES;
+            if task.stack.policy() == StackPolicy::GuardPageAndPoison {
+                bottom = task.stack.as_ptr() as usize + task.stack.len() - stack::peak(&task.stack);
+            }
+            let start = (bottom + page - 1) & !(page - 1);
+            let end = arch::stack_pointer(&task.ctx).saturating_sub(RED_ZONE) & !(page - 1);
+            if end <= start {
+                continue;
+            }
+            let res = unsafe { sys::madvise(start as *mut u8, end - start, sys::MADV_DONTNEED) };
+            if res == 0 {
+                released += end - start;
+            }
+        }
+        released
+    }
+}
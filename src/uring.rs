@@ -0,0 +1,370 @@
+//! An io_uring backend for the reactor (needs Linux 5.10 or later and the `io-uring` feature).
+//!
+//! With epoll the kernel tells us when a file descriptor is *ready*, and the task then does the read or write
+//! itself. With io_uring the task hands the whole operation to the kernel and gets woken when it's *done*. That
+//! saves a system call per operation, and unlike epoll it works for regular files as well, which are always
+//! "ready" even though reading them can block.
+//!
+//! An io_uring is two ring buffers shared with the kernel: we put submission queue entries (SQEs) in one and the
+//! kernel puts completion queue entries (CQEs) in the other. Every operation gets a token in the SQE's
+//! `user_data`, which the kernel copies to the CQE, and we keep track of which task each token belongs to
+//! so we know who to wake.
+//!
+//! We don't wait on the ring directly. Its file descriptor becomes readable when there are completions, so we
+//! register it with our epoll instance and both backends share the same event loop.
+use crate::sys;
+use crate::{Runtime, RUNTIME};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// We never have more operations in flight than we have tasks, so this is plenty.
+const ENTRIES: u32 = 64;
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+const SOCK_CLOEXEC: u32 = 0x80000;
+
+const IORING_OP_ACCEPT: u8 = 13;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+const IORING_OP_SEND: u8 = 26;
+const IORING_OP_RECV: u8 = 27;
+
+/// Passing this as the offset reads from or writes to the current file position (and moves it).
+pub(crate) const CURRENT_POSITION: u64 = u64::MAX;
+
+#[allow(dead_code)]
+#[derive(Default)]
+#[repr(C)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[allow(dead_code)]
+#[derive(Default)]
+#[repr(C)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[allow(dead_code)]
+#[derive(Default)]
+#[repr(C)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    file_index: u32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+// Both layouts are fixed by the kernel ABI
+const _: [(); 64] = [(); std::mem::size_of::<Sqe>()];
+const _: [(); 16] = [(); std::mem::size_of::<Cqe>()];
+const _: [(); 120] = [(); std::mem::size_of::<Params>()];
+
+// a region of memory we share with the kernel
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Self> {
+        let prot = sys::PROT_READ | sys::PROT_WRITE;
+        let flags = sys::MAP_SHARED | sys::MAP_POPULATE;
+        let ptr = unsafe { sys::mmap(ptr::null_mut(), len, prot, flags, fd, offset) };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr, len })
+    }
+
+    // the kernel updates the ring heads and tails concurrently, so we always go through atomics
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(offset as usize) as *const AtomicU32) }
+    }
+
+    fn at<T>(&self, offset: usize) -> *mut T {
+        unsafe { self.ptr.add(offset) as *mut T }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { sys::munmap(self.ptr, self.len) };
+    }
+}
+
+pub(crate) struct Uring {
+    fd: RawFd,
+    params: Params,
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    // results of finished operations by task id that the tasks haven't picked up yet
+    completed: HashMap<usize, i32>,
+    // the task waiting for each operation we've submitted, by token
+    in_flight: HashMap<u64, usize>,
+    next_token: u64,
+}
+
+impl Uring {
+    /// Sets up a new ring. Fails on kernels without io_uring, and the reactor falls back to plain
+    /// epoll in that case.
+    pub(crate) fn new() -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe { sys::syscall(sys::SYS_IO_URING_SETUP, ENTRIES, &mut params as *mut Params) };
+        let fd = sys::cvt(fd as i32)?;
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        let maps = Mapping::new(fd, sq_len, IORING_OFF_SQ_RING).and_then(|sq| {
+            let cq = Mapping::new(fd, cq_len, IORING_OFF_CQ_RING)?;
+            let sqes = Mapping::new(fd, sqes_len, IORING_OFF_SQES)?;
+            Ok((sq, cq, sqes))
+        });
+        let (sq, cq, sqes) = match maps {
+            Ok(maps) => maps,
+            Err(e) => {
+                unsafe { sys::close(fd) };
+                return Err(e);
+            }
+        };
+
+        Ok(Uring {
+            fd,
+            params,
+            sq,
+            cq,
+            sqes,
+            completed: HashMap::new(),
+            in_flight: HashMap::new(),
+            next_token: 0,
+        })
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Forgets about the operation task `id` is waiting for, if it has one. Returns true if it did, in which
+    /// case the kernel might still write to the buffer it passed us.
+    pub(crate) fn forget_task(&mut self, id: usize) -> bool {
+        self.completed.remove(&id);
+        let before = self.in_flight.len();
+        self.in_flight.retain(|_, &mut task| task != id);
+        self.in_flight.len() != before
+    }
+
+    /// Puts `sqe` in the submission queue on behalf of task `id` and tells the kernel about it.
+    fn submit(&mut self, mut sqe: Sqe, id: usize) -> io::Result<()> {
+        let token = self.next_token;
+        self.next_token = self.next_token.wrapping_add(1);
+        sqe.user_data = token;
+
+        let off = &self.params.sq_off;
+        let head = self.sq.atomic(off.head).load(Ordering::Acquire);
+        let tail = self.sq.atomic(off.tail).load(Ordering::Relaxed);
+        if tail.wrapping_sub(head) == self.params.sq_entries {
+            return Err(io::Error::other("io_uring submission queue is full"));
+        }
+
+        let index = tail & self.sq.atomic(off.ring_mask).load(Ordering::Relaxed);
+        unsafe {
+            *self.sqes.at::<Sqe>(index as usize * std::mem::size_of::<Sqe>()) = sqe;
+            *self.sq.at::<u32>(off.array as usize + index as usize * 4) = index;
+        }
+        // the kernel must see the entry before it sees the new tail
+        self.sq.atomic(off.tail).store(tail.wrapping_add(1), Ordering::Release);
+
+        self.in_flight.insert(token, id);
+        let res = unsafe { sys::syscall(sys::SYS_IO_URING_ENTER, self.fd, 1, 0, 0, 0usize, 0usize) };
+        if let Err(err) = sys::cvt(res as i32) {
+            // We don't use SQPOLL, so the kernel only takes entries in `io_uring_enter`. If it took ours before it
+            // failed, the operation runs and writes to the task's buffer when it completes, so it's in flight like
+            // any other. If it didn't we take it back, and the caller gets the error and its buffer.
+            if self.sq.atomic(off.head).load(Ordering::Acquire) == tail {
+                self.sq.atomic(off.tail).store(tail, Ordering::Release);
+                self.in_flight.remove(&token);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes every completion off the completion queue and returns the ids of the tasks they belong to.
+    pub(crate) fn reap(&mut self) -> Vec<usize> {
+        let off = &self.params.cq_off;
+        let mut head = self.cq.atomic(off.head).load(Ordering::Relaxed);
+        let tail = self.cq.atomic(off.tail).load(Ordering::Acquire);
+        let mask = self.cq.atomic(off.ring_mask).load(Ordering::Relaxed);
+
+        let mut woken = vec![];
+        while head != tail {
+            let cqe = self.cq.at::<Cqe>(off.cqes as usize + (head & mask) as usize * std::mem::size_of::<Cqe>());
+            let (token, res) = unsafe { ((*cqe).user_data, (*cqe).res) };
+            // the task might have been cancelled while it waited, then nobody wants the result
+            if let Some(id) = self.in_flight.remove(&token) {
+                self.completed.insert(id, res);
+                woken.push(id);
+            }
+            head = head.wrapping_add(1);
+        }
+        // this tells the kernel it can reuse the entries
+        self.cq.atomic(off.head).store(head, Ordering::Release);
+        woken
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        unsafe { sys::close(self.fd) };
+    }
+}
+
+impl Runtime {
+    /// Submits `sqe` on behalf of the current task and parks it until the operation completes.
+    fn t_uring(&mut self, sqe: Sqe) -> io::Result<usize> {
+        let me = self.current;
+        let ring = self.reactor.uring.as_mut().expect("io_uring is not available.");
+        ring.submit(sqe, me)?;
+
+        loop {
+            let ring = self.reactor.uring.as_mut().unwrap();
+            if let Some(res) = ring.completed.remove(&me) {
+                return if res < 0 {
+                    Err(io::Error::from_raw_os_error(-res))
+                } else {
+                    Ok(res as usize)
+                };
+            }
+            self.t_park();
+        }
+    }
+}
+
+fn submit(sqe: Sqe) -> io::Result<usize> {
+    unsafe {
+        let rt_ptr = RUNTIME as *mut Runtime;
+        (*rt_ptr).t_uring(sqe)
+    }
+}
+
+/// Returns true if the runtime got an io_uring. If it didn't, everything goes through epoll.
+pub(crate) fn available() -> bool {
+    unsafe {
+        let rt_ptr = RUNTIME as *const Runtime;
+        (*rt_ptr).reactor.uring.is_some()
+    }
+}
+
+pub(crate) fn read(fd: RawFd, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    submit(Sqe {
+        opcode: IORING_OP_READ,
+        fd,
+        off: offset,
+        addr: buf.as_mut_ptr() as u64,
+        len: buf.len() as u32,
+        ..Sqe::default()
+    })
+}
+
+pub(crate) fn write(fd: RawFd, buf: &[u8], offset: u64) -> io::Result<usize> {
+    submit(Sqe {
+        opcode: IORING_OP_WRITE,
+        fd,
+        off: offset,
+        addr: buf.as_ptr() as u64,
+        len: buf.len() as u32,
+        ..Sqe::default()
+    })
+}
+
+pub(crate) fn recv(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    submit(Sqe {
+        opcode: IORING_OP_RECV,
+        fd,
+        addr: buf.as_mut_ptr() as u64,
+        len: buf.len() as u32,
+        ..Sqe::default()
+    })
+}
+
+pub(crate) fn send(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    submit(Sqe {
+        opcode: IORING_OP_SEND,
+        fd,
+        addr: buf.as_ptr() as u64,
+        len: buf.len() as u32,
+        ..Sqe::default()
+    })
+}
+
+/// Accepts a connection on the listening socket `fd` and returns the new socket.
+pub(crate) fn accept(fd: RawFd) -> io::Result<RawFd> {
+    submit(Sqe {
+        opcode: IORING_OP_ACCEPT,
+        fd,
+        op_flags: SOCK_CLOEXEC,
+        ..Sqe::default()
+    })
+    .map(|fd| fd as RawFd)
+}
@@ -0,0 +1,116 @@
+//! A lock-free queue of wakes, for code that can't take a lock: interrupt handlers in `bare` and signal handlers
+//! in the full runtime. Pushing never blocks, never allocates and never touches the runtime, so it's safe to do
+//! at any point, even while the runtime itself is halfway through a switch. Only the runtime pops, at a point
+//! where it's safe to act on what it finds.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Has to be a power of two. If more wakes than this arrive before the runtime gets to run, we wake every
+// waiting task instead (see `overflow`).
+const QUEUE_SIZE: usize = 64;
+
+// A bounded multi-producer queue (Dmitry Vyukov's design). Every slot has a sequence number that tells
+// producers and the consumer whose turn it is, so nobody ever waits for anyone else: a producer that gets
+// interrupted halfway through only delays the consumer until it's done.
+struct Slot {
+    sequence: AtomicUsize,
+    key: AtomicUsize,
+}
+
+pub(crate) struct WakeQueue {
+    slots: [Slot; QUEUE_SIZE],
+    head: AtomicUsize,
+    // only the runtime reads from the queue, but it's an atomic so the queue can live in a `static`
+    tail: AtomicUsize,
+    // set when the queue was full, the runtime then wakes every waiting task
+    overflow: AtomicBool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    sequence: AtomicUsize::new(0),
+    key: AtomicUsize::new(0),
+};
+
+/// What the runtime gets out of the queue.
+pub(crate) enum Woken {
+    Key(usize),
+    Everyone,
+}
+
+impl WakeQueue {
+    pub(crate) const fn new() -> Self {
+        let mut slots = [EMPTY_SLOT; QUEUE_SIZE];
+        let mut i = 0;
+        while i < QUEUE_SIZE {
+            slots[i].sequence = AtomicUsize::new(i);
+            i += 1;
+        }
+        WakeQueue {
+            slots,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overflow: AtomicBool::new(false),
+        }
+    }
+
+    /// Queues a wake for `key`. If the queue is full it turns into a wake for everyone.
+    pub(crate) fn push(&self, key: usize) {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % QUEUE_SIZE];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            if sequence == pos {
+                // the slot is free, try to claim it
+                match self
+                    .head
+                    .compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        slot.key.store(key, Ordering::Relaxed);
+                        slot.sequence.store(pos.wrapping_add(1), Ordering::Release);
+                        return;
+                    }
+                    Err(head) => pos = head,
+                }
+            } else if (sequence.wrapping_sub(pos) as isize) < 0 {
+                // the runtime hasn't taken the key that was here last time around yet
+                self.overflow.store(true, Ordering::Release);
+                return;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<usize> {
+        let pos = self.tail.load(Ordering::Relaxed);
+        let slot = &self.slots[pos % QUEUE_SIZE];
+        if slot.sequence.load(Ordering::Acquire) != pos.wrapping_add(1) {
+            return None;
+        }
+        let key = slot.key.load(Ordering::Relaxed);
+        self.tail.store(pos.wrapping_add(1), Ordering::Relaxed);
+        slot.sequence.store(pos.wrapping_add(QUEUE_SIZE), Ordering::Release);
+        Some(key)
+    }
+
+    /// Returns true if there are wakes the runtime hasn't taken yet.
+    #[cfg(feature = "static-alloc")]
+    pub(crate) fn is_pending(&self) -> bool {
+        if self.overflow.load(Ordering::Acquire) {
+            return true;
+        }
+        let pos = self.tail.load(Ordering::Relaxed);
+        self.slots[pos % QUEUE_SIZE].sequence.load(Ordering::Acquire) == pos.wrapping_add(1)
+    }
+
+    /// Takes the next wake out of the queue. Only the runtime calls this.
+    pub(crate) fn take(&self) -> Option<Woken> {
+        if self.overflow.swap(false, Ordering::Acquire) {
+            // everything in the queue is covered by waking everyone
+            while self.pop().is_some() {}
+            return Some(Woken::Everyone);
+        }
+        self.pop().map(Woken::Key)
+    }
+}
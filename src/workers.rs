@@ -0,0 +1,150 @@
+//! M:N mode: tasks on several OS threads ("workers"), every one of them running a `Runtime` of its own.
+//! `Workers::spawn` hands a task to the next worker in turn and `Workers::spawn_pinned` to the one you ask for.
+//! Tasks never move from one worker to another, so the worker a task starts on is where it runs until it
+//! returns. Tasks on different workers talk the same way tasks on any two runtimes do, through `channel`.
+//!
+//! The OS still moves the workers themselves between CPUs. `Workers::pinned` (Linux only) pins every worker to
+//! a CPU of its own with `sched_setaffinity`, so a task stays on the same core and its caches stay warm, and a
+//! benchmark can put its tasks on the same or on different cores (or NUMA nodes) on purpose.
+use crate::{Runtime, RuntimeHandle};
+use std::cell::Cell;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+thread_local! {
+    static WORKER: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+pub struct Workers {
+    handles: Vec<RuntimeHandle>,
+    threads: Vec<thread::JoinHandle<()>>,
+    // the worker `spawn` hands the next task to
+    next: AtomicUsize,
+    stop: Arc<AtomicBool>,
+}
+
+impl Workers {
+    /// Starts `workers` workers, and lets the OS decide which CPUs they run on.
+    pub fn new(workers: usize) -> Self {
+        Workers::start(vec![None; workers]).expect("failed to start the workers.")
+    }
+
+    /// Starts a worker for every CPU in `cpus` and pins it to that CPU. Fails if one of them isn't a CPU we may
+    /// run on.
+    #[cfg(target_os = "linux")]
+    pub fn pinned(cpus: &[usize]) -> io::Result<Self> {
+        Workers::start(cpus.iter().map(|&cpu| Some(cpu)).collect())
+    }
+
+    fn start(cpus: Vec<Option<usize>>) -> io::Result<Self> {
+        assert!(!cpus.is_empty(), "we need at least one worker.");
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut workers = Workers {
+            handles: vec![],
+            threads: vec![],
+            next: AtomicUsize::new(0),
+            stop: stop.clone(),
+        };
+        for (id, cpu) in cpus.into_iter().enumerate() {
+            let (ready, started) = mpsc::channel();
+            let stop = stop.clone();
+            let thread = thread::Builder::new()
+                .name(format!("worker {}", id))
+                .spawn(move || worker(id, cpu, stop, ready))?;
+            workers.threads.push(thread);
+            // if it failed, dropping `workers` stops the ones we started so far
+            workers.handles.push(started.recv().unwrap()?);
+        }
+        Ok(workers)
+    }
+
+    pub fn count(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Spawns `f` on the next worker in turn and returns which one that is.
+    pub fn spawn(&self, f: fn()) -> usize {
+        let worker = self.next.fetch_add(1, Ordering::Relaxed) % self.handles.len();
+        self.spawn_pinned(worker, f);
+        worker
+    }
+
+    /// Spawns `f` on `worker`. Panics if there's no such worker.
+    pub fn spawn_pinned(&self, worker: usize, f: fn()) {
+        assert!(worker < self.handles.len(), "there's no worker {}.", worker);
+        self.handles[worker].spawn(f);
+    }
+}
+
+/// Waits until every task on every worker has finished, and stops the workers.
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        // a worker with nothing to do waits for a spawn, this is the spawn that tells it to look at `stop`
+        for handle in self.handles.drain(..) {
+            handle.spawn(stop);
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn stop() {}
+
+fn worker(id: usize, cpu: Option<usize>, stop: Arc<AtomicBool>, ready: mpsc::Sender<io::Result<RuntimeHandle>>) {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(cpu) = cpu {
+            if let Err(e) = pin_to(cpu) {
+                let _ = ready.send(Err(e));
+                return;
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = cpu;
+    WORKER.with(|worker| worker.set(Some(id)));
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let _ = ready.send(Ok(runtime.handle()));
+    drop(ready);
+    loop {
+        runtime.run();
+        if stop.load(Ordering::Acquire) && !runtime.injector.is_pending() {
+            break;
+        }
+        runtime.injector.wait();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to(cpu: usize) -> io::Result<()> {
+    use crate::sys;
+
+    if cpu >= sys::CPU_SETSIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("there's no CPU {}", cpu),
+        ));
+    }
+    let mut set = [0_u64; sys::CPU_SETSIZE / 64];
+    set[cpu / 64] |= 1 << (cpu % 64);
+    let res = unsafe { sys::sched_setaffinity(0, std::mem::size_of_val(&set), set.as_ptr()) };
+    sys::cvt(res).map(|_| ())
+}
+
+/// The worker the current task runs on, `None` if it isn't running on one of the `Workers`. The `sim` backend runs
+/// every task on a thread of its own, so there it's always `None`.
+pub fn current() -> Option<usize> {
+    WORKER.with(|worker| worker.get())
+}
+
+/// The CPU the current OS thread is running on right now.
+#[cfg(target_os = "linux")]
+pub fn current_cpu() -> usize {
+    unsafe { crate::sys::sched_getcpu() as usize }
+}
@@ -0,0 +1,353 @@
+//! Joining, detaching and cancelling tasks, on their own and in a `TaskGroup`.
+//!
+//! The tasks count what they did in a thread local, which the threads of the `sim` backend don't share, so
+//! these only run with a real backend. `tests/sim.rs` covers the same ground for `sim`.
+#![cfg(not(feature = "sim"))]
+use green_threads::channel::{self, Receiver};
+use green_threads::{coro, parallel, sync, JoinHandle, Overload, Runtime, TaskGroup};
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+
+thread_local! {
+    static DONE: Cell<usize> = const { Cell::new(0) };
+    static HANDLED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+fn work() {
+    for _ in 0..5 {
+        coro::yield_now();
+    }
+    DONE.with(|done| done.set(done.get() + 1));
+}
+
+fn done() -> usize {
+    DONE.with(|done| done.get())
+}
+
+#[test]
+fn join_waits_until_the_task_is_done() {
+    fn main() -> i32 {
+        let worker = coro::spawn(work);
+        worker.join();
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 1);
+}
+
+#[test]
+fn joining_a_finished_task_returns_right_away() {
+    fn main() -> i32 {
+        let worker = coro::spawn(work);
+        while done() == 0 {
+            coro::yield_now();
+        }
+        worker.join();
+        0
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 0);
+}
+
+#[test]
+fn detached_tasks_give_their_slot_back() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    // far more tasks than there are slots, so every slot is used many times
+    for _ in 0..20 {
+        for _ in 0..3 {
+            runtime.spawn(work).detach();
+        }
+        runtime.run();
+        assert_eq!(runtime.alive_count(), 0);
+    }
+    assert_eq!(done(), 60);
+}
+
+#[test]
+fn a_finished_task_keeps_its_slot_until_it_is_joined() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let handles: Vec<_> = (0..3).map(|_| runtime.spawn(work)).collect();
+    runtime.run();
+
+    // every slot is taken by a finished task nobody joined yet
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        runtime.spawn(work);
+    }));
+    assert!(result.is_err());
+
+    drop(handles);
+    runtime.spawn(work).detach();
+    runtime.run();
+    assert_eq!(done(), 4);
+}
+
+#[test]
+fn run_until_cancels_the_tasks_that_are_still_alive() {
+    fn parks_forever() {
+        loop {
+            coro::park();
+        }
+    }
+    fn main() -> i32 {
+        coro::spawn(parks_forever).detach();
+        coro::spawn(parks_forever).detach();
+        7
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 7);
+    assert_eq!(runtime.alive_count(), 0);
+
+    // and the slots can be used again
+    runtime.spawn(work).detach();
+    runtime.run();
+    assert_eq!(done(), 1);
+}
+
+#[test]
+fn cancelling_a_group_cancels_what_its_members_spawned() {
+    fn helper() {
+        loop {
+            coro::yield_now();
+        }
+    }
+    fn connection() {
+        // the helper ends up in a group inside ours
+        let inner = TaskGroup::new();
+        inner.spawn(helper).detach();
+        helper();
+    }
+    fn main() -> i32 {
+        let group = TaskGroup::new();
+        group.spawn(connection).detach();
+        while group.alive_count() < 2 {
+            coro::yield_now();
+        }
+        group.cancel();
+        group.wait();
+        group.alive_count() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 0);
+    assert_eq!(runtime.alive_count(), 0);
+}
+
+#[test]
+fn waiting_for_a_group_waits_for_every_member() {
+    fn main() -> i32 {
+        let group = TaskGroup::new();
+        group.spawn(work).detach();
+        group.spawn(work).detach();
+        group.wait();
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 2);
+}
+
+#[test]
+fn dropping_a_group_cancels_members_before_they_run() {
+    fn main() -> i32 {
+        let group = TaskGroup::new();
+        let worker = group.spawn(work);
+        drop(group);
+        // it finished without ever running
+        worker.join();
+        for _ in 0..10 {
+            coro::yield_now();
+        }
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 0);
+}
+
+#[test]
+fn a_cancelled_task_doesnt_take_the_next_notification() {
+    static EVENT: sync::Event = sync::Event::new();
+    fn waits() {
+        EVENT.wait();
+        DONE.with(|done| done.set(done.get() + 1));
+    }
+    fn main() -> i32 {
+        let group = TaskGroup::new();
+        group.spawn(waits).detach();
+        coro::yield_now();
+        let other = coro::spawn(waits);
+        coro::yield_now();
+        // the member waits longer, so it would have been first
+        group.cancel();
+        group.wait();
+        EVENT.notify_one();
+        other.join();
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 1);
+}
+
+#[test]
+fn a_cancelled_receiver_doesnt_wake_the_next_task_on_its_slot() {
+    fn receives(messages: Receiver<usize>) {
+        let _ = messages.recv();
+    }
+    fn parks() {
+        coro::park();
+        DONE.with(|done| done.set(done.get() + 1));
+    }
+    fn main() -> i32 {
+        let (tx, rx) = channel::channel::<usize>();
+        let group = TaskGroup::new();
+        let receiver = group.spawn_with(receives, rx).id();
+        coro::yield_now();
+        group.cancel();
+        group.wait();
+        coro::yield_now();
+
+        let parked = coro::spawn(parks);
+        assert_eq!(parked.id(), receiver);
+        coro::yield_now();
+        tx.send(1).unwrap();
+        for _ in 0..5 {
+            coro::yield_now();
+        }
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 0);
+}
+
+#[test]
+fn a_cancelled_spawner_doesnt_take_the_next_free_slot() {
+    static RELEASE: sync::Event = sync::Event::new();
+    fn blocks() {
+        RELEASE.wait();
+    }
+    fn spawns() {
+        coro::spawn(work).detach();
+    }
+    fn main() -> i32 {
+        // we, the blocker and the member use up every slot
+        coro::spawn(blocks).detach();
+        let group = TaskGroup::new();
+        group.spawn(spawns).detach();
+        coro::yield_now();
+        group.cancel();
+        // the member waits longer, so the slot it frees would have gone to it
+        let worker = coro::spawn(work);
+        RELEASE.notify_one();
+        worker.join();
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.on_overload(Overload::Wait);
+    assert_eq!(runtime.run_until(main), 1);
+}
+
+#[test]
+fn a_cancelled_joiner_doesnt_wake_the_next_task_on_its_slot() {
+    static RELEASE: sync::Event = sync::Event::new();
+    thread_local! {
+        static JOINED: RefCell<Option<JoinHandle>> = const { RefCell::new(None) };
+    }
+    fn blocks() {
+        RELEASE.wait();
+    }
+    fn joins() {
+        JOINED.with(|joined| joined.borrow_mut().take()).unwrap().join();
+    }
+    fn parks() {
+        coro::park();
+        DONE.with(|done| done.set(done.get() + 1));
+    }
+    fn main() -> i32 {
+        let blocker = coro::spawn(blocks);
+        JOINED.with(|joined| *joined.borrow_mut() = Some(blocker));
+        let group = TaskGroup::new();
+        let joiner = group.spawn(joins).id();
+        coro::yield_now();
+        group.cancel();
+        group.wait();
+        coro::yield_now();
+
+        let parked = coro::spawn(parks);
+        assert_eq!(parked.id(), joiner);
+        coro::yield_now();
+        RELEASE.notify_one();
+        for _ in 0..5 {
+            coro::yield_now();
+        }
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 0);
+}
+
+#[test]
+fn parallel_for_picks_up_the_chunks_of_cancelled_workers() {
+    static LOCK: sync::Mutex<()> = sync::Mutex::new(());
+    fn caller() {
+        // we aren't cancelled while we hold a lock, our worker is
+        let _guard = LOCK.lock();
+        parallel::parallel_for(0..4, 2, |i| {
+            HANDLED.with(|handled| handled.borrow_mut().push(i));
+            coro::yield_now();
+        });
+        DONE.with(|done| done.set(1));
+    }
+    fn main() -> i32 {
+        let group = TaskGroup::new();
+        group.spawn(caller).detach();
+        // the caller is in the middle of index 0 and its worker in the middle of index 2
+        while HANDLED.with(|handled| handled.borrow().len()) < 2 {
+            coro::yield_now();
+        }
+        group.cancel();
+        group.wait();
+        done() as i32
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 1);
+    // the caller started over with the index the worker didn't finish
+    assert_eq!(HANDLED.with(|handled| handled.take()), vec![0, 2, 1, 2, 3]);
+}
+
+#[test]
+fn a_panic_in_parallel_for_waits_for_the_workers() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let calls = Cell::new(0);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        runtime.parallel_for(0..8, 2, |i| {
+            calls.set(calls.get() + 1);
+            assert_ne!(i, 0, "index 0 panics");
+        });
+    }));
+    assert!(result.is_err());
+    // the workers never got to start, and they're gone before the job they'd work on
+    assert_eq!(calls.get(), 1);
+    assert_eq!(runtime.alive_count(), 0);
+}
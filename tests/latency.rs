@@ -0,0 +1,74 @@
+//! The histograms of `Runtime::enable_latency_histograms`.
+use green_threads::latency::Histogram;
+use green_threads::{coro, Runtime};
+use std::time::Duration;
+
+#[test]
+fn a_histogram_knows_its_percentiles_within_a_bucket() {
+    let mut histogram = Histogram::new();
+    assert_eq!(histogram.percentile(99.0), Duration::from_secs(0));
+    for micros in 1..=1000 {
+        histogram.record(Duration::from_micros(micros));
+    }
+    assert_eq!(histogram.count(), 1000);
+    assert_eq!(histogram.min(), Duration::from_micros(1));
+    assert_eq!(histogram.max(), Duration::from_micros(1000));
+    for &(percentile, micros) in &[(50.0, 500), (99.0, 990), (100.0, 1000)] {
+        let value = histogram.percentile(percentile).as_nanos() as f64;
+        let exact = (micros * 1000) as f64;
+        assert!(
+            value >= exact && value <= exact * 1.07,
+            "p{} is {}ns",
+            percentile,
+            value
+        );
+    }
+
+    let mut merged = Histogram::new();
+    merged.merge(&histogram);
+    merged.merge(&histogram);
+    assert_eq!(merged.count(), 2000);
+    assert_eq!(merged.percentile(50.0), histogram.percentile(50.0));
+
+    let mut hgrm = vec![];
+    histogram.write_hgrm(&mut hgrm).unwrap();
+    let hgrm = String::from_utf8(hgrm).unwrap();
+    let last = hgrm.lines().rev().find(|line| !line.starts_with('#')).unwrap();
+    assert_eq!(
+        last.split_whitespace().collect::<Vec<_>>(),
+        ["1000.000", "1.000000000000", "1000"]
+    );
+    assert!(hgrm.contains("Total count    =         1000]"));
+}
+
+#[test]
+fn every_pick_and_every_switch_back_is_recorded() {
+    fn yields() {
+        for _ in 0..10 {
+            coro::yield_now();
+        }
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.enable_latency_histograms();
+    for _ in 0..3 {
+        runtime.spawn(yields).detach();
+    }
+    runtime.run();
+
+    let stats = runtime.stats();
+    let tasks: Vec<_> = stats.tasks.iter().filter(|t| t.id != 0).collect();
+    assert_eq!(tasks.len(), 3);
+    for task in &tasks {
+        // a task's first switch starts it instead of bringing it back
+        assert_eq!(task.scheduling_latency.count(), task.scheduled);
+        assert_eq!(task.switch_time.count(), task.scheduled - 1);
+        assert!(task.scheduling_latency.percentile(99.0) <= task.scheduling_latency.max());
+    }
+    let picked: u64 = stats.tasks.iter().map(|t| t.scheduling_latency.count()).sum();
+    let switched: u64 = stats.tasks.iter().map(|t| t.switch_time.count()).sum();
+    assert_eq!(stats.scheduling_latency.count(), picked);
+    assert_eq!(stats.switch_time.count(), switched);
+    assert!(stats.switch_time.count() > 0);
+}
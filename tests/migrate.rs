@@ -0,0 +1,68 @@
+//! Moving parked tasks between runtimes with `Runtime::migrate` and `Runtime::steal_from`.
+use green_threads::channel::{self, Receiver};
+use green_threads::{coro, tls, MigrateError, Runtime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn a_migrated_task_gets_its_messages_on_the_new_runtime() {
+    static GOT: AtomicUsize = AtomicUsize::new(0);
+    fn receive(messages: Receiver<usize>) {
+        while let Ok(message) = messages.recv() {
+            GOT.fetch_add(message, Ordering::SeqCst);
+        }
+    }
+
+    let (tx, rx) = channel::channel::<usize>();
+    let mut from = Runtime::new();
+    let mut to = Runtime::new();
+    from.init();
+    let id = from.spawn_with(receive, rx).id();
+    from.step();
+    let id = from.migrate(id, &mut to).unwrap();
+    assert_eq!(from.alive_count(), 0);
+    assert_eq!(to.ready_count(), 1);
+
+    // it wakes up on `to`, finds nothing and tells `tx` where it waits now
+    to.init();
+    to.step();
+    tx.send(5).unwrap();
+    tx.send(7).unwrap();
+    drop(tx);
+    to.run();
+    assert_eq!(GOT.load(Ordering::SeqCst), 12);
+    assert!(to.stats().tasks.iter().any(|t| t.id == id && t.scheduled == 3));
+}
+
+#[test]
+fn only_tasks_parked_in_park_with_nothing_holding_them_can_move() {
+    static UNPARKED: AtomicUsize = AtomicUsize::new(0);
+    fn parks() {
+        coro::park();
+        UNPARKED.fetch_add(1, Ordering::SeqCst);
+    }
+    fn parks_pinned() {
+        let _guard = tls::pin();
+        coro::park();
+    }
+
+    let mut from = Runtime::new();
+    let mut to = Runtime::new();
+    from.init();
+    let joinable = from.spawn(parks);
+    let pinned = from.spawn(parks_pinned).id();
+    assert_eq!(from.migrate(pinned, &mut to), Err(MigrateError::NotParked));
+    from.step();
+    assert_eq!(from.migrate(joinable.id(), &mut to), Err(MigrateError::Bound));
+    assert_eq!(from.migrate(pinned, &mut to), Err(MigrateError::Pinned));
+    assert_eq!(to.steal_from(&mut from), None);
+
+    let id = joinable.id();
+    drop(joinable);
+    let stolen = to.steal_from(&mut from).unwrap();
+    assert_eq!(from.migrate(id, &mut to), Err(MigrateError::NotParked));
+    to.init();
+    to.run();
+    assert_eq!(UNPARKED.load(Ordering::SeqCst), 1);
+    assert_eq!(to.alive_count(), 0);
+    assert!(to.stats().tasks.iter().any(|t| t.id == stolen));
+}
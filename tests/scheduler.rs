@@ -0,0 +1,458 @@
+//! The order tasks run in. Every test gets its own runtime on its own OS thread (that's how `cargo test` runs
+//! them), and the tasks log to a thread local so the tests don't see each other's tasks.
+//!
+//! The threads of the `sim` backend don't share that thread local, so these only run with a real backend.
+//! `tests/sim.rs` covers the same ground for `sim`.
+#![cfg(not(feature = "sim"))]
+use green_threads::actor::{self, Actor};
+use green_threads::monitor::Monitor;
+use green_threads::scheduler::Fair;
+use green_threads::{coro, preempt, sync, BudgetExceeded, OverBudget, Overload, Runtime, RuntimeHandle, TaskState};
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::Duration;
+
+thread_local! {
+    static LOG: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    static HANDLE: RefCell<Option<RuntimeHandle>> = const { RefCell::new(None) };
+}
+
+fn log() {
+    LOG.with(|log| log.borrow_mut().push(coro::current().id()));
+}
+
+fn take_log() -> Vec<usize> {
+    LOG.with(|log| log.borrow_mut().split_off(0))
+}
+
+fn three_turns() {
+    for _ in 0..3 {
+        log();
+        coro::yield_now();
+    }
+}
+
+#[test]
+fn ready_tasks_take_turns_in_the_same_order_every_round() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let ids: Vec<usize> = (0..3).map(|_| runtime.spawn(three_turns).id()).collect();
+
+    assert!(runtime.step());
+    let first = take_log();
+    let mut sorted = first.clone();
+    sorted.sort_unstable();
+    let mut expected = ids.clone();
+    expected.sort_unstable();
+    assert_eq!(sorted, expected, "every task runs exactly once per round");
+
+    for _ in 0..2 {
+        assert!(runtime.step());
+        assert_eq!(take_log(), first);
+    }
+
+    // they all return in the next round, and then there's nothing left to do
+    assert!(runtime.step());
+    assert!(take_log().is_empty());
+    assert!(!runtime.step());
+    assert_eq!(runtime.alive_count(), 0);
+}
+
+#[test]
+fn a_task_spawned_during_a_round_runs_in_the_next_one_at_the_latest() {
+    fn spawner() {
+        log();
+        coro::spawn(log).detach();
+        coro::yield_now();
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let spawner_id = runtime.spawn(spawner).id();
+    runtime.step();
+    runtime.step();
+    let log = take_log();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0], spawner_id);
+    assert_ne!(log[1], spawner_id);
+}
+
+#[test]
+fn round_robin_runs_higher_priorities_first() {
+    fn busy() {
+        for _ in 0..10 {
+            log();
+            coro::yield_now();
+        }
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let low = runtime.spawn(busy).id();
+    let high = runtime.spawn_with_priority(busy, 1).id();
+    runtime.run();
+
+    let log = take_log();
+    let first_low = log.iter().position(|&id| id == low).unwrap();
+    assert!(log[..first_low].iter().all(|&id| id == high));
+    assert_eq!(
+        first_low, 10,
+        "the low priority task waits until the high priority one is done"
+    );
+}
+
+#[test]
+fn fair_scheduler_does_not_starve_low_priorities() {
+    fn spin() {
+        let start = std::time::Instant::now();
+        while start.elapsed() < Duration::from_micros(50) {}
+    }
+    fn busy() {
+        for _ in 0..200 {
+            log();
+            spin();
+            coro::yield_now();
+        }
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.set_scheduler(Box::new(Fair::new()));
+    let high = runtime.spawn_with_priority(busy, 8).id();
+    let low = runtime.spawn(busy).id();
+
+    for _ in 0..50 {
+        runtime.step();
+    }
+    let log = take_log();
+    let turns = |who| log.iter().filter(|&&id| id == who).count();
+    assert!(turns(low) > 0, "the low priority task never ran");
+    assert!(
+        turns(high) > turns(low),
+        "the high priority task should get a bigger share"
+    );
+}
+
+#[test]
+fn spawned_tasks_get_their_argument() {
+    fn logs(arg: usize) {
+        LOG.with(|log| log.borrow_mut().push(arg));
+    }
+    fn logs_sum((name, values): (String, Vec<usize>)) {
+        logs(name.len() + values.iter().sum::<usize>());
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn_arg(logs, 7);
+    runtime.spawn_arg(logs, usize::MAX);
+    runtime.spawn_with(logs_sum, ("four".to_string(), vec![10, 20]));
+    runtime.run();
+
+    assert_eq!(take_log(), vec![7, usize::MAX, 34]);
+}
+
+#[test]
+fn a_rejected_spawn_with_drops_its_value() {
+    fn ignore(_: fn()) {}
+    fn keep(_: Arc<()>) {}
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.on_overload(Overload::Call(ignore));
+    let value = Arc::new(());
+    let handles: Vec<_> = (0..4).map(|_| runtime.spawn_with(keep, value.clone())).collect();
+
+    assert_eq!(handles[3].id(), usize::MAX, "there are only three free tasks");
+    assert_eq!(Arc::strong_count(&value), 4);
+    drop(handles);
+    runtime.run();
+    assert_eq!(Arc::strong_count(&value), 1);
+}
+
+#[test]
+fn an_actor_that_didnt_get_a_task_doesnt_take_the_next_ones() {
+    struct Logs(usize);
+    impl Actor for Logs {
+        type Message = usize;
+        fn handle(&mut self, message: usize) {
+            LOG.with(|log| log.borrow_mut().push(self.0 * 10 + message));
+        }
+    }
+    fn ignore(_: fn()) {}
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.on_overload(Overload::Call(ignore));
+    let busy: Vec<_> = (1..=3).map(|n| actor::spawn(Logs(n))).collect();
+    let rejected = actor::spawn(Logs(4));
+    assert!(rejected.send(1).is_err());
+    drop(busy);
+    runtime.run();
+
+    let next = actor::spawn(Logs(5));
+    next.send(1).unwrap();
+    drop(next);
+    runtime.run();
+    assert_eq!(take_log(), vec![51]);
+}
+
+#[test]
+fn batched_wakes_wait_until_the_waking_task_yields() {
+    static LOCK: sync::Mutex<()> = sync::Mutex::new(());
+    fn holder() {
+        let guard = LOCK.lock();
+        coro::yield_now();
+        // hands the lock to the waiter, which is more important than us
+        drop(guard);
+        log();
+    }
+    fn waiter() {
+        let _guard = LOCK.lock();
+        log();
+    }
+    fn order(batching: bool) -> Vec<&'static str> {
+        let mut runtime = Runtime::new();
+        runtime.init();
+        runtime.set_wake_batching(batching);
+        let holder = runtime.spawn(holder).id();
+        runtime.step();
+        runtime.spawn_with_priority(waiter, 1).detach();
+        runtime.run();
+        take_log()
+            .into_iter()
+            .map(|id| if id == holder { "holder" } else { "waiter" })
+            .collect()
+    }
+
+    assert_eq!(order(false), vec!["waiter", "holder"]);
+    assert_eq!(order(true), vec!["holder", "waiter"]);
+}
+
+#[test]
+fn a_mutex_locked_on_one_runtime_cant_be_used_from_another() {
+    static LOCK: sync::Mutex<()> = sync::Mutex::new(());
+    let first = Runtime::new();
+    first.init();
+    let guard = LOCK.lock();
+
+    let second = Runtime::new();
+    second.init();
+    assert!(std::panic::catch_unwind(|| LOCK.try_lock().is_none()).is_err());
+
+    first.init();
+    drop(guard);
+    // once it's unlocked another runtime can have it
+    second.init();
+    assert!(LOCK.try_lock().is_some());
+}
+
+#[test]
+fn a_monitor_sample_shows_what_every_task_is_doing() {
+    fn parks() {
+        coro::park();
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let yielder = runtime.spawn_named(three_turns, "yielder").id();
+    let parker = runtime.spawn_named(parks, "parker").id();
+    let mut monitor = Monitor::new();
+    runtime.step();
+    let sample = monitor.sample_stats(runtime.stats());
+
+    let task = |id| sample.tasks.iter().find(|t| t.id == id).unwrap();
+    assert_eq!(task(0).state, TaskState::Running);
+    assert_eq!(task(0).stack_used, None);
+    assert_eq!(task(yielder).state, TaskState::Ready);
+    assert_eq!(task(yielder).name.as_deref(), Some("yielder"));
+    assert_eq!(task(parker).state, TaskState::Parked);
+    assert!(task(parker).stack_used.unwrap() > 0);
+    assert!(task(parker).switches_per_sec > 0.0);
+    assert!(sample.to_string().contains("parker"));
+
+    // the next sample only counts what happened since this one
+    runtime.step();
+    let sample = monitor.sample_stats(runtime.stats());
+    assert_eq!(
+        sample.tasks.iter().find(|t| t.id == parker).unwrap().switches_per_sec,
+        0.0
+    );
+    take_log();
+}
+
+#[test]
+fn a_virtual_clock_wakes_sleepers_in_deadline_order() {
+    fn sleep_for(ms: u64) {
+        coro::sleep(Duration::from_millis(ms));
+        log();
+    }
+    fn long() {
+        sleep_for(30);
+    }
+    fn short() {
+        sleep_for(10);
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.use_virtual_clock();
+    let long = runtime.spawn(long).id();
+    let short = runtime.spawn(short).id();
+    while runtime.step() {}
+
+    assert_eq!(take_log(), vec![short, long]);
+}
+
+#[test]
+fn a_task_over_its_budget_is_cancelled() {
+    fn endless() {
+        loop {
+            log();
+            let start = std::time::Instant::now();
+            while start.elapsed() < Duration::from_micros(100) {}
+            coro::yield_now();
+        }
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let handle = runtime.spawn_with_budget(endless, Duration::from_millis(2));
+    while runtime.step() {}
+
+    assert!(take_log().len() > 1, "it runs until it has used up its budget");
+    assert_eq!(runtime.alive_count(), 0);
+    assert_eq!(runtime.stats().budgets_exceeded, 1);
+    // joining a cancelled task returns like it finished
+    handle.join();
+}
+
+#[test]
+fn a_demoted_task_only_runs_when_nobody_else_is_ready() {
+    fn hog() {
+        for _ in 0..10 {
+            log();
+            let start = std::time::Instant::now();
+            while start.elapsed() < Duration::from_micros(100) {}
+            coro::yield_now();
+        }
+    }
+    fn demote(_: &BudgetExceeded) -> OverBudget {
+        OverBudget::Demote
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.on_budget_exceeded(demote);
+    let hog = runtime.spawn_with_budget(hog, Duration::from_micros(1)).id();
+    // its first turn uses up its budget
+    assert!(runtime.step());
+    let other = runtime.spawn(three_turns).id();
+    while runtime.step() {}
+
+    let log = take_log();
+    assert_eq!(log.len(), 13);
+    assert_eq!(&log[..4], &[hog, other, other, other]);
+    assert!(log[4..].iter().all(|&id| id == hog));
+}
+
+#[test]
+fn preempt_makes_the_running_task_yield_at_its_next_checkpoint() {
+    fn preempted() {
+        log();
+        HANDLE.with(|handle| handle.borrow().as_ref().unwrap().preempt());
+        {
+            let _no_preempt = preempt::disable();
+            green_threads::maybe_yield();
+            log();
+            // we yield right here, when we let go of it
+        }
+        log();
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    // the slice alone never makes it yield
+    runtime.set_time_slice(Duration::from_secs(3600));
+    HANDLE.with(|handle| *handle.borrow_mut() = Some(runtime.handle()));
+    let task = runtime.spawn(preempted).id();
+    let other = runtime.spawn(log).id();
+    while runtime.step() {}
+
+    assert_eq!(take_log(), vec![task, task, other, task]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_signal_handler_can_unpark_a_task() {
+    use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+    const SIGUSR1: i32 = 10;
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+        fn raise(signum: i32) -> i32;
+    }
+    static SIGNAL_HANDLE: AtomicPtr<RuntimeHandle> = AtomicPtr::new(std::ptr::null_mut());
+    static PARKED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn on_signal(_signum: i32) {
+        let handle = unsafe { &*SIGNAL_HANDLE.load(Ordering::Acquire) };
+        handle.unpark_from_signal(PARKED.load(Ordering::Relaxed));
+    }
+    fn parks() {
+        log();
+        coro::park();
+        log();
+    }
+    fn signals() {
+        unsafe { raise(SIGUSR1) };
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    SIGNAL_HANDLE.store(Box::into_raw(Box::new(runtime.handle())), Ordering::Release);
+    unsafe { signal(SIGUSR1, on_signal as extern "C" fn(i32) as usize) };
+    let parked = runtime.spawn(parks).id();
+    PARKED.store(parked, Ordering::Relaxed);
+    runtime.spawn(signals);
+    runtime.run();
+
+    assert_eq!(take_log(), vec![parked, parked]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_task_woken_by_io_runs_before_the_busy_ones() {
+    use green_threads::reactor::EventFd;
+    use green_threads::scheduler::{Edf, RoundRobin, Scheduler};
+
+    fn busy(event: Option<Arc<EventFd>>) {
+        for turn in 0..3 {
+            log();
+            if let (1, Some(event)) = (turn, &event) {
+                event.notify(1).unwrap();
+            }
+            coro::yield_now();
+        }
+    }
+    fn reads(event: Arc<EventFd>) {
+        event.wait().unwrap();
+        log();
+    }
+    fn run_with(scheduler: Box<dyn Scheduler>) -> (Vec<usize>, u64) {
+        let mut runtime = Runtime::new();
+        runtime.init();
+        runtime.set_scheduler(scheduler);
+        let event = Arc::new(EventFd::new().unwrap());
+        runtime.spawn_with(busy, Some(event.clone()));
+        runtime.spawn_with(busy, None);
+        runtime.spawn_with(reads, event);
+        runtime.run();
+        (take_log(), runtime.stats().io_boosts)
+    }
+
+    // task 1 makes the event readable in the second round, and the reactor sees it before the third
+    assert_eq!(run_with(Box::new(RoundRobin)), (vec![1, 2, 1, 2, 3, 1, 2], 1));
+    // `Edf` doesn't boost, so the reader waits for its turn
+    assert_eq!(run_with(Box::new(Edf)), (vec![1, 2, 1, 2, 1, 2, 3], 0));
+}
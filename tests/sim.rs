@@ -0,0 +1,193 @@
+//! Tests for `--features sim`, which runs every task on an OS thread of its own (see `src/arch/sim.rs`). Tasks
+//! can't record what they did in a thread local there, so they use statics, and the tests take turns so they
+//! don't see each other's statics. Run them under Miri with
+//! `MIRIFLAGS=-Zmiri-ignore-leaks cargo +nightly miri test --features sim --test sim`.
+#![cfg(feature = "sim")]
+use green_threads::channel::{self, Receiver, Sender};
+use green_threads::sync::{self, Event};
+use green_threads::{coro, Runtime};
+use std::sync::{Mutex, MutexGuard};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+static LOG: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+fn serial() -> MutexGuard<'static, ()> {
+    // a test that failed doesn't make the others fail too
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    LOG.lock().unwrap().clear();
+    guard
+}
+
+fn log(value: usize) {
+    LOG.lock().unwrap().push(value);
+}
+
+fn take_log() -> Vec<usize> {
+    LOG.lock().unwrap().split_off(0)
+}
+
+#[test]
+fn tasks_take_turns_in_the_same_order_every_round() {
+    fn three_turns() {
+        for _ in 0..3 {
+            log(coro::current().id());
+            coro::yield_now();
+        }
+    }
+
+    let _serial = serial();
+    let mut runtime = Runtime::new();
+    runtime.init();
+    for _ in 0..3 {
+        runtime.spawn(three_turns).detach();
+    }
+
+    assert!(runtime.step());
+    let first = take_log();
+    assert_eq!(first.len(), 3);
+    for _ in 0..2 {
+        assert!(runtime.step());
+        assert_eq!(take_log(), first);
+    }
+    while runtime.step() {}
+    assert_eq!(runtime.alive_count(), 0);
+}
+
+#[test]
+fn finished_tasks_hand_their_thread_to_the_next_task() {
+    fn one() {
+        log(1);
+    }
+
+    let _serial = serial();
+    let mut runtime = Runtime::new();
+    runtime.init();
+    for _ in 0..20 {
+        runtime.spawn(one).detach();
+        runtime.run();
+    }
+    assert_eq!(take_log().len(), 20);
+}
+
+#[test]
+fn tasks_get_their_argument_on_new_and_reused_threads() {
+    fn logs(arg: usize) {
+        log(arg);
+    }
+    fn plain() {
+        log(0);
+    }
+
+    let _serial = serial();
+    let mut runtime = Runtime::new();
+    runtime.init();
+    // the first round starts new threads, the second one hands them the next task
+    for round in 0..2 {
+        runtime.spawn_arg(logs, round * 10 + 1).detach();
+        runtime.spawn(plain).detach();
+        runtime.spawn_with(logs, round * 10 + 2).detach();
+        runtime.run();
+    }
+    let mut log = take_log();
+    log.sort_unstable();
+    assert_eq!(log, vec![0, 0, 1, 2, 11, 12]);
+}
+
+#[test]
+fn join_waits_for_the_task() {
+    fn worker() {
+        for _ in 0..5 {
+            coro::yield_now();
+        }
+        log(1);
+    }
+    fn main() -> i32 {
+        coro::spawn(worker).join();
+        take_log().len() as i32
+    }
+
+    let _serial = serial();
+    let mut runtime = Runtime::new();
+    runtime.init();
+    assert_eq!(runtime.run_until(main), 1);
+}
+
+#[test]
+fn a_channel_delivers_every_message_in_order() {
+    static ENDS: Mutex<Option<(Sender<usize>, Receiver<usize>)>> = Mutex::new(None);
+
+    fn producer() {
+        let sender = ENDS.lock().unwrap().as_ref().unwrap().0.clone();
+        for i in 0..100 {
+            sender.send(i).unwrap();
+            if i % 7 == 0 {
+                coro::yield_now();
+            }
+        }
+    }
+    fn consumer() {
+        let (sender, receiver) = ENDS.lock().unwrap().take().unwrap();
+        // the producer has its own sender, we don't want to keep the channel open ourselves
+        drop(sender);
+        while let Ok(i) = receiver.recv() {
+            log(i);
+        }
+    }
+
+    let _serial = serial();
+    *ENDS.lock().unwrap() = Some(channel::channel());
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let producer = runtime.spawn(producer);
+    let consumer = runtime.spawn(consumer);
+    runtime.run();
+    drop((producer, consumer));
+
+    assert_eq!(take_log(), (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn a_mutex_keeps_its_data_consistent() {
+    static COUNTER: sync::Mutex<usize> = sync::Mutex::new(0);
+
+    fn add() {
+        for _ in 0..50 {
+            let mut counter = COUNTER.lock();
+            let seen = *counter;
+            // everybody else gets a turn while we hold the lock
+            coro::yield_now();
+            *counter = seen + 1;
+        }
+    }
+
+    let _serial = serial();
+    let mut runtime = Runtime::new();
+    runtime.init();
+    for _ in 0..3 {
+        runtime.spawn(add).detach();
+    }
+    runtime.run();
+    assert_eq!(*COUNTER.lock(), 150);
+}
+
+#[test]
+fn an_event_wakes_the_task_waiting_for_it() {
+    static READY: Event = Event::new();
+
+    fn waiter() {
+        READY.wait();
+        log(2);
+    }
+    fn notifier() {
+        log(1);
+        READY.notify_one();
+    }
+
+    let _serial = serial();
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(waiter).detach();
+    runtime.spawn(notifier).detach();
+    runtime.run();
+    assert_eq!(take_log(), vec![1, 2]);
+}
@@ -0,0 +1,121 @@
+//! Tasks reuse the stacks of tasks that finished before them. Whatever the old task left there mustn't matter,
+//! and it mustn't matter how the stacks are protected either, so everything runs with every `StackPolicy`.
+//!
+//! The `sim` backend doesn't run tasks on these stacks at all, so these only run with a real backend.
+#![cfg(not(feature = "sim"))]
+use green_threads::{coro, Runtime, StackPolicy};
+use std::cell::RefCell;
+
+thread_local! {
+    static RESULTS: RefCell<Vec<(usize, u64)>> = const { RefCell::new(Vec::new()) };
+}
+
+// Goes `depth` calls deep with a page of locals in every call and yields at the bottom, so the other tasks run
+// while this one has a deep stack.
+#[inline(never)]
+fn deep(depth: u64) -> u64 {
+    let mut page = [0u8; 4096];
+    for (i, byte) in page.iter_mut().enumerate() {
+        *byte = (i as u64 + depth) as u8;
+    }
+    let below = if depth == 0 {
+        coro::yield_now();
+        0
+    } else {
+        deep(depth - 1)
+    };
+    below + page.iter().map(|&b| b as u64).sum::<u64>()
+}
+
+fn expected(depth: u64) -> u64 {
+    (0..=depth)
+        .map(|d| (0..4096u64).map(|i| (i + d) as u8 as u64).sum::<u64>())
+        .sum()
+}
+
+fn worker() {
+    let id = coro::current().id();
+    let depth = 20 + 10 * id as u64;
+    let result = deep(depth);
+    RESULTS.with(|results| results.borrow_mut().push((id, result)));
+}
+
+#[test]
+fn reused_stacks_give_the_same_results() {
+    let mut runtime = Runtime::new();
+    runtime.init();
+    for round in 0..10 {
+        // the slots get a new stack every time the policy changes
+        runtime.set_stack_policy(StackPolicy::AVAILABLE[round % StackPolicy::AVAILABLE.len()]);
+        for _ in 0..3 {
+            runtime.spawn(worker).detach();
+        }
+        runtime.run();
+
+        let results = RESULTS.with(|results| results.borrow_mut().split_off(0));
+        assert_eq!(results.len(), 3);
+        for (id, result) in results {
+            assert_eq!(result, expected(20 + 10 * id as u64), "task {} got it wrong", id);
+        }
+    }
+}
+
+#[test]
+fn a_task_never_sees_another_tasks_stack() {
+    fn check() {
+        let local = 0u8;
+        let here = &local as *const u8 as usize;
+        ADDRESSES.with(|addresses| addresses.borrow_mut().push(here));
+        coro::yield_now();
+    }
+    thread_local! {
+        static ADDRESSES: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    for &policy in StackPolicy::AVAILABLE {
+        let mut runtime = Runtime::new();
+        runtime.init();
+        runtime.set_stack_policy(policy);
+        for _ in 0..3 {
+            runtime.spawn(check).detach();
+        }
+        runtime.run();
+
+        let mut addresses = ADDRESSES.with(|addresses| addresses.borrow_mut().split_off(0));
+        addresses.sort_unstable();
+        // every task has a stack of its own, so their locals are at least a stack apart
+        for pair in addresses.windows(2) {
+            assert!(
+                pair[1] - pair[0] >= 1024 * 1024,
+                "two tasks share a stack with {:?}",
+                policy
+            );
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_poisoned_stack_knows_how_deep_its_task_went() {
+    fn goes_deep() {
+        deep(20);
+        coro::park();
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    let deep_one = runtime
+        .spawn_with_stack_policy(goes_deep, StackPolicy::GuardPageAndPoison)
+        .id();
+    let other = runtime.spawn(goes_deep).id();
+    runtime.run();
+    assert!(runtime.trim() > 0);
+
+    let stats = runtime.stats();
+    let task = |id| stats.tasks.iter().find(|t| t.id == id).unwrap();
+    // 21 pages of locals, trimming them away doesn't make us forget
+    let peak = task(deep_one).stack_peak.unwrap();
+    assert!(peak > 21 * 4096 && peak < 32 * 4096, "peak {}", peak);
+    assert!(peak > task(deep_one).stack_used.unwrap());
+    assert_eq!(task(other).stack_peak, None);
+}
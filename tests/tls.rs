@@ -0,0 +1,100 @@
+//! Task locals, pinning and, with the `tls-check` feature, catching references to thread locals across a yield.
+use green_threads::tls::{self, TaskLocal};
+use green_threads::{coro, Runtime};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn every_task_has_its_own_task_local() {
+    static COUNT: TaskLocal<Cell<usize>> = TaskLocal::new(|| Cell::new(0));
+    static TOTAL: AtomicUsize = AtomicUsize::new(0);
+    fn count(times: usize) {
+        for _ in 0..times {
+            COUNT.with(|count| count.set(count.get() + 1));
+            coro::yield_now();
+        }
+        // nobody else's counting ended up in ours
+        assert_eq!(COUNT.with(|count| count.get()), times);
+        TOTAL.fetch_add(times, Ordering::SeqCst);
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn_arg(count, 3);
+    runtime.spawn_arg(count, 5);
+    runtime.run();
+    assert_eq!(TOTAL.load(Ordering::SeqCst), 8);
+}
+
+#[test]
+fn task_locals_are_dropped_when_the_task_returns() {
+    struct Counted;
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    static VALUE: TaskLocal<Counted> = TaskLocal::new(|| Counted);
+    fn uses_it() {
+        VALUE.with(|_| ());
+        coro::yield_now();
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(uses_it);
+    runtime.step();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+    runtime.run();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn a_migration_guard_pins_the_task_until_it_is_dropped() {
+    fn pinned() {
+        assert!(!tls::is_pinned());
+        let guard = tls::pin();
+        let inner = tls::pin();
+        coro::yield_now();
+        drop(inner);
+        assert!(tls::is_pinned());
+        drop(guard);
+        assert!(!tls::is_pinned());
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(pinned);
+    runtime.run();
+}
+
+#[cfg(feature = "tls-check")]
+#[test]
+fn yielding_inside_tls_with_is_caught() {
+    use std::panic::{self, AssertUnwindSafe};
+    thread_local! {
+        static NAME: String = String::from("worker");
+    }
+    static CAUGHT: AtomicUsize = AtomicUsize::new(0);
+    fn yields_inside() {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| tls::with(&NAME, |_| coro::yield_now())));
+        if res.is_err() {
+            CAUGHT.fetch_add(1, Ordering::SeqCst);
+        }
+        // and it's forgotten once the closure is gone
+        tls::with(&NAME, |_| ());
+        coro::yield_now();
+    }
+    fn pinned() {
+        let _guard = tls::pin();
+        tls::with(&NAME, |_| coro::yield_now());
+    }
+
+    let mut runtime = Runtime::new();
+    runtime.init();
+    runtime.spawn(yields_inside);
+    runtime.spawn(pinned);
+    runtime.run();
+    assert_eq!(CAUGHT.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,61 @@
+//! M:N mode. The tasks run on the workers' threads, so they log to a static, and the tests take turns so they
+//! don't see each other's tasks.
+//!
+//! `workers::current` is a thread local too, and the tasks of the `sim` backend run on threads of their own, so
+//! these only run with a real backend.
+#![cfg(not(feature = "sim"))]
+use green_threads::coro;
+use green_threads::workers::{self, Workers};
+use std::sync::{Mutex, MutexGuard};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+static LOG: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+fn serial() -> MutexGuard<'static, ()> {
+    // a test that failed doesn't make the others fail too
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    LOG.lock().unwrap().clear();
+    guard
+}
+
+fn take_log() -> Vec<usize> {
+    let mut log = LOG.lock().unwrap().split_off(0);
+    log.sort_unstable();
+    log
+}
+
+fn log_worker() {
+    coro::yield_now();
+    LOG.lock().unwrap().push(workers::current().unwrap());
+}
+
+#[test]
+fn spawn_takes_turns_between_the_workers() {
+    let _serial = serial();
+    let workers = Workers::new(3);
+    let picked: Vec<usize> = (0..6).map(|_| workers.spawn(log_worker)).collect();
+    // dropping them waits for every task
+    drop(workers);
+
+    assert_eq!(picked, vec![0, 1, 2, 0, 1, 2]);
+    assert_eq!(take_log(), vec![0, 0, 1, 1, 2, 2]);
+}
+
+#[test]
+fn spawn_pinned_runs_on_the_worker_we_asked_for() {
+    let _serial = serial();
+    let workers = Workers::new(3);
+    for _ in 0..3 {
+        workers.spawn_pinned(1, log_worker);
+    }
+    drop(workers);
+
+    assert_eq!(take_log(), vec![1, 1, 1]);
+    assert_eq!(workers::current(), None, "the test's own thread isn't a worker");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn workers_cant_be_pinned_to_a_cpu_that_isnt_there() {
+    assert!(Workers::pinned(&[100_000]).is_err());
+}